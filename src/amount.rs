@@ -0,0 +1,205 @@
+//! Token amounts that carry their mint and decimals
+//!
+//! As plain `u64`s, base units of different mints, whole tokens and base units, or an
+//! amount and a basis point rate are all easy to mix up. `TokenAmount` keeps the mint and
+//! decimals with the base units, refuses arithmetic across mints and fails on overflow
+//! instead of wrapping.
+//!
+//! The flows use it wherever they convert whole tokens or apply slippage: swap requests,
+//! the `--amount-out` max in, remove liquidity min amounts and the migration's holdings
+//! and LP amount. The pool math in `math` is out of its scope: it mirrors the program's
+//! integer math on the reserves of both sides of a pool, so it stays in base units.
+
+use anyhow::{Context, Result, bail};
+use solana_sdk::pubkey::Pubkey;
+
+/// Denominator of basis point rates
+pub const BPS_DENOMINATOR: u64 = 10_000;
+
+/// Base units of a token mint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenAmount {
+    mint: Pubkey,
+    decimals: u8,
+    raw: u64,
+}
+
+impl TokenAmount {
+    pub fn new(mint: Pubkey, decimals: u8, raw: u64) -> Self {
+        Self {
+            mint,
+            decimals,
+            raw,
+        }
+    }
+
+    /// Whole tokens, e.g. `1.5`, rounded to the nearest base unit
+    pub fn from_ui(mint: Pubkey, decimals: u8, amount: f64) -> Result<Self> {
+        if !amount.is_finite() || amount < 0.0 {
+            bail!("Invalid token amount {}", amount);
+        }
+        let raw = (amount * 10f64.powi(decimals as i32)).round();
+        // u64::MAX as f64 rounds up to 2^64, which doesn't fit
+        if raw >= u64::MAX as f64 {
+            bail!("Token amount {} is too large", amount);
+        }
+        Ok(Self::new(mint, decimals, raw as u64))
+    }
+
+    pub fn mint(&self) -> Pubkey {
+        self.mint
+    }
+
+    pub fn decimals(&self) -> u8 {
+        self.decimals
+    }
+
+    pub fn raw(&self) -> u64 {
+        self.raw
+    }
+
+    /// Whole tokens, rounded to what an `f64` holds
+    pub fn to_ui(&self) -> f64 {
+        self.raw as f64 / 10f64.powi(self.decimals as i32)
+    }
+
+    /// Same token amount with other base units
+    fn with_raw(&self, raw: u64) -> Self {
+        Self::new(self.mint, self.decimals, raw)
+    }
+
+    fn check_same_mint(&self, other: &Self) -> Result<()> {
+        if self.mint != other.mint || self.decimals != other.decimals {
+            bail!(
+                "Can't combine amounts of mint {} and mint {}",
+                self.mint,
+                other.mint
+            );
+        }
+        Ok(())
+    }
+
+    pub fn checked_add(self, other: Self) -> Result<Self> {
+        self.check_same_mint(&other)?;
+        Ok(self.with_raw(
+            self.raw
+                .checked_add(other.raw)
+                .with_context(|| format!("Amount of mint {} overflows", self.mint))?,
+        ))
+    }
+
+    pub fn checked_sub(self, other: Self) -> Result<Self> {
+        self.check_same_mint(&other)?;
+        Ok(
+            self.with_raw(self.raw.checked_sub(other.raw).with_context(|| {
+                format!(
+                    "Can't subtract {} from {} of mint {}",
+                    other.raw, self.raw, self.mint
+                )
+            })?),
+        )
+    }
+
+    /// `bps` basis points of the amount, rounded down
+    pub fn mul_bps(self, bps: u64) -> Result<Self> {
+        let raw = self.raw as u128 * bps as u128 / BPS_DENOMINATOR as u128;
+        Ok(self.with_raw(
+            u64::try_from(raw)
+                .with_context(|| format!("Amount of mint {} overflows", self.mint))?,
+        ))
+    }
+
+    /// The amount less `bps` basis points, rounded down, e.g. a min out after slippage
+    pub fn less_bps(self, bps: u64) -> Result<Self> {
+        if bps > BPS_DENOMINATOR {
+            bail!("{} bps is above 100%", bps);
+        }
+        self.mul_bps(BPS_DENOMINATOR - bps)
+    }
+
+    /// The amount plus `bps` basis points, rounded up, e.g. a max in after slippage
+    pub fn plus_bps(self, bps: u64) -> Result<Self> {
+        let extra = (self.raw as u128 * bps as u128).div_ceil(BPS_DENOMINATOR as u128);
+        self.checked_add(
+            self.with_raw(
+                u64::try_from(extra)
+                    .with_context(|| format!("Amount of mint {} overflows", self.mint))?,
+            ),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn amount(raw: u64) -> TokenAmount {
+        TokenAmount::new(Pubkey::new_from_array([1; 32]), 6, raw)
+    }
+
+    #[test]
+    fn add_and_sub_fail_instead_of_wrapping() {
+        assert_eq!(amount(1).checked_add(amount(2)).unwrap(), amount(3));
+        assert!(amount(u64::MAX).checked_add(amount(1)).is_err());
+        assert_eq!(amount(3).checked_sub(amount(2)).unwrap(), amount(1));
+        assert!(amount(1).checked_sub(amount(2)).is_err());
+    }
+
+    #[test]
+    fn amounts_of_other_mints_dont_mix() {
+        let other_mint = TokenAmount::new(Pubkey::new_from_array([2; 32]), 6, 1);
+        let other_decimals = TokenAmount::new(amount(1).mint(), 9, 1);
+        assert!(amount(1).checked_add(other_mint).is_err());
+        assert!(amount(1).checked_sub(other_decimals).is_err());
+    }
+
+    #[test]
+    fn mul_bps_rounds_down() {
+        assert_eq!(amount(10_001).mul_bps(5_000).unwrap(), amount(5_000));
+        assert_eq!(amount(999).mul_bps(1).unwrap(), amount(0));
+        assert!(amount(u64::MAX).mul_bps(2 * BPS_DENOMINATOR).is_err());
+    }
+
+    #[test]
+    fn less_bps_rounds_down() {
+        // 995_000.995
+        assert_eq!(amount(1_000_001).less_bps(50).unwrap(), amount(995_000));
+        assert_eq!(amount(1_000).less_bps(BPS_DENOMINATOR).unwrap(), amount(0));
+        assert!(amount(1_000).less_bps(BPS_DENOMINATOR + 1).is_err());
+    }
+
+    #[test]
+    fn plus_bps_rounds_up() {
+        // 1_005_001.005
+        assert_eq!(amount(1_000_001).plus_bps(50).unwrap(), amount(1_005_002));
+        assert_eq!(amount(1_000_000).plus_bps(50).unwrap(), amount(1_005_000));
+        assert!(amount(u64::MAX).plus_bps(1).is_err());
+    }
+
+    #[test]
+    fn ui_amounts_round_trip() {
+        let mint = amount(0).mint();
+        for decimals in [0, 6, 9] {
+            for raw in [0, 1, 999_999, 123_456_789_012] {
+                let ui = TokenAmount::new(mint, decimals, raw).to_ui();
+                assert_eq!(TokenAmount::from_ui(mint, decimals, ui).unwrap().raw(), raw);
+            }
+        }
+        assert_eq!(
+            TokenAmount::from_ui(mint, 6, 1.5).unwrap(),
+            amount(1_500_000)
+        );
+        // 0.29 * 100 is 28.999999999999996 as an f64, rounded to the nearest base unit
+        assert_eq!(TokenAmount::from_ui(mint, 2, 0.29).unwrap().raw(), 29);
+    }
+
+    #[test]
+    fn from_ui_rejects_invalid_amounts() {
+        let mint = amount(0).mint();
+        assert!(TokenAmount::from_ui(mint, 6, -1.0).is_err());
+        assert!(TokenAmount::from_ui(mint, 6, f64::NAN).is_err());
+        assert!(TokenAmount::from_ui(mint, 6, f64::INFINITY).is_err());
+        assert!(TokenAmount::from_ui(mint, 9, 1e11).is_err());
+        assert!(TokenAmount::from_ui(mint, 0, 2f64.powi(64)).is_err());
+    }
+}
@@ -1,46 +1,350 @@
-use anyhow::{Context, Result, bail};
+use anyhow::{Context, Result, anyhow, bail};
+use base64::{Engine, engine::general_purpose::STANDARD};
 use darklake_sdk_on_chain::{
     AddLiquidityParamsIx, DEVNET_LOOKUP, DarklakeSDK, FinalizeParamsIx, InitializePoolParamsIx,
     RemoveLiquidityParamsIx, SwapMode, SwapParamsIx,
 };
 
-use serde_json;
 use solana_rpc_client::rpc_client::RpcClient;
+use solana_rpc_client_api::request::TokenAccountsFilter;
 use solana_sdk::{
+    account::Account,
+    address_lookup_table::AddressLookupTableAccount,
     commitment_config::{CommitmentConfig, CommitmentLevel},
-    compute_budget::ComputeBudgetInstruction,
+    hash::Hash,
     instruction::Instruction,
-    message::{VersionedMessage, v0},
+    message::VersionedMessage,
+    native_token::lamports_to_sol,
     pubkey::Pubkey,
-    signature::{Keypair, Signer},
+    signature::{Keypair, Signature, Signer},
     transaction::VersionedTransaction,
 };
+use spl_associated_token_account::get_associated_token_address_with_program_id;
 use spl_token::native_mint;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use std::str::FromStr;
-
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::time::{Duration, sleep};
+
+use crate::amount::TokenAmount;
+use crate::math::PoolSnapshot;
+use crate::requests::{FinalizeRequest, SwapRequest};
+use crate::submit::{
+    DEFAULT_JITO_TIP, ENDPOINT_METRICS_PATH, FanOutSubmitter, JitoSubmitter, RpcSubmitter,
+    TxSubmitter, rank_endpoints, read_endpoint_metrics, set_tx_submitter,
+};
 use crate::utils::{
-    create_new_tokens, create_token_mint, get_address_lookup_table, get_order, mint_tokens_to_user,
+    COSTS_LEDGER_PATH, ComputeBudget, FailureClass, MAX_RPC_SLOT_LAG, MidPrice, ReceivedCheck,
+    StageTimer, Timeouts, TokenInfo, TransactionCost, TxKind, check_order_open, check_pool_health,
+    check_wrap_amount, classify_error, close_token_accounts, compile_message, create_new_tokens,
+    create_token_mint, decode_wallet_blob, derive_order_addresses, encode_wallet_blob,
+    estimate_message_cost, get_address_lookup_table, get_blockhash_and_lookup_table,
+    get_finalize_order_address, get_init_pool_new_accounts, get_mid_price,
+    get_or_create_new_tokens, get_order, get_order_address, get_pool_address, get_pool_snapshot,
+    get_rpc_slot_lag, get_slot_and_blockhash, get_token_balances, get_token_infos,
+    get_token_mint_lp, get_transaction_cost, get_wallet_blob, is_interactive, mint_tokens_to_user,
+    missing_signers, new_mint_keypair, parse_order_summary, parse_pool_state, preview_message,
+    print_settler_earnings, print_unsigned_message, print_wallet_blob, quote_batch,
+    quote_remove_liquidity, read_address_lookup_table_file, read_costs_ledger,
+    resolve_token_programs, send_and_confirm, send_finalize, set_assert_received, set_command,
+    set_compute_budget, set_non_interactive, set_raw_amounts, set_record_stats,
+    set_skip_simulation, set_timeouts, set_value_mint, sign_partial, simulate_account_changes,
+    subscribe_accounts, to_pool_mint, unpack_token_account, unsigned_transaction, value_amounts,
+    value_mint, wait_for_slot, with_compute_budget, write_account_json,
 };
 
+pub mod amount;
+pub mod math;
+pub mod requests;
+pub mod submit;
 pub mod utils;
 
 const RPC_ENDPOINT: &str = "https://api.devnet.solana.com";
-
-const TOKEN_MINT_X: &str = "DdLxrGFs2sKYbbqVk76eVx9268ASUdTMAhrsqphqDuX";
-const TOKEN_MINT_Y: &str = "HXsKnhXPtGr2mq4uTpxbxyy7ZydYWJwx4zMuYPEDukY";
+// Genesis hash of devnet, where the example pools and the lookup table live
+const DEVNET_GENESIS_HASH: &str = "EtWTRABZaYq6iMfeYKouRu166VU2xqa1wcaWoxPkrZBG";
+// Genesis hashes of the clusters Jito block engines run on, mainnet and testnet
+const JITO_GENESIS_HASHES: [&str; 2] = [
+    "5eykt4UsFv8P8NJdTREpY1vzqKqZKvdpKuc147dw2N9d",
+    "4uhcVJyU9pJkvQyS88uRDiswHXSCkY3zQawwpjk2NsNY",
+];
+// Config written by `solana config set`, relative to the home directory
+const SOLANA_CLI_CONFIG_PATH: &str = ".config/solana/cli/config.yml";
+
+// Shared devnet pair, used until demo_setup records a pair of the user's own
+const DEVNET_TOKEN_MINT_X: &str = "DdLxrGFs2sKYbbqVk76eVx9268ASUdTMAhrsqphqDuX";
+const DEVNET_TOKEN_MINT_Y: &str = "HXsKnhXPtGr2mq4uTpxbxyy7ZydYWJwx4zMuYPEDukY";
 const SOL_MINT: &str = "So11111111111111111111111111111111111111111";
 
+// demo_setup records the demo pair here, relative to the working directory
+const DEMO_CONFIG_PATH: &str = "demo.json";
+// Demo tokens minted to the user, and the initial liquidity of the demo pools
+const DEMO_MINT_AMOUNT: u64 = 1_000_000_000_000;
+const DEMO_POOL_AMOUNT: u64 = 1_000_000_000;
+const DEMO_POOL_SOL_AMOUNT: u64 = 10_000_000;
+
+/// Pair recorded by demo_setup, loaded once by `run`
+static DEMO_MINTS: OnceLock<(Pubkey, Pubkey)> = OnceLock::new();
+
 const LABEL: &str = "sdkexample"; // up to 10 characters
 const REF_CODE: &str = "refexample"; // up to 21 characters
 
-/// Load wallet keypair from key file
-fn load_keypair(key_filename: &str) -> Result<Keypair> {
-    let key_path = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), key_filename);
-    let key_data = fs::read_to_string(key_path).context("Failed to read key file")?;
+const MID_PRICE_AMOUNT: u64 = 1_000; // small amount so the quotes barely move the price
+const MID_PRICE_INTERVAL_SECS: u64 = 10;
+
+// fork_pool writes the account files to <FORK_OUTPUT_DIR>/<pool>/
+const FORK_OUTPUT_DIR: &str = "fork";
+
+// generate_fixtures writes its test vectors to <FIXTURES_OUTPUT_DIR>/<pool>/
+const FIXTURES_OUTPUT_DIR: &str = "fixtures";
+
+// Fixtures are built for this owner and salt with a zero blockhash so they're reproducible
+const FIXTURE_OWNER: Pubkey = Pubkey::new_from_array([1; 32]);
+const FIXTURE_SALT: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+const FIXTURE_QUOTE_AMOUNTS: [u64; 4] = [1, 1_000, 1_000_000, 1_000_000_000];
+
+// treasury_report writes a snapshot per run to <TREASURY_OUTPUT_DIR>/<timestamp>.json
+const TREASURY_OUTPUT_DIR: &str = "treasury";
+
+// migrate_liquidity saves its progress to <MIGRATIONS_OUTPUT_DIR>/<old pool>-<new pool>.json
+const MIGRATIONS_OUTPUT_DIR: &str = "migrations";
+
+// Slippage of migrate_liquidity's swaps and headroom of its LP amount to add
+const MIGRATION_SLIPPAGE_BPS: u64 = 50;
+
+// Upper bound of chunk_plan's search
+const MAX_CHUNKS: u64 = 100;
+
+// Price impact levels depth reports the liquidity within, in bps
+const DEPTH_LEVELS_BPS: [f64; 3] = [10.0, 50.0, 100.0];
+// Width of the longest bar of depth's chart
+const DEPTH_CHART_WIDTH: usize = 40;
+
+/// Options followed by a value, skipped when collecting positional arguments
+const FLAGS_WITH_VALUE: [&str; 23] = [
+    "--blockhash",
+    "--mint-seed",
+    "--keypair-file",
+    "--keypair-env",
+    "--settler-keypair-file",
+    "--settler-keypair-env",
+    "--rpc-timeout",
+    "--confirm-timeout",
+    "--order-timeout",
+    "--ws-timeout",
+    "--reference-rpc",
+    "--quote-mint",
+    "--cu-limit",
+    "--cu-price",
+    "--amount-out",
+    "--value-in",
+    "--submit-via",
+    "--fan-out-rpc",
+    "--jito-url",
+    "--jito-tip",
+    "--fee-buffer",
+    "--lookup-table-file",
+    "--tolerance-bps",
+];
+
+// Tolerance applied to the expected remove liquidity outputs
+const REMOVE_LIQUIDITY_SLIPPAGE_BPS: u64 = 100;
+
+// Extra input sent with --amount-out so the output is still reached if the price moves
+const AMOUNT_OUT_SLIPPAGE_BPS: u64 = 100;
+
+const DEFAULT_FEE_BUFFER_LAMPORTS: u64 = 10_000_000; // SOL kept in the wallet after wrapping (0.01 SOL)
+
+/// `--lookup-table-file` of the offline manual flows, loaded once by `run`
+static OFFLINE_LOOKUP_TABLE: OnceLock<AddressLookupTableAccount> = OnceLock::new();
+
+/// `--fee-buffer` of the SOL flows, set once by `run`
+static FEE_BUFFER: OnceLock<u64> = OnceLock::new();
+
+/// Cluster and keypair of the Solana CLI, used when the examples' defaults aren't set up
+struct SolanaCliConfig {
+    json_rpc_url: Option<String>,
+    keypair_path: Option<String>,
+}
+
+impl SolanaCliConfig {
+    /// Read `~/.config/solana/cli/config.yml`, `None` when there is none
+    ///
+    /// Only its top-level `key: value` lines are read, which is all `solana config set` writes
+    /// besides the address labels.
+    fn load() -> Option<Self> {
+        let home = std::env::var("HOME").ok()?;
+        let contents = fs::read_to_string(Path::new(&home).join(SOLANA_CLI_CONFIG_PATH)).ok()?;
+        let value = |key: &str| {
+            contents
+                .lines()
+                .filter(|line| !line.starts_with(char::is_whitespace))
+                .find_map(|line| {
+                    let (name, value) = line.split_once(':')?;
+                    (name.trim() == key).then(|| value.trim().trim_matches(['"', '\'']).to_string())
+                })
+                .filter(|value| !value.is_empty())
+        };
+
+        Some(Self {
+            json_rpc_url: value("json_rpc_url"),
+            keypair_path: value("keypair_path"),
+        })
+    }
+}
+
+/// Use the Solana CLI cluster only on devnet or a local validator (e.g. one started with
+/// the `fork_pool` accounts), the example pools don't exist anywhere else
+fn check_solana_cli_cluster(url: &str, timeout: Duration) -> Result<()> {
+    if url.contains("://localhost") || url.contains("://127.0.0.1") {
+        return Ok(());
+    }
+
+    let genesis_hash = RpcClient::new_with_timeout(url.to_string(), timeout)
+        .get_genesis_hash()
+        .context("Failed to get genesis hash")?;
+    if genesis_hash.to_string() != DEVNET_GENESIS_HASH {
+        bail!("it isn't devnet");
+    }
+    Ok(())
+}
+
+/// Send Jito bundles only to a cluster block engines run on, elsewhere they never land
+fn check_jito_cluster(url: &str, timeout: Duration) -> Result<()> {
+    let genesis_hash = RpcClient::new_with_timeout(url.to_string(), timeout)
+        .get_genesis_hash()
+        .context("Failed to get genesis hash")?;
+    if !JITO_GENESIS_HASHES.contains(&genesis_hash.to_string().as_str()) {
+        bail!(
+            "--submit-via jito only lands on mainnet and testnet, {} is neither",
+            url
+        );
+    }
+    Ok(())
+}
+
+/// Where a wallet keypair is loaded from
+enum KeySource {
+    /// Key file, see `resolve_key_path`
+    File(String),
+    /// Key file, or the Solana CLI keypair when the key file isn't found
+    FileOrSolanaCli(String, String),
+    /// Environment variable holding the key
+    Env(String),
+}
+
+impl KeySource {
+    /// `--<name>-file <path>` / `--<name>-env <VAR>`, falling back to `default_file`
+    fn from_args(args: &[String], name: &str, default_file: &str) -> Self {
+        let flag_value = |flag: String| {
+            args.iter()
+                .position(|arg| *arg == flag)
+                .and_then(|i| args.get(i + 1))
+                .cloned()
+        };
+
+        if let Some(var) = flag_value(format!("--{name}-env")) {
+            KeySource::Env(var)
+        } else if let Some(path) = flag_value(format!("--{name}-file")) {
+            KeySource::File(path)
+        } else {
+            KeySource::File(default_file.to_string())
+        }
+    }
+
+    /// Fall back to the Solana CLI keypair when the default key file is missing
+    fn or_solana_cli(self, solana_cli: Option<&SolanaCliConfig>) -> Self {
+        match (
+            self,
+            solana_cli.and_then(|config| config.keypair_path.clone()),
+        ) {
+            (KeySource::File(key_filename), Some(keypair_path)) => {
+                KeySource::FileOrSolanaCli(key_filename, keypair_path)
+            }
+            (source, _) => source,
+        }
+    }
+}
+
+/// Find a key file: `~/` is expanded to the home directory, absolute paths are
+/// used as is and relative paths are looked up in the current directory, then
+/// next to the executable
+fn resolve_key_path(key_filename: &str) -> Result<PathBuf> {
+    let path = match key_filename.strip_prefix("~/") {
+        Some(rest) => {
+            let home = std::env::var("HOME").context("Cannot expand ~, HOME is not set")?;
+            Path::new(&home).join(rest)
+        }
+        None => PathBuf::from(key_filename),
+    };
+
+    let candidates = if path.is_absolute() {
+        vec![path]
+    } else {
+        let mut candidates = vec![std::env::current_dir()?.join(&path)];
+        if let Some(exe_dir) = std::env::current_exe()?.parent() {
+            candidates.push(exe_dir.join(&path));
+        }
+        candidates
+    };
+
+    candidates
+        .iter()
+        .find(|candidate| candidate.is_file())
+        .cloned()
+        .with_context(|| {
+            let tried: Vec<String> = candidates
+                .iter()
+                .map(|candidate| candidate.display().to_string())
+                .collect();
+            format!(
+                "Key file {key_filename} not found, tried: {}",
+                tried.join(", ")
+            )
+        })
+}
+
+/// Load wallet keypair from a key file or environment variable, either a JSON
+/// array or base64 of the 64 key bytes (as mounted by container secret stores)
+fn load_keypair(source: &KeySource) -> Result<Keypair> {
+    read_keypair(source).context(FailureClass::Config)
+}
+
+fn read_keypair(source: &KeySource) -> Result<Keypair> {
+    let read_key_file = |key_path: PathBuf| {
+        fs::read_to_string(&key_path)
+            .with_context(|| format!("Failed to read key file {}", key_path.display()))
+    };
+    let key_data = match source {
+        KeySource::File(key_filename) => read_key_file(resolve_key_path(key_filename)?)?,
+        KeySource::FileOrSolanaCli(key_filename, keypair_path) => {
+            match resolve_key_path(key_filename) {
+                Ok(key_path) => read_key_file(key_path)?,
+                Err(_) => {
+                    println!(
+                        "No {}, using the Solana CLI keypair {}",
+                        key_filename, keypair_path
+                    );
+                    read_key_file(resolve_key_path(keypair_path)?)?
+                }
+            }
+        }
+        KeySource::Env(var) => std::env::var(var)
+            .with_context(|| format!("Failed to read key from environment variable {var}"))?,
+    };
+    let key_data = key_data.trim();
 
-    let key_bytes: Vec<u8> =
-        serde_json::from_str(&key_data).context("Failed to parse key file as JSON array")?;
+    let key_bytes: Vec<u8> = if key_data.starts_with('[') {
+        serde_json::from_str(key_data).context("Failed to parse key as JSON array")?
+    } else {
+        STANDARD
+            .decode(key_data)
+            .context("Failed to parse key as JSON array or base64")?
+    };
 
     if key_bytes.len() != 64 {
         bail!(
@@ -55,10 +359,134 @@ fn load_keypair(key_filename: &str) -> Result<Keypair> {
     Ok(keypair)
 }
 
-async fn quote(mut sdk: DarklakeSDK) -> Result<()> {
-    let token_mint_x = Pubkey::from_str(TOKEN_MINT_X).unwrap();
-    let token_mint_y = Pubkey::from_str(TOKEN_MINT_Y).unwrap();
-    let amount_in = 1_000;
+/// Transaction building options of the manual flows
+#[derive(Clone, Copy)]
+struct TxOptions {
+    /// Print fees and created accounts before sending (--cost-preview)
+    cost_preview: bool,
+    /// Build with this blockhash and print the unsigned message instead of sending (--blockhash)
+    offline_blockhash: Option<Hash>,
+    /// Compile legacy messages without the lookup table (--legacy-tx)
+    legacy_tx: bool,
+    /// Print the unsigned transaction for a wallet adapter instead of sending (--wallet-blob)
+    wallet_blob: bool,
+    /// Print a wallet-style preview and ask before signing (--preview)
+    preview: bool,
+}
+
+impl TxOptions {
+    /// Blockhash and lookup table to compile the message with
+    ///
+    /// With --blockhash, the lookup table comes from --lookup-table-file, or is left empty
+    /// for --legacy-tx (legacy messages don't use it), `run` refuses --blockhash without one
+    /// of them.
+    async fn blockhash_and_lookup_table(
+        &self,
+        rpc_client: &RpcClient,
+    ) -> Result<(Hash, AddressLookupTableAccount)> {
+        let Some(blockhash) = self.offline_blockhash else {
+            return get_blockhash_and_lookup_table(rpc_client, DEVNET_LOOKUP).await;
+        };
+
+        let address_lookup_table =
+            OFFLINE_LOOKUP_TABLE
+                .get()
+                .cloned()
+                .unwrap_or_else(|| AddressLookupTableAccount {
+                    key: DEVNET_LOOKUP,
+                    addresses: Vec::new(),
+                });
+        Ok((blockhash, address_lookup_table))
+    }
+
+    /// Print the message to be signed elsewhere if requested, returns true when nothing should be sent
+    fn export(&self, message: &VersionedMessage) -> Result<bool> {
+        if self.wallet_blob {
+            println!("Unsigned transaction (base64):");
+            println!("{}", get_wallet_blob(message)?);
+            return Ok(true);
+        }
+
+        if self.offline_blockhash.is_some() {
+            print_unsigned_message(message);
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    /// Show the preview if requested and ask to sign when interactive, returns false when
+    /// the user declined
+    fn approve(
+        &self,
+        rpc_client: &RpcClient,
+        message: &VersionedMessage,
+        min_out: Option<(Pubkey, u64)>,
+    ) -> Result<bool> {
+        if !self.preview {
+            return Ok(true);
+        }
+
+        preview_message(rpc_client, message, min_out)?.print();
+        if !is_interactive() {
+            return Ok(true);
+        }
+
+        print!("Sign and send? [y/N] ");
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            println!("Not sent");
+            return Ok(false);
+        }
+        Ok(true)
+    }
+}
+
+/// Input of an exact-in swap paying out at least `amount_out`: the quoted input plus
+/// `AMOUNT_OUT_SLIPPAGE_BPS`, since the program has no exact-out swaps
+fn get_max_in_for_amount_out(
+    rpc_client: &RpcClient,
+    token_mint_in: &Pubkey,
+    token_mint_out: &Pubkey,
+    amount_out: u64,
+) -> Result<u64> {
+    let pool_key = get_pool_address(&to_pool_mint(token_mint_in), &to_pool_mint(token_mint_out));
+    let (pool, snapshot) = get_pool_snapshot(rpc_client, &pool_key)?;
+    let is_swap_x_to_y = to_pool_mint(token_mint_in) == pool.token_mint_x;
+    let exact_out_quote = math::quote_exact_out(&snapshot, amount_out, is_swap_x_to_y)?;
+
+    let [token_in, token_out] = <[_; 2]>::try_from(get_token_infos(
+        rpc_client,
+        &[*token_mint_in, *token_mint_out],
+    )?)
+    .map_err(|_| anyhow!("Unexpected number of token mints returned"))?;
+    let needed_in = token_in
+        .amount(exact_out_quote.from_amount)
+        .checked_add(token_in.amount(exact_out_quote.trade_fee))?;
+    let max_in = needed_in.plus_bps(AMOUNT_OUT_SLIPPAGE_BPS)?;
+    println!(
+        "{} out needs {} in, max in with {} bps slippage: {} (the whole max in is swapped, for at least {} out)",
+        token_out.format(amount_out),
+        token_in.format(needed_in.raw()),
+        AMOUNT_OUT_SLIPPAGE_BPS,
+        token_in.format(max_in.raw()),
+        token_out.format(amount_out)
+    );
+
+    Ok(max_in.raw())
+}
+
+async fn quote(mut sdk: DarklakeSDK, rpc_client: RpcClient, amount_out: Option<u64>) -> Result<()> {
+    let token_mint_x = default_mint_x();
+    let token_mint_y = default_mint_y();
+    let amount_in = match amount_out {
+        Some(amount_out) => {
+            get_max_in_for_amount_out(&rpc_client, &token_mint_x, &token_mint_y, amount_out)?
+        }
+        None => 1_000,
+    };
 
     println!("\nGetting quote...");
     let quote = sdk.quote(&token_mint_x, &token_mint_y, amount_in).await?;
@@ -66,16 +494,533 @@ async fn quote(mut sdk: DarklakeSDK) -> Result<()> {
     Ok(())
 }
 
+async fn spread(
+    mut sdk: DarklakeSDK,
+    rpc_client: RpcClient,
+    token_mint_a: &Pubkey,
+    token_mint_b: &Pubkey,
+    size: u64,
+) -> Result<()> {
+    println!("Darklake DEX SDK - Spread");
+    println!("==========================");
+
+    // A -> B with the given size, then the received amount back B -> A
+    let quote_ab = sdk.quote(token_mint_a, token_mint_b, size).await?;
+    let quote_ba = sdk
+        .quote(token_mint_b, token_mint_a, quote_ab.out_amount)
+        .await?;
+    let [token_a, token_b] = <[_; 2]>::try_from(get_token_infos(
+        &rpc_client,
+        &[*token_mint_a, *token_mint_b],
+    )?)
+    .map_err(|_| anyhow!("Unexpected number of token mints returned"))?;
+
+    // fees are charged in the input token
+    println!(
+        "A -> B: {} -> {} (fee {}, {}%)",
+        token_a.format(size),
+        token_b.format(quote_ab.out_amount),
+        token_a.format(quote_ab.fee_amount),
+        quote_ab.fee_pct
+    );
+    println!(
+        "B -> A: {} -> {} (fee {}, {}%)",
+        token_b.format(quote_ab.out_amount),
+        token_a.format(quote_ba.out_amount),
+        token_b.format(quote_ba.fee_amount),
+        quote_ba.fee_pct
+    );
+
+    let round_trip_cost = size.saturating_sub(quote_ba.out_amount);
+    println!(
+        "Round trip cost: {} ({:.2} bps, fees and price impact included)",
+        token_a.format(round_trip_cost),
+        round_trip_cost as f64 / size as f64 * 10_000.0
+    );
+
+    // same legs as get_mid_price, so bid / ask come straight from the quotes above
+    let price = MidPrice::from_round_trip(
+        &token_a,
+        &token_b,
+        (size, quote_ab.out_amount),
+        (quote_ab.out_amount, quote_ba.out_amount),
+    )?;
+    println!(
+        "Effective spread: {:.2} bps (bid {}, ask {}, mid {})",
+        price.spread_bps(),
+        price.bid,
+        price.ask,
+        price.mid()
+    );
+
+    Ok(())
+}
+
+async fn verify_quote(
+    mut sdk: DarklakeSDK,
+    rpc_client: RpcClient,
+    token_mint_in: &Pubkey,
+    token_mint_out: &Pubkey,
+    amount_in: u64,
+) -> Result<()> {
+    println!("Darklake DEX SDK - Verify Quote");
+    println!("================================");
+
+    let pool_key = get_pool_address(&to_pool_mint(token_mint_in), &to_pool_mint(token_mint_out));
+    let (pool, snapshot) = get_pool_snapshot(&rpc_client, &pool_key)?;
+    let is_swap_x_to_y = to_pool_mint(token_mint_in) == pool.token_mint_x;
+
+    let sdk_quote = sdk.quote(token_mint_in, token_mint_out, amount_in).await?;
+    let offline_quote = math::quote(&snapshot, amount_in, is_swap_x_to_y)?;
+    let [token_in, token_out] = <[_; 2]>::try_from(get_token_infos(
+        &rpc_client,
+        &[*token_mint_in, *token_mint_out],
+    )?)
+    .map_err(|_| anyhow!("Unexpected number of token mints returned"))?;
+
+    println!(
+        "SDK:     in {} -> out {} (fee {})",
+        token_in.format(sdk_quote.in_amount),
+        token_out.format(sdk_quote.out_amount),
+        token_in.format(sdk_quote.fee_amount)
+    );
+    println!(
+        "Offline: in {} -> out {} (fee {}, protocol fee {}, locked {})",
+        token_in.format(offline_quote.from_amount),
+        token_out.format(offline_quote.to_amount),
+        token_in.format(offline_quote.trade_fee),
+        token_in.format(offline_quote.protocol_fee),
+        token_in.format(offline_quote.from_to_lock)
+    );
+
+    // the reserves can move between the two reads, so run it again before blaming the math
+    if (
+        sdk_quote.in_amount,
+        sdk_quote.out_amount,
+        sdk_quote.fee_amount,
+    ) != (
+        offline_quote.from_amount,
+        offline_quote.to_amount,
+        offline_quote.trade_fee,
+    ) {
+        bail!("Offline quote doesn't match the SDK quote");
+    }
+    println!("Offline quote matches the SDK quote");
+
+    Ok(())
+}
+
+fn quotes(rpc_client: RpcClient, requests: &[(Pubkey, Pubkey, u64)]) -> Result<()> {
+    println!("Darklake DEX SDK - Quotes");
+    println!("==========================");
+
+    let batch = quote_batch(&rpc_client, requests)?;
+    println!("Pools read at slot {}", batch.slot);
+
+    let mut mints: Vec<Pubkey> = requests
+        .iter()
+        .flat_map(|(token_mint_in, token_mint_out, _)| [*token_mint_in, *token_mint_out])
+        .map(|token_mint| to_pool_mint(&token_mint))
+        .collect();
+    mints.sort();
+    mints.dedup();
+    let token_infos: HashMap<_, _> = mints
+        .iter()
+        .copied()
+        .zip(get_token_infos(&rpc_client, &mints)?)
+        .collect();
+
+    for ((token_mint_in, token_mint_out, amount_in), quote) in requests.iter().zip(batch.quotes) {
+        let token_in = &token_infos[&to_pool_mint(token_mint_in)];
+        let token_out = &token_infos[&to_pool_mint(token_mint_out)];
+        match quote {
+            Ok(quote) => println!(
+                "{} -> {} (fee {})",
+                token_in.format(*amount_in),
+                token_out.format(quote.to_amount),
+                token_in.format(quote.trade_fee)
+            ),
+            Err(e) => println!(
+                "{} -> {}: {:#}",
+                token_in.format(*amount_in),
+                token_out.symbol,
+                e
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+async fn simulate_swap(
+    mut sdk: DarklakeSDK,
+    user_keypair: Keypair,
+    rpc_client: RpcClient,
+    token_mint_in: &Pubkey,
+    token_mint_out: &Pubkey,
+    amount_in: u64,
+) -> Result<()> {
+    println!("Darklake DEX SDK - Simulate Swap");
+    println!("=================================");
+
+    println!("Loading pool...");
+    sdk.load_pool(token_mint_in, token_mint_out).await?;
+    sdk.update_accounts().await?;
+
+    let swap_params = SwapRequest::builder()
+        .from(*token_mint_in)
+        .to(*token_mint_out)
+        .amount(amount_in)
+        .authority(user_keypair.pubkey())
+        .to_params(&mut sdk, &rpc_client)
+        .await?;
+    let swap_ix = sdk.swap_ix(&swap_params).await?;
+
+    let (recent_blockhash, address_lookup_table) =
+        get_blockhash_and_lookup_table(&rpc_client, DEVNET_LOOKUP).await?;
+    let message = compile_message(
+        &user_keypair.pubkey(),
+        &with_compute_budget(TxKind::Swap, vec![swap_ix]),
+        &address_lookup_table,
+        recent_blockhash,
+        false,
+    )?;
+
+    let addresses = derive_order_addresses(&user_keypair.pubkey(), token_mint_in, token_mint_out);
+    let (pool_mint_in, pool_mint_out) = (to_pool_mint(token_mint_in), to_pool_mint(token_mint_out));
+    let (reserve_in, reserve_out) = if pool_mint_in == addresses.token_mint_x {
+        (addresses.reserve_x, addresses.reserve_y)
+    } else {
+        (addresses.reserve_y, addresses.reserve_x)
+    };
+    let [token_in, token_out] = <[_; 2]>::try_from(get_token_infos(
+        &rpc_client,
+        &[pool_mint_in, pool_mint_out],
+    )?)
+    .map_err(|_| anyhow!("Unexpected number of token mints returned"))?;
+
+    let [token_program_in, token_program_out] = <[_; 2]>::try_from(resolve_token_programs(
+        &rpc_client,
+        &[pool_mint_in, pool_mint_out],
+    )?)
+    .map_err(|_| anyhow!("Unexpected number of token mints returned"))?;
+
+    let token_accounts = [
+        (
+            "Your token in",
+            get_associated_token_address_with_program_id(
+                &user_keypair.pubkey(),
+                &pool_mint_in,
+                &token_program_in,
+            ),
+            &token_in,
+        ),
+        (
+            "Your token out",
+            get_associated_token_address_with_program_id(
+                &user_keypair.pubkey(),
+                &pool_mint_out,
+                &token_program_out,
+            ),
+            &token_out,
+        ),
+        ("Pool reserve in", reserve_in, &token_in),
+        ("Pool reserve out", reserve_out, &token_out),
+    ];
+    let mut watched: Vec<Pubkey> = token_accounts
+        .iter()
+        .map(|(_, address, _)| *address)
+        .collect();
+    watched.push(addresses.order);
+
+    println!("Simulating swap of {}...", token_in.format(amount_in));
+    let changes = simulate_account_changes(&rpc_client, &message, &watched)?;
+
+    let amount_of = |account: &Option<Account>| -> Result<u64> {
+        match account {
+            Some(account) => Ok(unpack_token_account(&account.data)?.amount),
+            None => Ok(0),
+        }
+    };
+    for ((label, _, info), (before, after)) in token_accounts.iter().zip(&changes) {
+        let (before, after) = (amount_of(before)?, amount_of(after)?);
+        println!(
+            "{:<18} {} -> {} ({})",
+            label,
+            info.format(before),
+            info.format(after),
+            info.format_change(before, after)
+        );
+    }
+
+    // the output is only paid out by the finalize, until then the order holds it
+    let order = changes
+        .last()
+        .and_then(|(_, after)| after.as_ref())
+        .context("Simulated swap didn't create an order")?;
+    let order = parse_order_summary(&order.data)?;
+    println!(
+        "You would pay {} and the pool would owe you {}, paid out when the order is finalized (deadline slot {})",
+        token_in.format(order.actual_in),
+        token_out.format(order.actual_out),
+        order.deadline
+    );
+
+    Ok(())
+}
+
+async fn chunk_plan(
+    rpc_client: RpcClient,
+    token_mint_in: &Pubkey,
+    token_mint_out: &Pubkey,
+    amount_in: u64,
+    max_impact_bps: f64,
+) -> Result<()> {
+    println!("Darklake DEX SDK - Chunk Plan");
+    println!("==============================");
+
+    let pool_key = get_pool_address(&to_pool_mint(token_mint_in), &to_pool_mint(token_mint_out));
+    let (pool, snapshot) = get_pool_snapshot(&rpc_client, &pool_key)?;
+    let is_swap_x_to_y = to_pool_mint(token_mint_in) == pool.token_mint_x;
+    let token_in = get_token_infos(&rpc_client, &[*token_mint_in])?.remove(0);
+
+    println!(
+        "Single swap of {}: {}",
+        token_in.format(amount_in),
+        math::price_impact_bps(&snapshot, amount_in, is_swap_x_to_y).map_or(
+            "too big for the pool".to_string(),
+            |impact| format!("{:.2} bps price impact", impact)
+        )
+    );
+
+    let plan = math::plan_chunks(
+        &snapshot,
+        amount_in,
+        is_swap_x_to_y,
+        max_impact_bps,
+        MAX_CHUNKS,
+    )?;
+    println!(
+        "{} chunks of {} ({:.2} bps price impact each)",
+        plan.chunks,
+        token_in.format(plan.chunk_size),
+        plan.chunk_impact_bps
+    );
+    if plan.last_chunk_size > 0 {
+        println!("Last chunk: {}", token_in.format(plan.last_chunk_size));
+    }
+
+    Ok(())
+}
+
+/// Liquidity of a pair within a price impact: base sold for quote on the bid side, base
+/// bought with quote on the ask side, `None` when no swap stays within the impact
+struct DepthLevel {
+    impact_bps: f64,
+    bid: Option<(u64, u64)>,
+    ask: Option<(u64, u64)>,
+}
+
+/// Order book style depth of a pool: how much base can be sold (bid) or bought (ask)
+/// within each of `DEPTH_LEVELS_BPS` of price impact
+///
+/// Every level is quoted against one snapshot of the reserves with the offline quote math.
+/// Prices are in quote per base and the average prices include the trade fee. Prints a
+/// table, JSON instead with `json`, and an ASCII chart after the table with `chart`.
+fn depth(
+    rpc_client: RpcClient,
+    token_mint_base: &Pubkey,
+    token_mint_quote: &Pubkey,
+    json: bool,
+    chart: bool,
+) -> Result<()> {
+    print_status(json, "Darklake DEX SDK - Depth");
+    print_status(json, "=========================");
+
+    let (base_mint, quote_mint) = (
+        to_pool_mint(token_mint_base),
+        to_pool_mint(token_mint_quote),
+    );
+    let pool_key = get_pool_address(&base_mint, &quote_mint);
+    let (pool, snapshot) = get_pool_snapshot(&rpc_client, &pool_key)?;
+    let is_base_x = base_mint == pool.token_mint_x;
+    let [base, quote] = <[_; 2]>::try_from(get_token_infos(&rpc_client, &[base_mint, quote_mint])?)
+        .map_err(|_| anyhow!("Unexpected number of token mints returned"))?;
+
+    // quote per base in whole tokens from base units of each
+    let price = |base_amount: u64, quote_amount: u64| {
+        quote.amount(quote_amount).to_ui() / base.amount(base_amount).to_ui()
+    };
+    let mid =
+        snapshot.spot_price(is_base_x)? * 10f64.powi(base.decimals as i32 - quote.decimals as i32);
+
+    let mut levels = Vec::new();
+    for impact_bps in DEPTH_LEVELS_BPS {
+        levels.push(DepthLevel {
+            impact_bps,
+            bid: math::max_swap_within_impact(&snapshot, is_base_x, impact_bps)?
+                .map(|(amount_in, quote)| (amount_in, quote.to_amount)),
+            ask: math::max_swap_within_impact(&snapshot, !is_base_x, impact_bps)?
+                .map(|(amount_in, quote)| (quote.to_amount, amount_in)),
+        });
+    }
+
+    if json {
+        let side = |side: Option<(u64, u64)>| {
+            side.map_or(serde_json::Value::Null, |(base_amount, quote_amount)| {
+                serde_json::json!({
+                    "base": base_amount,
+                    "quote": quote_amount,
+                    "average_price": price(base_amount, quote_amount),
+                })
+            })
+        };
+        let levels: Vec<_> = levels
+            .iter()
+            .map(|level| {
+                serde_json::json!({
+                    "impact_bps": level.impact_bps,
+                    "bid": side(level.bid),
+                    "ask": side(level.ask),
+                })
+            })
+            .collect();
+        let depth = serde_json::json!({
+            "pool": pool_key.to_string(),
+            "base": base_mint.to_string(),
+            "quote": quote_mint.to_string(),
+            "base_decimals": base.decimals,
+            "quote_decimals": quote.decimals,
+            "mid": mid,
+            "levels": levels,
+        });
+        println!("{}", serde_json::to_string_pretty(&depth)?);
+        return Ok(());
+    }
+
+    println!("Pool: {}", pool_key);
+    println!("Mid price: {} {} per {}", mid, quote.symbol, base.symbol);
+    println!(
+        "\n{:>7} {:>24} {:>16} {:>24} {:>16}",
+        "Impact", "Bid size", "Bid avg price", "Ask size", "Ask avg price"
+    );
+    let side = |side: Option<(u64, u64)>| {
+        side.map_or(
+            ("-".to_string(), "-".to_string()),
+            |(base_amount, quote_amount)| {
+                (
+                    base.format(base_amount),
+                    format!("{:.6}", price(base_amount, quote_amount)),
+                )
+            },
+        )
+    };
+    for level in &levels {
+        let ((bid_size, bid_price), (ask_size, ask_price)) = (side(level.bid), side(level.ask));
+        println!(
+            "{:>6.2}% {:>24} {:>16} {:>24} {:>16}",
+            level.impact_bps / 100.0,
+            bid_size,
+            bid_price,
+            ask_size,
+            ask_price
+        );
+    }
+
+    if chart {
+        // bars of base size, bids below the mid price and asks above like an order book
+        let longest = levels
+            .iter()
+            .flat_map(|level| [level.bid, level.ask])
+            .flatten()
+            .map(|(base_amount, _)| base_amount)
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        let bar = |side: Option<(u64, u64)>| {
+            let base_amount = side.map_or(0, |(base_amount, _)| base_amount);
+            let width = (base_amount as f64 / longest as f64 * DEPTH_CHART_WIDTH as f64).round();
+            format!(
+                "{} {}",
+                "#".repeat(width as usize),
+                base.format(base_amount)
+            )
+        };
+
+        println!();
+        for level in levels.iter().rev() {
+            println!("ask +{:.2}% | {}", level.impact_bps / 100.0, bar(level.ask));
+        }
+        println!("mid        | {} {} per {}", mid, quote.symbol, base.symbol);
+        for level in &levels {
+            println!("bid -{:.2}% | {}", level.impact_bps / 100.0, bar(level.bid));
+        }
+    }
+
+    Ok(())
+}
+
+async fn mid_price(mut sdk: DarklakeSDK, rpc_client: RpcClient) -> Result<()> {
+    println!("Darklake DEX SDK - Mid Price");
+    println!("=============================");
+
+    // published as (base, quote)
+    let pairs = [
+        (default_mint_x(), default_mint_y()),
+        (default_mint_x(), Pubkey::from_str(SOL_MINT).unwrap()),
+    ];
+    let pairs: Vec<(TokenInfo, TokenInfo)> = pairs
+        .iter()
+        .map(|(base, quote)| {
+            let [base, quote] = <[_; 2]>::try_from(get_token_infos(&rpc_client, &[*base, *quote])?)
+                .map_err(|_| anyhow!("Unexpected number of token mints returned"))?;
+            Ok((base, quote))
+        })
+        .collect::<Result<_>>()?;
+
+    loop {
+        for (base, quote) in &pairs {
+            let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+            let entry = match get_mid_price(&mut sdk, base, quote, MID_PRICE_AMOUNT).await {
+                Ok(price) => serde_json::json!({
+                    "timestamp": timestamp,
+                    "base": base.mint.to_string(),
+                    "quote": quote.mint.to_string(),
+                    "bid": price.bid,
+                    "ask": price.ask,
+                    "mid": price.mid(),
+                    "spread_bps": price.spread_bps(),
+                }),
+                Err(e) => serde_json::json!({
+                    "timestamp": timestamp,
+                    "base": base.mint.to_string(),
+                    "quote": quote.mint.to_string(),
+                    "error": e.to_string(),
+                }),
+            };
+
+            println!("{}", entry);
+        }
+
+        sleep(Duration::from_secs(MID_PRICE_INTERVAL_SECS)).await;
+    }
+}
+
 async fn manual_swap(
     mut sdk: DarklakeSDK,
     user_keypair: Keypair,
     rpc_client: RpcClient,
+    latency_report: bool,
+    tx_options: TxOptions,
 ) -> Result<()> {
     println!("Darklake DEX SDK - Manual Swap");
     println!("===============================");
 
-    let token_mint_x = Pubkey::from_str(TOKEN_MINT_X).unwrap();
-    let token_mint_y = Pubkey::from_str(TOKEN_MINT_Y).unwrap();
+    let mut timer = StageTimer::new();
+
+    let token_mint_x = default_mint_x();
+    let token_mint_y = default_mint_y();
 
     println!("Loading pool...");
     sdk.load_pool(&token_mint_x, &token_mint_y).await?;
@@ -83,6 +1028,8 @@ async fn manual_swap(
     println!("Updating accounts...");
     sdk.update_accounts().await?;
 
+    timer.stage("load pool");
+
     let salt = [1, 2, 3, 4, 5, 6, 7, 8];
     let min_out = 1;
 
@@ -98,36 +1045,68 @@ async fn manual_swap(
 
     let swap_ix = sdk.swap_ix(&swap_params).await?;
 
-    let recent_blockhash = rpc_client
-        .get_latest_blockhash()
-        .context("Failed to get recent blockhash")?;
-
-    let address_lookup_table = get_address_lookup_table(&rpc_client, DEVNET_LOOKUP).await?;
+    let (recent_blockhash, address_lookup_table) =
+        tx_options.blockhash_and_lookup_table(&rpc_client).await?;
 
-    let message_v0 = v0::Message::try_compile(
+    let message = compile_message(
         &user_keypair.pubkey(),
-        &[swap_ix],
-        &[address_lookup_table.clone()],
+        &with_compute_budget(TxKind::Swap, vec![swap_ix]),
+        &address_lookup_table,
         recent_blockhash,
+        tx_options.legacy_tx,
     )?;
 
+    if tx_options.export(&message)? {
+        return Ok(());
+    }
+
+    timer.stage("build");
+
+    if tx_options.cost_preview {
+        estimate_message_cost(&rpc_client, &message, TxKind::Swap.cu_limit())?.print();
+        timer.stage("cost preview");
+    }
+
+    if !tx_options.approve(&rpc_client, &message, Some((token_mint_y, min_out)))? {
+        return Ok(());
+    }
+
+    let received_check =
+        ReceivedCheck::start(&rpc_client, &user_keypair.pubkey(), &token_mint_y, min_out)?;
+
     let mut transaction = VersionedTransaction {
         signatures: vec![],
-        message: VersionedMessage::V0(message_v0),
+        message,
     };
 
     transaction.signatures = vec![user_keypair.sign_message(&transaction.message.serialize())];
 
+    timer.stage("sign");
+
     println!("Swap transaction signature: {}", transaction.signatures[0]);
 
-    let _swap_signature = rpc_client.send_and_confirm_transaction_with_spinner(&transaction)?;
+    let _swap_signature = send_and_confirm(&rpc_client, &transaction)?;
+
+    timer.stage("send + confirm");
 
     // Retry get_order up to 5 times with 5 second delays
     let order = get_order(&sdk, &user_keypair.pubkey(), &rpc_client).await?;
 
+    timer.stage("order available");
+
     println!("Updating accounts...");
     sdk.update_accounts().await?;
 
+    let order_address = get_order_address(
+        &get_pool_address(&token_mint_x, &token_mint_y),
+        &user_keypair.pubkey(),
+    );
+    if !check_order_open(&rpc_client, &order_address)? {
+        return Ok(());
+    }
+
+    let (current_slot, recent_blockhash) = get_slot_and_blockhash(&rpc_client).await?;
+
     let finalize_params = FinalizeParamsIx {
         settle_signer: user_keypair.pubkey(),
         order_owner: user_keypair.pubkey(),
@@ -137,38 +1116,43 @@ async fn manual_swap(
         output: order.d_out, // on-chain order value
         commitment: order.c_min, // on-chain order value
         deadline: order.deadline, // on-chain order value
-        current_slot: rpc_client.get_slot()?,
+        current_slot,
     };
 
-    let compute_budget_ix: Instruction = ComputeBudgetInstruction::set_compute_unit_limit(500_000);
-
     let finalize_ix = sdk.finalize_ix(&finalize_params).await?;
 
-    let recent_blockhash = rpc_client
-        .get_latest_blockhash()
-        .context("Failed to get recent blockhash")?;
-
-    let message_v0 = v0::Message::try_compile(
+    let message = compile_message(
         &user_keypair.pubkey(),
-        &[compute_budget_ix, finalize_ix],
-        &[address_lookup_table],
+        &with_compute_budget(TxKind::Finalize, vec![finalize_ix]),
+        &address_lookup_table,
         recent_blockhash,
+        tx_options.legacy_tx,
     )?;
 
     let mut transaction = VersionedTransaction {
         signatures: vec![],
-        message: VersionedMessage::V0(message_v0),
+        message,
     };
 
     transaction.signatures = vec![user_keypair.sign_message(&transaction.message.serialize())];
 
-    let _finalize_signature = rpc_client.send_and_confirm_transaction_with_spinner(&transaction)?;
+    let _finalize_signature = send_finalize(&rpc_client, &transaction, &order_address)?;
+
+    timer.stage("finalize");
 
     println!(
         "Finalize transaction signature: {}",
         transaction.signatures[0]
     );
 
+    if let Some(received_check) = received_check {
+        received_check.check(&rpc_client)?;
+    }
+
+    if latency_report {
+        timer.print_report();
+    }
+
     Ok(())
 }
 
@@ -180,8 +1164,8 @@ async fn manual_swap_slash(
     println!("Darklake DEX SDK - Manual Swap");
     println!("===============================");
 
-    let token_mint_x = Pubkey::from_str(TOKEN_MINT_X).unwrap();
-    let token_mint_y = Pubkey::from_str(TOKEN_MINT_Y).unwrap();
+    let token_mint_x = default_mint_x();
+    let token_mint_y = default_mint_y();
 
     println!("Loading pool...");
     sdk.load_pool(&token_mint_x, &token_mint_y).await?;
@@ -204,29 +1188,27 @@ async fn manual_swap_slash(
 
     let swap_ix = sdk.swap_ix(&swap_params).await?;
 
-    let recent_blockhash = rpc_client
-        .get_latest_blockhash()
-        .context("Failed to get recent blockhash")?;
-
-    let address_lookup_table = get_address_lookup_table(&rpc_client, DEVNET_LOOKUP).await?;
+    let (recent_blockhash, address_lookup_table) =
+        get_blockhash_and_lookup_table(&rpc_client, DEVNET_LOOKUP).await?;
 
-    let message_v0 = v0::Message::try_compile(
+    let message = compile_message(
         &user_keypair.pubkey(),
-        &[swap_ix],
-        &[address_lookup_table.clone()],
+        &with_compute_budget(TxKind::Swap, vec![swap_ix]),
+        &address_lookup_table,
         recent_blockhash,
+        false,
     )?;
 
     let mut transaction = VersionedTransaction {
         signatures: vec![],
-        message: VersionedMessage::V0(message_v0),
+        message,
     };
 
     transaction.signatures = vec![user_keypair.sign_message(&transaction.message.serialize())];
 
     println!("Swap transaction signature: {}", transaction.signatures[0]);
 
-    let _swap_signature = rpc_client.send_and_confirm_transaction_with_spinner(&transaction)?;
+    let _swap_signature = send_and_confirm(&rpc_client, &transaction)?;
 
     // Retry get_order up to 5 times with 5 second delays
     let order = get_order(&sdk, &user_keypair.pubkey(), &rpc_client).await?;
@@ -235,13 +1217,18 @@ async fn manual_swap_slash(
     sdk.update_accounts().await?;
 
     // Wait for order to expire
-    let mut current_slot = rpc_client.get_slot()?;
-    while order.deadline >= current_slot + 1 {
-        current_slot = rpc_client.get_slot()?;
-        println!("Waiting for order to expire...");
-        println!("Current slot: {}", current_slot);
-        println!("Order deadline: {}", order.deadline);
-        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+    println!(
+        "Waiting for order to expire (deadline slot {})...",
+        order.deadline
+    );
+    let current_slot = wait_for_slot(&rpc_client, order.deadline + 1).await?;
+
+    let order_address = get_order_address(
+        &get_pool_address(&token_mint_x, &token_mint_y),
+        &user_keypair.pubkey(),
+    );
+    if !check_order_open(&rpc_client, &order_address)? {
+        return Ok(());
     }
 
     let finalize_params = FinalizeParamsIx {
@@ -253,32 +1240,31 @@ async fn manual_swap_slash(
         output: order.d_out, // on-chain order value
         commitment: order.c_min, // on-chain order value
         deadline: order.deadline, // on-chain order value
-        current_slot: current_slot + 1,
+        current_slot,
     };
 
-    let compute_budget_ix: Instruction = ComputeBudgetInstruction::set_compute_unit_limit(500_000);
-
     let finalize_ix = sdk.finalize_ix(&finalize_params).await?;
 
     let recent_blockhash = rpc_client
         .get_latest_blockhash()
         .context("Failed to get recent blockhash")?;
 
-    let message_v0 = v0::Message::try_compile(
+    let message = compile_message(
         &user_keypair.pubkey(),
-        &[compute_budget_ix, finalize_ix],
-        &[address_lookup_table],
+        &with_compute_budget(TxKind::Finalize, vec![finalize_ix]),
+        &address_lookup_table,
         recent_blockhash,
+        false,
     )?;
 
     let mut transaction = VersionedTransaction {
         signatures: vec![],
-        message: VersionedMessage::V0(message_v0),
+        message,
     };
 
     transaction.signatures = vec![user_keypair.sign_message(&transaction.message.serialize())];
 
-    let _finalize_signature = rpc_client.send_and_confirm_transaction_with_spinner(&transaction)?;
+    let _finalize_signature = send_finalize(&rpc_client, &transaction, &order_address)?;
 
     println!(
         "Finalize transaction signature: {}",
@@ -297,8 +1283,8 @@ async fn manual_swap_different_settler(
     println!("Darklake DEX SDK - Manual Swap Different Settler");
     println!("===============================");
 
-    let token_mint_x = Pubkey::from_str(TOKEN_MINT_X).unwrap();
-    let token_mint_y = Pubkey::from_str(TOKEN_MINT_Y).unwrap();
+    let token_mint_x = default_mint_x();
+    let token_mint_y = default_mint_y();
 
     println!("Loading pool...");
     sdk.load_pool(&token_mint_x, &token_mint_y).await?;
@@ -306,105 +1292,271 @@ async fn manual_swap_different_settler(
     println!("Updating accounts...");
     sdk.update_accounts().await?;
 
-    let salt = [1, 2, 3, 4, 5, 6, 7, 8];
-    let min_out = 1;
-
-    let swap_params = SwapParamsIx {
-        source_mint: token_mint_x,
-        destination_mint: token_mint_y,
-        token_transfer_authority: user_keypair.pubkey(),
-        amount_in: 1_000,
-        swap_mode: SwapMode::ExactIn,
-        min_out,
-        salt, // Random salt for order uniqueness
-    };
+    // the builder picks a random salt, the min out could be quoted with .slippage_bps() instead
+    let swap_params = SwapRequest::builder()
+        .from(token_mint_x)
+        .to(token_mint_y)
+        .amount(1_000)
+        .min_out(1)
+        .authority(user_keypair.pubkey())
+        .to_params(&mut sdk, &rpc_client)
+        .await?;
 
     let swap_ix = sdk.swap_ix(&swap_params).await?;
 
-    let recent_blockhash = rpc_client
-        .get_latest_blockhash()
-        .context("Failed to get recent blockhash")?;
+    let received_check = ReceivedCheck::start(
+        &rpc_client,
+        &user_keypair.pubkey(),
+        &token_mint_y,
+        swap_params.min_out,
+    )?;
 
-    let address_lookup_table = get_address_lookup_table(&rpc_client, DEVNET_LOOKUP).await?;
+    let (recent_blockhash, address_lookup_table) =
+        get_blockhash_and_lookup_table(&rpc_client, DEVNET_LOOKUP).await?;
 
-    let message_v0 = v0::Message::try_compile(
+    let message = compile_message(
         &user_keypair.pubkey(),
-        &[swap_ix],
-        &[address_lookup_table.clone()],
+        &with_compute_budget(TxKind::Swap, vec![swap_ix]),
+        &address_lookup_table,
         recent_blockhash,
+        false,
     )?;
 
     let mut transaction = VersionedTransaction {
         signatures: vec![],
-        message: VersionedMessage::V0(message_v0),
+        message,
     };
 
     transaction.signatures = vec![user_keypair.sign_message(&transaction.message.serialize())];
 
     println!("Swap transaction signature: {}", transaction.signatures[0]);
 
-    let _swap_signature = rpc_client.send_and_confirm_transaction_with_spinner(&transaction)?;
+    let _swap_signature = send_and_confirm(&rpc_client, &transaction)?;
 
     let order = get_order(&sdk, &user_keypair.pubkey(), &rpc_client).await?;
 
     println!("Updating accounts...");
     sdk.update_accounts().await?;
 
-    let finalize_params = FinalizeParamsIx {
-        settle_signer: settler.pubkey(),
-        order_owner: user_keypair.pubkey(),
-        unwrap_wsol: false, // Set to true to unwrap WSOL using dex (no extra instruction added)
-        min_out,            // Same min_out as swap
-        salt,               // Same salt as swap
-        output: order.d_out, // on-chain order value
-        commitment: order.c_min, // on-chain order value
-        deadline: order.deadline, // on-chain order value
-        current_slot: rpc_client.get_slot()?,
-    };
+    let order_address = get_order_address(
+        &get_pool_address(&token_mint_x, &token_mint_y),
+        &user_keypair.pubkey(),
+    );
+    if !check_order_open(&rpc_client, &order_address)? {
+        return Ok(());
+    }
 
-    let compute_budget_ix: Instruction = ComputeBudgetInstruction::set_compute_unit_limit(500_000);
+    // same min out and salt as the swap, output / commitment / deadline from the order
+    let (current_slot, recent_blockhash) = get_slot_and_blockhash(&rpc_client).await?;
+    let finalize_params = FinalizeRequest::for_swap(&swap_params, &order)
+        .settler(settler.pubkey())
+        .to_params_at(current_slot);
 
     let finalize_ix = sdk.finalize_ix(&finalize_params).await?;
 
-    let recent_blockhash = rpc_client
-        .get_latest_blockhash()
-        .context("Failed to get recent blockhash")?;
-
-    let message_v0 = v0::Message::try_compile(
+    let message = compile_message(
         &settler.pubkey(),
-        &[compute_budget_ix, finalize_ix],
-        &[address_lookup_table],
+        &with_compute_budget(TxKind::Finalize, vec![finalize_ix]),
+        &address_lookup_table,
         recent_blockhash,
+        false,
     )?;
 
     let mut transaction = VersionedTransaction {
         signatures: vec![],
-        message: VersionedMessage::V0(message_v0),
+        message,
     };
 
     transaction.signatures = vec![settler.sign_message(&transaction.message.serialize())];
 
-    let _finalize_signature = rpc_client.send_and_confirm_transaction_with_spinner(&transaction)?;
+    let settler_balance_before = rpc_client.get_balance(&settler.pubkey())?;
+
+    let _finalize_signature = send_finalize(&rpc_client, &transaction, &order_address)?;
+
+    println!(
+        "Finalize transaction signature: {}",
+        transaction.signatures[0]
+    );
+
+    // Settler pays the finalize fees and receives any settle rewards
+    let settler_balance_after = rpc_client.get_balance(&settler.pubkey())?;
+    print_settler_earnings(settler_balance_before, settler_balance_after);
+
+    if let Some(received_check) = received_check {
+        received_check.check(&rpc_client)?;
+    }
+
+    Ok(())
+}
+
+/// Swap on the example pool, then build its finalize paid for by `settler` and signed by
+/// the user, written to `path` for the settler to cosign and send
+async fn finalize_build(
+    mut sdk: DarklakeSDK,
+    user_keypair: Keypair,
+    rpc_client: RpcClient,
+    settler: &Pubkey,
+    path: &Path,
+) -> Result<()> {
+    println!("Darklake DEX SDK - Finalize Build");
+    println!("==================================");
+
+    let token_mint_x = default_mint_x();
+    let token_mint_y = default_mint_y();
+
+    println!("Loading pool...");
+    sdk.load_pool(&token_mint_x, &token_mint_y).await?;
+
+    println!("Updating accounts...");
+    sdk.update_accounts().await?;
+
+    let swap_params = SwapRequest::builder()
+        .from(token_mint_x)
+        .to(token_mint_y)
+        .amount(1_000)
+        .min_out(1)
+        .authority(user_keypair.pubkey())
+        .to_params(&mut sdk, &rpc_client)
+        .await?;
+
+    let swap_ix = sdk.swap_ix(&swap_params).await?;
+
+    let (recent_blockhash, address_lookup_table) =
+        get_blockhash_and_lookup_table(&rpc_client, DEVNET_LOOKUP).await?;
+
+    let message = compile_message(
+        &user_keypair.pubkey(),
+        &with_compute_budget(TxKind::Swap, vec![swap_ix]),
+        &address_lookup_table,
+        recent_blockhash,
+        false,
+    )?;
+    let transaction = VersionedTransaction::try_new(message, &[&user_keypair])?;
+
+    println!("Swap transaction signature: {}", transaction.signatures[0]);
+
+    let _swap_signature = send_and_confirm(&rpc_client, &transaction)?;
+
+    let order = get_order(&sdk, &user_keypair.pubkey(), &rpc_client).await?;
+
+    println!("Updating accounts...");
+    sdk.update_accounts().await?;
+
+    let order_address = get_order_address(
+        &get_pool_address(&token_mint_x, &token_mint_y),
+        &user_keypair.pubkey(),
+    );
+    if !check_order_open(&rpc_client, &order_address)? {
+        return Ok(());
+    }
+
+    let finalize_params = FinalizeRequest::for_swap(&swap_params, &order).to_params(&rpc_client)?;
+    let finalize_ix = sdk.finalize_ix(&finalize_params).await?;
+
+    let (recent_blockhash, address_lookup_table) =
+        get_blockhash_and_lookup_table(&rpc_client, DEVNET_LOOKUP).await?;
+
+    // the settler pays the fees, the user settles its own order, so both have to sign
+    let message = compile_message(
+        settler,
+        &with_compute_budget(TxKind::Finalize, vec![finalize_ix]),
+        &address_lookup_table,
+        recent_blockhash,
+        false,
+    )?;
+
+    let mut transaction = unsigned_transaction(&message);
+    sign_partial(&mut transaction, &user_keypair)?;
+
+    fs::write(path, encode_wallet_blob(&transaction)?)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    println!(
+        "Wrote the finalize signed by {} to {}",
+        user_keypair.pubkey(),
+        path.display()
+    );
+    println!(
+        "Have {} run `finalize_cosign {}` before blockhash {} expires (about a minute)",
+        settler,
+        path.display(),
+        recent_blockhash
+    );
+
+    Ok(())
+}
+
+/// Cosign a finalize written by `finalize_build` as its fee payer and send it
+fn finalize_cosign(settler: Keypair, rpc_client: RpcClient, path: &Path) -> Result<()> {
+    println!("Darklake DEX SDK - Finalize Cosign");
+    println!("===================================");
+
+    let blob =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    print_wallet_blob(&blob)?;
+
+    let mut transaction = decode_wallet_blob(&blob)?;
+    if transaction.message.static_account_keys()[0] != settler.pubkey() {
+        bail!(
+            "The finalize is paid for by {}, not by the settler {}",
+            transaction.message.static_account_keys()[0],
+            settler.pubkey()
+        );
+    }
+    sign_partial(&mut transaction, &settler)?;
+
+    let missing = missing_signers(&transaction);
+    if !missing.is_empty() {
+        bail!("The finalize is still missing signatures of {:?}", missing);
+    }
+
+    let order_address = get_finalize_order_address(&rpc_client, &transaction.message)?;
+
+    let settler_balance_before = rpc_client.get_balance(&settler.pubkey())?;
+
+    let _finalize_signature = send_finalize(&rpc_client, &transaction, &order_address)?;
 
     println!(
         "Finalize transaction signature: {}",
         transaction.signatures[0]
     );
 
+    // Settler pays the finalize fees
+    let settler_balance_after = rpc_client.get_balance(&settler.pubkey())?;
+    print_settler_earnings(settler_balance_before, settler_balance_after);
+
     Ok(())
 }
 
-async fn swap(mut sdk: DarklakeSDK, user_keypair: Keypair, rpc_client: RpcClient) -> Result<()> {
+async fn swap(
+    mut sdk: DarklakeSDK,
+    user_keypair: Keypair,
+    rpc_client: RpcClient,
+    latency_report: bool,
+    amount_out: Option<u64>,
+) -> Result<()> {
     println!("Darklake DEX SDK - Swap");
     println!("========================");
 
-    let token_mint_x = Pubkey::from_str(TOKEN_MINT_X).unwrap();
-    let token_mint_y = Pubkey::from_str(TOKEN_MINT_Y).unwrap();
+    let mut timer = StageTimer::new();
+
+    let token_mint_x = default_mint_x();
+    let token_mint_y = default_mint_y();
 
     println!("Token X Mint: {}", token_mint_x);
     println!("Token Y Mint: {}", token_mint_y);
 
-    let res_quote = sdk.quote(&token_mint_x, &token_mint_y, 1_000).await?;
+    // with --amount-out the input is sized from the output and the output becomes the minimum
+    let (amount_in, min_out) = match amount_out {
+        Some(amount_out) => (
+            get_max_in_for_amount_out(&rpc_client, &token_mint_x, &token_mint_y, amount_out)?,
+            amount_out,
+        ),
+        None => (1_000, 1),
+    };
+
+    let res_quote = sdk.quote(&token_mint_x, &token_mint_y, amount_in).await?;
+
+    timer.stage("quote");
 
     println!("Quote: {:?}", res_quote);
 
@@ -414,26 +1566,50 @@ async fn swap(mut sdk: DarklakeSDK, user_keypair: Keypair, rpc_client: RpcClient
         .swap_tx(
             &token_mint_x,
             &token_mint_y,
-            1_000,
-            1,
+            amount_in,
+            min_out,
             &user_keypair.pubkey(),
         )
         .await?;
 
+    timer.stage("build");
+
+    let received_check =
+        ReceivedCheck::start(&rpc_client, &user_keypair.pubkey(), &token_mint_y, min_out)?;
+
     let tx = VersionedTransaction::try_new(swap_tx.message, &[&user_keypair])?;
-    let res = rpc_client.send_and_confirm_transaction_with_spinner(&tx)?;
+
+    timer.stage("sign");
+
+    let res = send_and_confirm(&rpc_client, &tx)?;
+
+    timer.stage("send + confirm");
 
     println!("Swap: {:?}", res);
 
+    // finalize_tx waits for the order account and generates the proof before returning
     let finalize_tx: solana_sdk::transaction::VersionedTransaction = sdk
         .finalize_tx(&order_key, unwrap_wsol, min_out, salt, None)
         .await?;
 
+    timer.stage("order + finalize build");
+
     let tx = VersionedTransaction::try_new(finalize_tx.message, &[&user_keypair])?;
 
-    let res = rpc_client.send_and_confirm_transaction_with_spinner(&tx)?;
+    let res = send_finalize(&rpc_client, &tx, &order_key)?;
+
+    timer.stage("finalize");
+
     println!("Finalize: {:?}", res);
 
+    if let Some(received_check) = received_check {
+        received_check.check(&rpc_client)?;
+    }
+
+    if latency_report {
+        timer.print_report();
+    }
+
     Ok(())
 }
 
@@ -446,8 +1622,8 @@ async fn swap_different_settler(
     println!("Darklake DEX SDK - Swap Different Settler");
     println!("==========================================");
 
-    let token_mint_x = Pubkey::from_str(TOKEN_MINT_X).unwrap();
-    let token_mint_y = Pubkey::from_str(TOKEN_MINT_Y).unwrap();
+    let token_mint_x = default_mint_x();
+    let token_mint_y = default_mint_y();
 
     println!("Token X Mint: {}", token_mint_x);
     println!("Token Y Mint: {}", token_mint_y);
@@ -468,9 +1644,12 @@ async fn swap_different_settler(
         )
         .await?;
 
+    let received_check =
+        ReceivedCheck::start(&rpc_client, &user_keypair.pubkey(), &token_mint_y, min_out)?;
+
     let tx = VersionedTransaction::try_new(swap_tx_.message, &[&user_keypair])?;
 
-    let res = rpc_client.send_and_confirm_transaction_with_spinner(&tx)?;
+    let res = send_and_confirm(&rpc_client, &tx)?;
 
     println!("Swap: {:?}", res);
 
@@ -486,9 +1665,18 @@ async fn swap_different_settler(
 
     let tx = VersionedTransaction::try_new(finalize_tx.message, &[&settler])?;
 
-    let res = rpc_client.send_and_confirm_transaction_with_spinner(&tx)?;
+    let settler_balance_before = rpc_client.get_balance(&settler.pubkey())?;
+
+    let res = send_finalize(&rpc_client, &tx, &order_key)?;
     println!("Finalize: {:?}", res);
 
+    let settler_balance_after = rpc_client.get_balance(&settler.pubkey())?;
+    print_settler_earnings(settler_balance_before, settler_balance_after);
+
+    if let Some(received_check) = received_check {
+        received_check.check(&rpc_client)?;
+    }
+
     Ok(())
 }
 
@@ -496,12 +1684,13 @@ async fn manual_add_liquidity(
     mut sdk: DarklakeSDK,
     user_keypair: Keypair,
     rpc_client: RpcClient,
+    tx_options: TxOptions,
 ) -> Result<()> {
     println!("Darklake DEX SDK - Manual Add Liquidity");
     println!("========================================");
 
-    let token_mint_x = Pubkey::from_str(TOKEN_MINT_X).unwrap();
-    let token_mint_y = Pubkey::from_str(TOKEN_MINT_Y).unwrap();
+    let token_mint_x = default_mint_x();
+    let token_mint_y = default_mint_y();
 
     println!("Loading pool...");
     sdk.load_pool(&token_mint_x, &token_mint_y).await?;
@@ -518,28 +1707,37 @@ async fn manual_add_liquidity(
 
     let add_liquidity_ix = sdk.add_liquidity_ix(&add_liquidity_params).await?;
 
-    let recent_blockhash = rpc_client
-        .get_latest_blockhash()
-        .context("Failed to get recent blockhash")?;
-
-    let address_lookup_table = get_address_lookup_table(&rpc_client, DEVNET_LOOKUP).await?;
+    let (recent_blockhash, address_lookup_table) =
+        tx_options.blockhash_and_lookup_table(&rpc_client).await?;
 
-    let message_v0 = v0::Message::try_compile(
+    let message = compile_message(
         &user_keypair.pubkey(),
-        &[add_liquidity_ix],
-        &[address_lookup_table],
+        &with_compute_budget(TxKind::AddLiquidity, vec![add_liquidity_ix]),
+        &address_lookup_table,
         recent_blockhash,
+        tx_options.legacy_tx,
     )?;
 
+    if tx_options.export(&message)? {
+        return Ok(());
+    }
+
+    if tx_options.cost_preview {
+        estimate_message_cost(&rpc_client, &message, TxKind::AddLiquidity.cu_limit())?.print();
+    }
+
+    if !tx_options.approve(&rpc_client, &message, None)? {
+        return Ok(());
+    }
+
     let mut transaction = VersionedTransaction {
         signatures: vec![],
-        message: VersionedMessage::V0(message_v0),
+        message,
     };
 
     transaction.signatures = vec![user_keypair.sign_message(&transaction.message.serialize())];
 
-    let _add_liquidity_signature =
-        rpc_client.send_and_confirm_transaction_with_spinner(&transaction)?;
+    let _add_liquidity_signature = send_and_confirm(&rpc_client, &transaction)?;
 
     println!(
         "Add Liquidity transaction signature: {}",
@@ -557,8 +1755,8 @@ async fn add_liquidity(
     println!("Darklake DEX SDK - Add Liquidity");
     println!("=================================");
 
-    let token_mint_x = Pubkey::from_str(TOKEN_MINT_X).unwrap();
-    let token_mint_y = Pubkey::from_str(TOKEN_MINT_Y).unwrap();
+    let token_mint_x = default_mint_x();
+    let token_mint_y = default_mint_y();
 
     println!("Token X Mint: {}", token_mint_x);
     println!("Token Y Mint: {}", token_mint_y);
@@ -576,7 +1774,7 @@ async fn add_liquidity(
 
     let tx = VersionedTransaction::try_new(add_liquidity_tx.message, &[&user_keypair])?;
 
-    let res = rpc_client.send_and_confirm_transaction_with_spinner(&tx)?;
+    let res = send_and_confirm(&rpc_client, &tx)?;
     println!("Add Liquidity: {:?}", res);
 
     Ok(())
@@ -586,12 +1784,13 @@ async fn manual_remove_liquidity(
     mut sdk: DarklakeSDK,
     user_keypair: Keypair,
     rpc_client: RpcClient,
+    tx_options: TxOptions,
 ) -> Result<()> {
     println!("Darklake DEX SDK - Manual Remove Liquidity");
     println!("===========================================");
 
-    let token_mint_x = Pubkey::from_str(TOKEN_MINT_X).unwrap();
-    let token_mint_y = Pubkey::from_str(TOKEN_MINT_Y).unwrap();
+    let token_mint_x = default_mint_x();
+    let token_mint_y = default_mint_y();
 
     println!("Loading pool...");
     sdk.load_pool(&token_mint_x, &token_mint_y).await?;
@@ -599,37 +1798,53 @@ async fn manual_remove_liquidity(
     println!("Updating accounts...");
     sdk.update_accounts().await?;
 
+    let amount_lp = 20;
+    let remove_liquidity_quote = quote_remove_liquidity(
+        &rpc_client,
+        &token_mint_x,
+        &token_mint_y,
+        amount_lp,
+        REMOVE_LIQUIDITY_SLIPPAGE_BPS,
+    )?;
+    remove_liquidity_quote.print();
+
     let remove_liquidity_params = RemoveLiquidityParamsIx {
         user: user_keypair.pubkey(),
-        amount_lp: 20,
-        min_amount_x: 1,
-        min_amount_y: 1,
+        amount_lp,
+        min_amount_x: remove_liquidity_quote.min_amount_x,
+        min_amount_y: remove_liquidity_quote.min_amount_y,
     };
 
     let remove_liquidity_ix = sdk.remove_liquidity_ix(&remove_liquidity_params).await?;
 
-    let recent_blockhash = rpc_client
-        .get_latest_blockhash()
-        .context("Failed to get recent blockhash")?;
-
-    let address_lookup_table = get_address_lookup_table(&rpc_client, DEVNET_LOOKUP).await?;
+    let (recent_blockhash, address_lookup_table) =
+        tx_options.blockhash_and_lookup_table(&rpc_client).await?;
 
-    let message_v0 = v0::Message::try_compile(
+    let message = compile_message(
         &user_keypair.pubkey(),
-        &[remove_liquidity_ix],
-        &[address_lookup_table],
+        &with_compute_budget(TxKind::RemoveLiquidity, vec![remove_liquidity_ix]),
+        &address_lookup_table,
         recent_blockhash,
+        tx_options.legacy_tx,
     )?;
 
+    if tx_options.export(&message)? {
+        return Ok(());
+    }
+
+    if !tx_options.approve(&rpc_client, &message, None)? {
+        return Ok(());
+    }
+
     let mut transaction = VersionedTransaction {
         signatures: vec![],
-        message: VersionedMessage::V0(message_v0),
+        message,
     };
 
     transaction.signatures = vec![user_keypair.sign_message(&transaction.message.serialize())];
 
     let _remove_liquidity_signature =
-        rpc_client.send_and_confirm_transaction_with_spinner(&transaction)?;
+        send_and_confirm(&rpc_client, &transaction).with_context(remove_liquidity_failed)?;
 
     println!(
         "Remove Liquidity transaction signature: {}",
@@ -639,6 +1854,14 @@ async fn manual_remove_liquidity(
     Ok(())
 }
 
+/// Context for a failed remove liquidity, usually the min amounts are no longer met
+fn remove_liquidity_failed() -> String {
+    format!(
+        "Remove liquidity failed, the pool may have moved more than {} bps since the quote",
+        REMOVE_LIQUIDITY_SLIPPAGE_BPS
+    )
+}
+
 async fn remove_liquidity(
     mut sdk: DarklakeSDK,
     user_keypair: Keypair,
@@ -647,26 +1870,37 @@ async fn remove_liquidity(
     println!("Darklake DEX SDK - Remove Liquidity");
     println!("====================================");
 
-    let token_mint_x = Pubkey::from_str(TOKEN_MINT_X).unwrap();
-    let token_mint_y = Pubkey::from_str(TOKEN_MINT_Y).unwrap();
+    let token_mint_x = default_mint_x();
+    let token_mint_y = default_mint_y();
 
     println!("Token X Mint: {}", token_mint_x);
     println!("Token Y Mint: {}", token_mint_y);
 
+    let amount_lp = 20;
+    let remove_liquidity_quote = quote_remove_liquidity(
+        &rpc_client,
+        &token_mint_x,
+        &token_mint_y,
+        amount_lp,
+        REMOVE_LIQUIDITY_SLIPPAGE_BPS,
+    )?;
+    remove_liquidity_quote.print();
+    let (min_amount_x, min_amount_y) = remove_liquidity_quote.min_amounts_for(&token_mint_x);
+
     let remove_liquidity_tx = sdk
         .remove_liquidity_tx(
             &token_mint_x,
             &token_mint_y,
-            1,
-            1,
-            20,
+            min_amount_x,
+            min_amount_y,
+            amount_lp,
             &user_keypair.pubkey(),
         )
         .await?;
 
     let tx = VersionedTransaction::try_new(remove_liquidity_tx.message, &[&user_keypair])?;
 
-    let res = rpc_client.send_and_confirm_transaction_with_spinner(&tx)?;
+    let res = send_and_confirm(&rpc_client, &tx).with_context(remove_liquidity_failed)?;
 
     println!("Remove Liquidity: {:?}", res);
 
@@ -682,7 +1916,7 @@ async fn manual_swap_from_sol(
     println!("=========================================");
 
     let token_mint_x = native_mint::ID;
-    let token_mint_y = Pubkey::from_str(TOKEN_MINT_X).unwrap();
+    let token_mint_y = default_mint_x();
 
     println!("Token X Mint (WSOL): {}", token_mint_x);
     println!("Token Y Mint (DuX): {}", token_mint_y);
@@ -697,8 +1931,13 @@ async fn manual_swap_from_sol(
     let min_out = 1;
     let sol_amount = 1_000;
 
-    let wrap_instructions =
-        utils::get_wrap_sol_to_wsol_instructions(user_keypair.pubkey(), sol_amount)?;
+    // Only wraps what the WSOL ATA lacks, keeping enough SOL around to pay for the finalize
+    let wrap_instructions = utils::get_wrap_shortfall_instructions(
+        &rpc_client,
+        user_keypair.pubkey(),
+        sol_amount,
+        fee_buffer(),
+    )?;
 
     let swap_params = SwapParamsIx {
         source_mint: token_mint_x,
@@ -712,38 +1951,47 @@ async fn manual_swap_from_sol(
 
     let swap_ix = sdk.swap_ix(&swap_params).await?;
 
-    let recent_blockhash = rpc_client
-        .get_latest_blockhash()
-        .context("Failed to get recent blockhash")?;
-
     let mut all_instructions = wrap_instructions;
     all_instructions.push(swap_ix);
 
-    let address_lookup_table = get_address_lookup_table(&rpc_client, DEVNET_LOOKUP).await?;
+    let (recent_blockhash, address_lookup_table) =
+        get_blockhash_and_lookup_table(&rpc_client, DEVNET_LOOKUP).await?;
 
-    let message_v0 = v0::Message::try_compile(
+    let message = compile_message(
         &user_keypair.pubkey(),
-        &all_instructions,
-        &[address_lookup_table],
+        &with_compute_budget(TxKind::Swap, all_instructions),
+        &address_lookup_table,
         recent_blockhash,
+        false,
     )?;
 
     let mut transaction = VersionedTransaction {
         signatures: vec![],
-        message: VersionedMessage::V0(message_v0),
+        message,
     };
 
     transaction.signatures = vec![user_keypair.sign_message(&transaction.message.serialize())];
 
     println!("Swap transaction signature: {}", transaction.signatures[0]);
 
-    let _swap_signature = rpc_client.send_and_confirm_transaction_with_spinner(&transaction)?;
+    let _swap_signature = send_and_confirm(&rpc_client, &transaction)?;
 
     let order = get_order(&sdk, &user_keypair.pubkey(), &rpc_client).await?;
 
     println!("Updating accounts...");
     sdk.update_accounts().await?;
 
+    let order_address = get_order_address(
+        &get_pool_address(&token_mint_x, &token_mint_y),
+        &user_keypair.pubkey(),
+    );
+    if !check_order_open(&rpc_client, &order_address)? {
+        return Ok(());
+    }
+
+    // the lookup table of the swap is reused, only the slot and blockhash are fresh
+    let (current_slot, recent_blockhash) = get_slot_and_blockhash(&rpc_client).await?;
+
     let finalize_params = FinalizeParamsIx {
         settle_signer: user_keypair.pubkey(),
         order_owner: user_keypair.pubkey(),
@@ -753,32 +2001,27 @@ async fn manual_swap_from_sol(
         output: order.d_out,
         commitment: order.c_min,
         deadline: order.deadline,
-        current_slot: rpc_client.get_slot()?,
+        current_slot,
     };
 
     let finalize_ix = sdk.finalize_ix(&finalize_params).await?;
 
-    let recent_blockhash = rpc_client
-        .get_latest_blockhash()
-        .context("Failed to get recent blockhash")?;
-
-    let address_lookup_table = get_address_lookup_table(&rpc_client, DEVNET_LOOKUP).await?;
-
-    let message_v0 = v0::Message::try_compile(
+    let message = compile_message(
         &user_keypair.pubkey(),
-        &[finalize_ix],
-        &[address_lookup_table],
+        &with_compute_budget(TxKind::Finalize, vec![finalize_ix]),
+        &address_lookup_table,
         recent_blockhash,
+        false,
     )?;
 
     let mut transaction = VersionedTransaction {
         signatures: vec![],
-        message: VersionedMessage::V0(message_v0),
+        message,
     };
 
     transaction.signatures = vec![user_keypair.sign_message(&transaction.message.serialize())];
 
-    let _finalize_signature = rpc_client.send_and_confirm_transaction_with_spinner(&transaction)?;
+    let _finalize_signature = send_finalize(&rpc_client, &transaction, &order_address)?;
 
     println!(
         "Finalize transaction signature: {}",
@@ -796,7 +2039,7 @@ async fn manual_swap_to_sol(
     println!("Darklake DEX SDK - Manual Swap To SOL");
     println!("======================================");
 
-    let token_mint_x = Pubkey::from_str(TOKEN_MINT_X).unwrap();
+    let token_mint_x = default_mint_x();
     let token_mint_y = native_mint::ID;
 
     println!("Token X Mint (DuX): {}", token_mint_x);
@@ -824,35 +2067,44 @@ async fn manual_swap_to_sol(
 
     let swap_ix = sdk.swap_ix(&swap_params).await?;
 
-    let recent_blockhash = rpc_client
-        .get_latest_blockhash()
-        .context("Failed to get recent blockhash")?;
-
-    let address_lookup_table = get_address_lookup_table(&rpc_client, DEVNET_LOOKUP).await?;
+    let (recent_blockhash, address_lookup_table) =
+        get_blockhash_and_lookup_table(&rpc_client, DEVNET_LOOKUP).await?;
 
-    let message_v0 = v0::Message::try_compile(
+    let message = compile_message(
         &user_keypair.pubkey(),
-        &[swap_ix],
-        &[address_lookup_table],
+        &with_compute_budget(TxKind::Swap, vec![swap_ix]),
+        &address_lookup_table,
         recent_blockhash,
+        false,
     )?;
 
     let mut transaction = VersionedTransaction {
         signatures: vec![],
-        message: VersionedMessage::V0(message_v0),
+        message,
     };
 
     transaction.signatures = vec![user_keypair.sign_message(&transaction.message.serialize())];
 
     println!("Swap transaction signature: {}", transaction.signatures[0]);
 
-    let _swap_signature = rpc_client.send_and_confirm_transaction_with_spinner(&transaction)?;
+    let _swap_signature = send_and_confirm(&rpc_client, &transaction)?;
 
     let order = get_order(&sdk, &user_keypair.pubkey(), &rpc_client).await?;
 
     println!("Updating accounts...");
     sdk.update_accounts().await?;
 
+    let order_address = get_order_address(
+        &get_pool_address(&token_mint_x, &token_mint_y),
+        &user_keypair.pubkey(),
+    );
+    if !check_order_open(&rpc_client, &order_address)? {
+        return Ok(());
+    }
+
+    // the lookup table of the swap is reused, only the slot and blockhash are fresh
+    let (current_slot, recent_blockhash) = get_slot_and_blockhash(&rpc_client).await?;
+
     let finalize_params = FinalizeParamsIx {
         settle_signer: user_keypair.pubkey(),
         order_owner: user_keypair.pubkey(),
@@ -862,35 +2114,30 @@ async fn manual_swap_to_sol(
         output: order.d_out,
         commitment: order.c_min,
         deadline: order.deadline,
-        current_slot: rpc_client.get_slot()?,
+        current_slot,
     };
 
     let finalize_ix = sdk.finalize_ix(&finalize_params).await?;
 
     // NOTE: Alternatively to unwrap_wsol you can manually unwrap the WSOL by closing the WSOL ATA
-    let recent_blockhash = rpc_client
-        .get_latest_blockhash()
-        .context("Failed to get recent blockhash")?;
-
     let all_instructions = vec![finalize_ix];
 
-    let address_lookup_table = get_address_lookup_table(&rpc_client, DEVNET_LOOKUP).await?;
-
-    let message_v0 = v0::Message::try_compile(
+    let message = compile_message(
         &user_keypair.pubkey(),
-        &all_instructions,
-        &[address_lookup_table],
+        &with_compute_budget(TxKind::Finalize, all_instructions),
+        &address_lookup_table,
         recent_blockhash,
+        false,
     )?;
 
     let mut transaction = VersionedTransaction {
         signatures: vec![],
-        message: VersionedMessage::V0(message_v0),
+        message,
     };
 
     transaction.signatures = vec![user_keypair.sign_message(&transaction.message.serialize())];
 
-    let _finalize_signature = rpc_client.send_and_confirm_transaction_with_spinner(&transaction)?;
+    let _finalize_signature = send_finalize(&rpc_client, &transaction, &order_address)?;
 
     println!(
         "Finalize transaction signature: {}",
@@ -911,20 +2158,30 @@ async fn swap_from_sol(
     // Darklake does not natively support SOL, SDK underneath will replace SOL with WSOL
     // and add a wrapping instruction
     let token_mint_x = Pubkey::from_str(SOL_MINT).unwrap();
-    let token_mint_y = Pubkey::from_str(TOKEN_MINT_X).unwrap();
+    let token_mint_y = default_mint_x();
 
     println!("Token X Mint (SOL): {}", token_mint_x);
     println!("Token Y Mint (DuX): {}", token_mint_y);
 
-    let res_quote = sdk.quote(&token_mint_x, &token_mint_y, 1_000).await?;
+    let sol_amount = 1_000;
+
+    let res_quote = sdk.quote(&token_mint_x, &token_mint_y, sol_amount).await?;
 
     println!("Quote: {:?}", res_quote);
 
+    // swap_tx wraps the SOL for us, make sure the wallet can still afford the finalize
+    check_wrap_amount(
+        &rpc_client,
+        &user_keypair.pubkey(),
+        sol_amount,
+        fee_buffer(),
+    )?;
+
     let (swap_tx_, order_key, min_out, salt) = sdk
         .swap_tx(
             &token_mint_x,
             &token_mint_y,
-            1_000,
+            sol_amount,
             1,
             &user_keypair.pubkey(),
         )
@@ -932,7 +2189,7 @@ async fn swap_from_sol(
 
     let tx = VersionedTransaction::try_new(swap_tx_.message, &[&user_keypair])?;
 
-    let res = rpc_client.send_and_confirm_transaction_with_spinner(&tx)?;
+    let res = send_and_confirm(&rpc_client, &tx)?;
 
     println!("Swap: {:?}", res);
 
@@ -942,7 +2199,7 @@ async fn swap_from_sol(
 
     let tx = VersionedTransaction::try_new(finalize_tx.message, &[&user_keypair])?;
 
-    let res = rpc_client.send_and_confirm_transaction_with_spinner(&tx)?;
+    let res = send_finalize(&rpc_client, &tx, &order_key)?;
 
     println!("Finalize: {:?}", res);
 
@@ -957,7 +2214,7 @@ async fn swap_to_sol(
     println!("Darklake DEX SDK - Swap To SOL");
     println!("===============================");
 
-    let token_mint_x = Pubkey::from_str(TOKEN_MINT_X).unwrap(); // DuX
+    let token_mint_x = default_mint_x(); // DuX
     let token_mint_y = Pubkey::from_str(SOL_MINT).unwrap(); // SOL
 
     println!("Token X Mint (DuX): {}", token_mint_x);
@@ -979,7 +2236,7 @@ async fn swap_to_sol(
 
     let tx = VersionedTransaction::try_new(swap_tx_.message, &[&user_keypair])?;
 
-    let res = rpc_client.send_and_confirm_transaction_with_spinner(&tx)?;
+    let res = send_and_confirm(&rpc_client, &tx)?;
 
     println!("Swap: {:?}", res);
 
@@ -989,7 +2246,7 @@ async fn swap_to_sol(
 
     let tx = VersionedTransaction::try_new(finalize_tx.message, &[&user_keypair])?;
 
-    let res = rpc_client.send_and_confirm_transaction_with_spinner(&tx)?;
+    let res = send_finalize(&rpc_client, &tx, &order_key)?;
 
     println!("Finalize: {:?}", res);
 
@@ -1005,7 +2262,7 @@ async fn manual_add_liquidity_sol(
     println!("=============================================");
 
     let token_mint_x = native_mint::ID;
-    let token_mint_y = Pubkey::from_str(TOKEN_MINT_X).unwrap();
+    let token_mint_y = default_mint_x();
 
     println!("Token X Mint (WSOL): {}", token_mint_x);
     println!("Token Y Mint (DuX): {}", token_mint_y);
@@ -1019,8 +2276,13 @@ async fn manual_add_liquidity_sol(
     let sol_amount = 1_000;
     let token_amount = 1_000;
 
-    let wrap_instructions =
-        utils::get_wrap_sol_to_wsol_instructions(user_keypair.pubkey(), sol_amount)?;
+    // WSOL left over by an earlier add liquidity is used before wrapping more
+    let wrap_instructions = utils::get_wrap_shortfall_instructions(
+        &rpc_client,
+        user_keypair.pubkey(),
+        sol_amount,
+        fee_buffer(),
+    )?;
 
     let add_liquidity_params = AddLiquidityParamsIx {
         user: user_keypair.pubkey(),
@@ -1031,33 +2293,30 @@ async fn manual_add_liquidity_sol(
 
     let add_liquidity_ix = sdk.add_liquidity_ix(&add_liquidity_params).await?;
 
-    let recent_blockhash = rpc_client
-        .get_latest_blockhash()
-        .context("Failed to get recent blockhash")?;
-
     let mut all_instructions = wrap_instructions;
     all_instructions.push(add_liquidity_ix);
 
-    let address_lookup_table = get_address_lookup_table(&rpc_client, DEVNET_LOOKUP).await?;
+    let (recent_blockhash, address_lookup_table) =
+        get_blockhash_and_lookup_table(&rpc_client, DEVNET_LOOKUP).await?;
 
-    let message_v0 = v0::Message::try_compile(
+    let message = compile_message(
         &user_keypair.pubkey(),
-        &all_instructions,
-        &[address_lookup_table],
+        &with_compute_budget(TxKind::AddLiquidity, all_instructions),
+        &address_lookup_table,
         recent_blockhash,
+        false,
     )?;
 
     let mut transaction = VersionedTransaction {
         signatures: vec![],
-        message: VersionedMessage::V0(message_v0),
+        message,
     };
 
     transaction.signatures = vec![user_keypair.sign_message(&transaction.message.serialize())];
 
     // NOTE: Optionally you can close the WSOL ATA after adding liquidity as it may contain some WSOL that wasn't used
 
-    let _add_liquidity_signature =
-        rpc_client.send_and_confirm_transaction_with_spinner(&transaction)?;
+    let _add_liquidity_signature = send_and_confirm(&rpc_client, &transaction)?;
 
     println!(
         "Add Liquidity transaction signature: {}",
@@ -1076,7 +2335,7 @@ async fn manual_remove_liquidity_sol(
     println!("===============================================");
 
     let token_mint_x = native_mint::ID;
-    let token_mint_y = Pubkey::from_str(TOKEN_MINT_X).unwrap();
+    let token_mint_y = default_mint_x();
 
     println!("Token X Mint (WSOL): {}", token_mint_x);
     println!("Token Y Mint (DuX): {}", token_mint_y);
@@ -1095,42 +2354,50 @@ async fn manual_remove_liquidity_sol(
             &spl_token::ID,
         );
 
+    let amount_lp = 20;
+    let remove_liquidity_quote = quote_remove_liquidity(
+        &rpc_client,
+        &token_mint_x,
+        &token_mint_y,
+        amount_lp,
+        REMOVE_LIQUIDITY_SLIPPAGE_BPS,
+    )?;
+    remove_liquidity_quote.print();
+
     let remove_liquidity_params = RemoveLiquidityParamsIx {
         user: user_keypair.pubkey(),
-        amount_lp: 20,
-        min_amount_x: 1, // Minimal SOL amount to receive
-        min_amount_y: 1, // Minimal DuX token amount to receive
+        amount_lp,
+        min_amount_x: remove_liquidity_quote.min_amount_x,
+        min_amount_y: remove_liquidity_quote.min_amount_y,
     };
 
     let remove_liquidity_ix = sdk.remove_liquidity_ix(&remove_liquidity_params).await?;
 
     let unwrap_instructions = utils::get_unwrap_wsol_to_sol_instructions(user_keypair.pubkey())?;
 
-    let recent_blockhash = rpc_client
-        .get_latest_blockhash()
-        .context("Failed to get recent blockhash")?;
-
     let mut all_instructions = vec![create_wsol_ata_ix, remove_liquidity_ix];
     all_instructions.extend(unwrap_instructions);
 
-    let address_lookup_table = get_address_lookup_table(&rpc_client, DEVNET_LOOKUP).await?;
+    let (recent_blockhash, address_lookup_table) =
+        get_blockhash_and_lookup_table(&rpc_client, DEVNET_LOOKUP).await?;
 
-    let message_v0 = v0::Message::try_compile(
+    let message = compile_message(
         &user_keypair.pubkey(),
-        &all_instructions,
-        &[address_lookup_table],
+        &with_compute_budget(TxKind::RemoveLiquidity, all_instructions),
+        &address_lookup_table,
         recent_blockhash,
+        false,
     )?;
 
     let mut transaction = VersionedTransaction {
         signatures: vec![],
-        message: VersionedMessage::V0(message_v0),
+        message,
     };
 
     transaction.signatures = vec![user_keypair.sign_message(&transaction.message.serialize())];
 
     let _remove_liquidity_signature =
-        rpc_client.send_and_confirm_transaction_with_spinner(&transaction)?;
+        send_and_confirm(&rpc_client, &transaction).with_context(remove_liquidity_failed)?;
 
     println!(
         "Remove Liquidity transaction signature: {}",
@@ -1149,25 +2416,36 @@ async fn remove_liquidity_sol(
     println!("=========================================");
 
     let token_mint_x = Pubkey::from_str(SOL_MINT).unwrap();
-    let token_mint_y = Pubkey::from_str(TOKEN_MINT_X).unwrap();
+    let token_mint_y = default_mint_x();
 
     println!("Token X Mint (SOL): {}", token_mint_x);
     println!("Token Y Mint (DuX): {}", token_mint_y);
 
+    let amount_lp = 20;
+    let remove_liquidity_quote = quote_remove_liquidity(
+        &rpc_client,
+        &token_mint_x,
+        &token_mint_y,
+        amount_lp,
+        REMOVE_LIQUIDITY_SLIPPAGE_BPS,
+    )?;
+    remove_liquidity_quote.print();
+    let (min_amount_x, min_amount_y) = remove_liquidity_quote.min_amounts_for(&token_mint_x);
+
     let remove_liquidity_tx = sdk
         .remove_liquidity_tx(
             &token_mint_x,
             &token_mint_y,
-            1,
-            1,
-            20,
+            min_amount_x,
+            min_amount_y,
+            amount_lp,
             &user_keypair.pubkey(),
         )
         .await?;
 
     let tx = VersionedTransaction::try_new(remove_liquidity_tx.message, &[&user_keypair])?;
 
-    let res = rpc_client.send_and_confirm_transaction_with_spinner(&tx)?;
+    let res = send_and_confirm(&rpc_client, &tx).with_context(remove_liquidity_failed)?;
 
     println!("Remove Liquidity: {:?}", res);
 
@@ -1183,17 +2461,27 @@ async fn add_liquidity_sol(
     println!("=====================================");
 
     let token_mint_x = Pubkey::from_str(SOL_MINT).unwrap();
-    let token_mint_y = Pubkey::from_str(TOKEN_MINT_X).unwrap();
+    let token_mint_y = default_mint_x();
 
     println!("Token X Mint (SOL): {}", token_mint_x);
     println!("Token Y Mint (DuX): {}", token_mint_y);
 
+    let sol_amount = 1_000;
+    let token_amount = 1_000;
+
+    check_wrap_amount(
+        &rpc_client,
+        &user_keypair.pubkey(),
+        sol_amount,
+        fee_buffer(),
+    )?;
+
     let add_liquidity_tx = sdk
         .add_liquidity_tx(
             &token_mint_x,
             &token_mint_y,
-            1_000,
-            1_000,
+            sol_amount,
+            token_amount,
             20,
             &user_keypair.pubkey(),
         )
@@ -1201,7 +2489,7 @@ async fn add_liquidity_sol(
 
     let tx = VersionedTransaction::try_new(add_liquidity_tx.message, &[&user_keypair])?;
 
-    let res = rpc_client.send_and_confirm_transaction_with_spinner(&tx)?;
+    let res = send_and_confirm(&rpc_client, &tx)?;
     println!("Add Liquidity: {:?}", res);
 
     Ok(())
@@ -1211,29 +2499,68 @@ async fn manual_init_pool(
     sdk: DarklakeSDK,
     user_keypair: Keypair,
     rpc_client: RpcClient,
+    existing_mints: Option<(Pubkey, Pubkey)>,
+    mint_seed: Option<&str>,
+    cost_preview: bool,
 ) -> Result<()> {
     println!("Darklake DEX SDK - Manual Init Pool");
     println!("=====================================");
 
-    println!("Creating new token mints...");
-    let (token_mint_x, token_mint_y) =
-        create_new_tokens(&rpc_client, &user_keypair, 1_000_000_000).await?;
+    let (token_mint_x, token_mint_y) = get_or_create_new_tokens(
+        &rpc_client,
+        &user_keypair,
+        existing_mints,
+        mint_seed,
+        "manual-",
+        1_000_000_000,
+    )
+    .await?;
 
     println!("Token X Mint: {}", token_mint_x);
     println!("Token Y Mint: {}", token_mint_y);
 
+    let res = manual_init_pool_with_mints(
+        sdk,
+        &user_keypair,
+        &rpc_client,
+        token_mint_x,
+        token_mint_y,
+        cost_preview,
+    )
+    .await;
+    if res.is_err() {
+        print_init_pool_recovery("manual_init_pool", &[token_mint_x, token_mint_y]);
+    }
+
+    res
+}
+
+async fn manual_init_pool_with_mints(
+    sdk: DarklakeSDK,
+    user_keypair: &Keypair,
+    rpc_client: &RpcClient,
+    token_mint_x: Pubkey,
+    token_mint_y: Pubkey,
+    cost_preview: bool,
+) -> Result<()> {
     let (ordered_token_mint_x, ordered_token_mint_y) = if token_mint_x < token_mint_y {
         (token_mint_x, token_mint_y)
     } else {
         (token_mint_y, token_mint_x)
     };
 
+    let [token_x_program, token_y_program] = <[_; 2]>::try_from(resolve_token_programs(
+        rpc_client,
+        &[ordered_token_mint_x, ordered_token_mint_y],
+    )?)
+    .map_err(|_| anyhow!("Unexpected number of token mints returned"))?;
+
     let initialize_pool_params = InitializePoolParamsIx {
         user: user_keypair.pubkey(),
         token_x: ordered_token_mint_x,
-        token_x_program: spl_token::ID,
+        token_x_program,
         token_y: ordered_token_mint_y,
-        token_y_program: spl_token::ID,
+        token_y_program,
         amount_x: 1_000,
         amount_y: 1_001,
     };
@@ -1241,32 +2568,31 @@ async fn manual_init_pool(
     println!("Initializing pool...");
     let initialize_pool_ix = sdk.initialize_pool_ix(&initialize_pool_params).await?;
 
-    let recent_blockhash = rpc_client
-        .get_latest_blockhash()
-        .context("Failed to get recent blockhash")?;
-
-    let compute_budget_ix: Instruction = ComputeBudgetInstruction::set_compute_unit_limit(500_000);
+    let all_instructions = vec![initialize_pool_ix];
 
-    let all_instructions = vec![compute_budget_ix, initialize_pool_ix];
-
-    let address_lookup_table = get_address_lookup_table(&rpc_client, DEVNET_LOOKUP).await?;
+    let (recent_blockhash, address_lookup_table) =
+        get_blockhash_and_lookup_table(rpc_client, DEVNET_LOOKUP).await?;
 
-    let message_v0 = v0::Message::try_compile(
+    let message = compile_message(
         &user_keypair.pubkey(),
-        &all_instructions,
-        &[address_lookup_table],
+        &with_compute_budget(TxKind::InitPool, all_instructions),
+        &address_lookup_table,
         recent_blockhash,
+        false,
     )?;
 
+    if cost_preview {
+        estimate_message_cost(rpc_client, &message, TxKind::InitPool.cu_limit())?.print();
+    }
+
     let mut transaction = VersionedTransaction {
         signatures: vec![],
-        message: VersionedMessage::V0(message_v0),
+        message,
     };
 
     transaction.signatures = vec![user_keypair.sign_message(&transaction.message.serialize())];
 
-    let _initialize_pool_signature =
-        rpc_client.send_and_confirm_transaction_with_spinner(&transaction)?;
+    let _initialize_pool_signature = send_and_confirm(rpc_client, &transaction)?;
 
     println!(
         "Initialize Pool transaction signature: {}",
@@ -1277,20 +2603,44 @@ async fn manual_init_pool(
 }
 
 async fn init_pool(
-    mut sdk: DarklakeSDK,
+    sdk: DarklakeSDK,
     user_keypair: Keypair,
     rpc_client: RpcClient,
+    existing_mints: Option<(Pubkey, Pubkey)>,
+    mint_seed: Option<&str>,
 ) -> Result<()> {
     println!("Darklake DEX SDK - Init Pool");
     println!("=====================================");
 
-    println!("Creating new token mints...");
-    let (token_mint_x, token_mint_y) =
-        create_new_tokens(&rpc_client, &user_keypair, 1_000_000_000).await?;
+    let (token_mint_x, token_mint_y) = get_or_create_new_tokens(
+        &rpc_client,
+        &user_keypair,
+        existing_mints,
+        mint_seed,
+        "",
+        1_000_000_000,
+    )
+    .await?;
 
     println!("Token X Mint: {}", token_mint_x);
     println!("Token Y Mint: {}", token_mint_y);
 
+    let res =
+        init_pool_with_mints(sdk, &user_keypair, &rpc_client, token_mint_x, token_mint_y).await;
+    if res.is_err() {
+        print_init_pool_recovery("init_pool", &[token_mint_x, token_mint_y]);
+    }
+
+    res
+}
+
+async fn init_pool_with_mints(
+    mut sdk: DarklakeSDK,
+    user_keypair: &Keypair,
+    rpc_client: &RpcClient,
+    token_mint_x: Pubkey,
+    token_mint_y: Pubkey,
+) -> Result<()> {
     println!("Initializing pool...");
     let initialize_pool_tx = sdk
         .initialize_pool_tx(
@@ -1302,66 +2652,2399 @@ async fn init_pool(
         )
         .await?;
 
-    let tx = VersionedTransaction::try_new(initialize_pool_tx.message, &[&user_keypair])?;
+    let tx = VersionedTransaction::try_new(initialize_pool_tx.message, &[user_keypair])?;
 
-    let res = rpc_client.send_and_confirm_transaction_with_spinner(&tx)?;
+    let res = send_and_confirm(rpc_client, &tx)?;
     println!("Initialize Pool: {:?}", res);
 
     Ok(())
 }
 
 async fn init_pool_sol(
-    mut sdk: DarklakeSDK,
+    sdk: DarklakeSDK,
     user_keypair: Keypair,
     rpc_client: RpcClient,
+    existing_mint: Option<Pubkey>,
+    mint_seed: Option<&str>,
 ) -> Result<()> {
     println!("Darklake DEX SDK - Init Pool SOL");
     println!("=====================================");
 
     let mint_amount = 1_000_000_000;
 
-    println!("Creating new token mint...");
-    let token_mint_x_keypair = Keypair::new();
+    let token_mint_x = match existing_mint {
+        Some(token_mint_x) => {
+            println!("Reusing existing token mint...");
+            token_mint_x
+        }
+        None => {
+            println!("Creating new token mint...");
+            let token_mint_x_keypair = new_mint_keypair(mint_seed, "sol-x")?;
+
+            println!("Creating Token X Mint...");
+            let token_mint_x =
+                create_token_mint(&rpc_client, &user_keypair, &token_mint_x_keypair).await?;
+
+            println!("Token X Mint: {}", token_mint_x);
+
+            println!("Minting Token X to user...");
+            mint_tokens_to_user(&rpc_client, &user_keypair, &token_mint_x, mint_amount).await?;
+            token_mint_x
+        }
+    };
+
+    println!("Token X Mint: {}", token_mint_x);
+
+    let token_mint_y = Pubkey::from_str(SOL_MINT).unwrap();
+
+    let res =
+        init_pool_with_mints(sdk, &user_keypair, &rpc_client, token_mint_x, token_mint_y).await;
+    if res.is_err() {
+        print_init_pool_recovery("init_pool_sol", &[token_mint_x]);
+    }
+
+    res
+}
+
+async fn fees_estimate(
+    mut sdk: DarklakeSDK,
+    user_keypair: Keypair,
+    rpc_client: RpcClient,
+    operation: &str,
+) -> Result<()> {
+    println!("Darklake DEX SDK - Fees Estimate ({})", operation);
+    println!("=====================================");
+
+    let token_mint_x = default_mint_x();
+    let token_mint_y = default_mint_y();
+
+    let salt = [1, 2, 3, 4, 5, 6, 7, 8];
+    let min_out = 1;
+    let mut init_pool_mints = None;
+
+    let (kind, instructions) = match operation {
+        "swap" => {
+            sdk.load_pool(&token_mint_x, &token_mint_y).await?;
+            sdk.update_accounts().await?;
+
+            let swap_params = SwapParamsIx {
+                source_mint: token_mint_x,
+                destination_mint: token_mint_y,
+                token_transfer_authority: user_keypair.pubkey(),
+                amount_in: 1_000,
+                swap_mode: SwapMode::ExactIn,
+                min_out,
+                salt,
+            };
+
+            (TxKind::Swap, vec![sdk.swap_ix(&swap_params).await?])
+        }
+        "finalize" => {
+            sdk.load_pool(&token_mint_x, &token_mint_y).await?;
+            sdk.update_accounts().await?;
+
+            // Needs a pending order created with the example salt / min_out (e.g. manual_swap)
+            let order = sdk
+                .get_order(&user_keypair.pubkey(), rpc_client.commitment().commitment)
+                .await
+                .context("No pending order to finalize, run a swap first")?;
+
+            let finalize_params = FinalizeParamsIx {
+                settle_signer: user_keypair.pubkey(),
+                order_owner: user_keypair.pubkey(),
+                unwrap_wsol: false,
+                min_out,
+                salt,
+                output: order.d_out,
+                commitment: order.c_min,
+                deadline: order.deadline,
+                current_slot: rpc_client.get_slot()?,
+            };
+
+            (
+                TxKind::Finalize,
+                vec![sdk.finalize_ix(&finalize_params).await?],
+            )
+        }
+        "add_liquidity" => {
+            sdk.load_pool(&token_mint_x, &token_mint_y).await?;
+            sdk.update_accounts().await?;
+
+            let add_liquidity_params = AddLiquidityParamsIx {
+                user: user_keypair.pubkey(),
+                amount_lp: 20,
+                max_amount_x: 1_000,
+                max_amount_y: 1_000,
+            };
+
+            (
+                TxKind::AddLiquidity,
+                vec![sdk.add_liquidity_ix(&add_liquidity_params).await?],
+            )
+        }
+        "init_pool" => {
+            // Fresh mints stand in for the tokens init_pool would create, they don't exist
+            // on-chain so the pool rent can't be simulated and is computed from the sizes
+            let mint_a = Keypair::new().pubkey();
+            let mint_b = Keypair::new().pubkey();
+            let (token_x, token_y) = if mint_a < mint_b {
+                (mint_a, mint_b)
+            } else {
+                (mint_b, mint_a)
+            };
+            init_pool_mints = Some((token_x, token_y));
+
+            let initialize_pool_params = InitializePoolParamsIx {
+                user: user_keypair.pubkey(),
+                token_x,
+                token_x_program: spl_token::ID,
+                token_y,
+                token_y_program: spl_token::ID,
+                amount_x: 1_000,
+                amount_y: 1_001,
+            };
+
+            (
+                TxKind::InitPool,
+                vec![sdk.initialize_pool_ix(&initialize_pool_params).await?],
+            )
+        }
+        _ => bail!(
+            "Unknown operation: {} (expected swap, finalize, add_liquidity or init_pool)",
+            operation
+        ),
+    };
+
+    let (recent_blockhash, address_lookup_table) =
+        get_blockhash_and_lookup_table(&rpc_client, DEVNET_LOOKUP).await?;
+
+    let compute_unit_limit = kind.cu_limit();
+    let message = compile_message(
+        &user_keypair.pubkey(),
+        &with_compute_budget(kind, instructions),
+        &address_lookup_table,
+        recent_blockhash,
+        false,
+    )?;
+
+    let mut fee_estimate = estimate_message_cost(&rpc_client, &message, compute_unit_limit)?;
+    if let Some((token_x, token_y)) = init_pool_mints {
+        fee_estimate.new_accounts =
+            get_init_pool_new_accounts(&rpc_client, &user_keypair.pubkey(), &token_x, &token_y)?;
+        fee_estimate.rent_error = None;
+    }
+    fee_estimate.print();
+    if init_pool_mints.is_some() {
+        println!("The LP token's metadata account is not included in the rent");
+    }
+
+    Ok(())
+}
+
+fn pool_health(rpc_client: RpcClient, token_mint_a: &Pubkey, token_mint_b: &Pubkey) -> Result<()> {
+    println!("Darklake DEX SDK - Pool Health");
+    println!("===============================");
+
+    let health = check_pool_health(&rpc_client, &get_pool_address(token_mint_a, token_mint_b))?;
+    health.print();
+
+    // non-zero exit code so monitoring can alert on it
+    if !health.is_healthy() {
+        bail!("Pool is unhealthy");
+    }
+
+    Ok(())
+}
+
+async fn prepare(
+    user_keypair: Keypair,
+    rpc_client: RpcClient,
+    token_mint_a: &Pubkey,
+    token_mint_b: &Pubkey,
+) -> Result<()> {
+    println!("Darklake DEX SDK - Prepare");
+    println!("===========================");
+
+    let owner = user_keypair.pubkey();
+    let addresses = derive_order_addresses(&owner, token_mint_a, token_mint_b);
+    let mints = [
+        addresses.token_mint_x,
+        addresses.token_mint_y,
+        addresses.token_mint_lp,
+    ];
+    let token_programs = resolve_token_programs(&rpc_client, &mints)
+        .context("Failed to resolve the pair's token programs, does the pool exist?")?;
+    let token_accounts: Vec<_> = mints
+        .iter()
+        .zip(token_programs)
+        .map(|(mint, token_program)| {
+            (
+                *mint,
+                token_program,
+                get_associated_token_address_with_program_id(&owner, mint, &token_program),
+            )
+        })
+        .collect();
+
+    let existing = rpc_client
+        .get_multiple_accounts(
+            &token_accounts
+                .iter()
+                .map(|(_, _, address)| *address)
+                .collect::<Vec<_>>(),
+        )
+        .context("Failed to get token accounts")?;
+
+    let mut instructions = Vec::new();
+    for ((mint, token_program, address), account) in token_accounts.iter().zip(existing) {
+        if account.is_some() {
+            println!("Token account of {}: {} (exists)", mint, address);
+            continue;
+        }
+        println!("Token account of {}: {} (creating)", mint, address);
+        instructions.push(
+            spl_associated_token_account::instruction::create_associated_token_account_idempotent(
+                &owner,
+                &owner,
+                mint,
+                token_program,
+            ),
+        );
+    }
+
+    if instructions.is_empty() {
+        println!("All token accounts of the pair already exist");
+        return Ok(());
+    }
+
+    let (recent_blockhash, address_lookup_table) =
+        get_blockhash_and_lookup_table(&rpc_client, DEVNET_LOOKUP).await?;
+    let message = compile_message(
+        &owner,
+        &with_compute_budget(TxKind::CreateAccounts, instructions),
+        &address_lookup_table,
+        recent_blockhash,
+        false,
+    )?;
+
+    let mut transaction = VersionedTransaction {
+        signatures: vec![],
+        message,
+    };
+
+    transaction.signatures = vec![user_keypair.sign_message(&transaction.message.serialize())];
+
+    let res = send_and_confirm(&rpc_client, &transaction)?;
+
+    println!("Create token accounts: {:?}", res);
+
+    Ok(())
+}
+
+/// Compile and sign a transaction of the user's with the default lookup table, without
+/// sending it
+async fn sign_transaction(
+    rpc_client: &RpcClient,
+    user_keypair: &Keypair,
+    kind: TxKind,
+    instructions: Vec<Instruction>,
+) -> Result<VersionedTransaction> {
+    let (recent_blockhash, address_lookup_table) =
+        get_blockhash_and_lookup_table(rpc_client, DEVNET_LOOKUP).await?;
+    let message = compile_message(
+        &user_keypair.pubkey(),
+        &with_compute_budget(kind, instructions),
+        &address_lookup_table,
+        recent_blockhash,
+        false,
+    )?;
+
+    let mut transaction = VersionedTransaction {
+        signatures: vec![],
+        message,
+    };
+
+    transaction.signatures = vec![user_keypair.sign_message(&transaction.message.serialize())];
+
+    Ok(transaction)
+}
+
+/// Finalize the order a swap of the user's opened, unless it's settled already
+async fn finalize_swap_order(
+    sdk: &mut DarklakeSDK,
+    user_keypair: &Keypair,
+    rpc_client: &RpcClient,
+    swap_params: &SwapParamsIx,
+) -> Result<()> {
+    let order_address = get_order_address(
+        &get_pool_address(&swap_params.source_mint, &swap_params.destination_mint),
+        &user_keypair.pubkey(),
+    );
+    // a keeper may have settled it already, the output is then measured all the same
+    if !check_order_open(rpc_client, &order_address)? {
+        return Ok(());
+    }
+
+    sdk.load_pool(&swap_params.source_mint, &swap_params.destination_mint)
+        .await?;
+    let order = get_order(sdk, &user_keypair.pubkey(), rpc_client).await?;
+    sdk.update_accounts().await?;
+
+    let finalize_params = FinalizeRequest::for_swap(swap_params, &order).to_params(rpc_client)?;
+    let finalize_ix = sdk.finalize_ix(&finalize_params).await?;
+    let transaction = sign_transaction(
+        rpc_client,
+        user_keypair,
+        TxKind::Finalize,
+        vec![finalize_ix],
+    )
+    .await?;
+
+    send_finalize(rpc_client, &transaction, &order_address)?;
+    println!(
+        "Finalize transaction signature: {}",
+        transaction.signatures[0]
+    );
+    Ok(())
+}
+
+/// Next step of a liquidity migration
+#[derive(Clone, Copy)]
+enum MigrationStep {
+    Remove,
+    /// Swap of a token of the old pair into the new pair, or of the new pair's token held
+    /// above the pool ratio into the other one when `balance` is set
+    Swap {
+        token_mint_in: Pubkey,
+        token_mint_out: Pubkey,
+        amount_in: u64,
+        balance: bool,
+    },
+    Add {
+        amount_lp: u64,
+    },
+    Done,
+}
+
+impl MigrationStep {
+    fn to_json(self) -> serde_json::Value {
+        match self {
+            MigrationStep::Remove => serde_json::json!({ "step": "remove" }),
+            MigrationStep::Swap {
+                token_mint_in,
+                token_mint_out,
+                amount_in,
+                balance,
+            } => serde_json::json!({
+                "step": if balance { "balance" } else { "swap" },
+                "token_mint_in": token_mint_in.to_string(),
+                "token_mint_out": token_mint_out.to_string(),
+                "amount_in": amount_in,
+            }),
+            MigrationStep::Add { amount_lp } => {
+                serde_json::json!({ "step": "add", "amount_lp": amount_lp })
+            }
+            MigrationStep::Done => serde_json::json!({ "step": "done" }),
+        }
+    }
+
+    fn from_json(value: &serde_json::Value) -> Result<Self> {
+        let mint = |key: &str| -> Result<Pubkey> {
+            Pubkey::from_str(value[key].as_str().unwrap_or_default())
+                .with_context(|| format!("Invalid {} in migration file", key))
+        };
+        let amount = |key: &str| {
+            value[key]
+                .as_u64()
+                .with_context(|| format!("Invalid {} in migration file", key))
+        };
+        Ok(match value["step"].as_str() {
+            Some("remove") => MigrationStep::Remove,
+            Some(step @ ("swap" | "balance")) => MigrationStep::Swap {
+                token_mint_in: mint("token_mint_in")?,
+                token_mint_out: mint("token_mint_out")?,
+                amount_in: amount("amount_in")?,
+                balance: step == "balance",
+            },
+            Some("add") => MigrationStep::Add {
+                amount_lp: amount("amount_lp")?,
+            },
+            _ => bail!("Invalid pending step in migration file"),
+        })
+    }
+}
+
+/// Step whose transaction is saved before it's sent, so a resumed run checks whether it
+/// landed instead of sending the step again
+struct PendingStep {
+    step: MigrationStep,
+    signature: Signature,
+    recent_blockhash: Hash,
+    /// User balances of the two tokens the step moves, before it was sent
+    balances_before: [(Pubkey, u64); 2],
+    /// Salt and min out of a swap, to finalize its order
+    swap: Option<([u8; 8], u64)>,
+}
+
+impl PendingStep {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "step": self.step.to_json(),
+            "signature": self.signature.to_string(),
+            "recent_blockhash": self.recent_blockhash.to_string(),
+            "balances_before": self
+                .balances_before
+                .map(|(mint, amount)| serde_json::json!([mint.to_string(), amount])),
+            "swap": self
+                .swap
+                .map(|(salt, min_out)| serde_json::json!({ "salt": salt, "min_out": min_out })),
+        })
+    }
+
+    fn from_json(value: &serde_json::Value) -> Result<Self> {
+        let invalid = || anyhow!("Invalid pending step in migration file");
+        let balance = |index: usize| -> Result<(Pubkey, u64)> {
+            let entry = &value["balances_before"][index];
+            Ok((
+                Pubkey::from_str(entry[0].as_str().ok_or_else(invalid)?)?,
+                entry[1].as_u64().ok_or_else(invalid)?,
+            ))
+        };
+        let swap = match value["swap"].as_object() {
+            Some(swap) => Some((
+                serde_json::from_value(swap["salt"].clone())?,
+                swap["min_out"].as_u64().ok_or_else(invalid)?,
+            )),
+            None => None,
+        };
+
+        Ok(Self {
+            step: MigrationStep::from_json(&value["step"])?,
+            signature: Signature::from_str(value["signature"].as_str().ok_or_else(invalid)?)?,
+            recent_blockhash: Hash::from_str(
+                value["recent_blockhash"].as_str().ok_or_else(invalid)?,
+            )?,
+            balances_before: [balance(0)?, balance(1)?],
+            swap,
+        })
+    }
+}
+
+/// Progress of a liquidity migration, saved after every step so an interrupted run resumes
+/// where it stopped
+struct Migration {
+    amount_lp: u64,
+    pool_from: Pubkey,
+    token_mints_from: [Pubkey; 2],
+    pool_to: Pubkey,
+    token_mints_to: [Pubkey; 2],
+    /// Tokens received from the old pool and not added to the new one yet
+    holdings: BTreeMap<Pubkey, u64>,
+    removed: bool,
+    balanced: bool,
+    added: bool,
+    /// Step sent but not known to have landed yet
+    pending: Option<PendingStep>,
+    /// Executed steps, for the record
+    log: Vec<serde_json::Value>,
+}
+
+impl Migration {
+    fn path(&self) -> PathBuf {
+        Path::new(MIGRATIONS_OUTPUT_DIR).join(format!("{}-{}.json", self.pool_from, self.pool_to))
+    }
+
+    /// The saved migration between the two pairs, or a new one
+    fn load(
+        amount_lp: u64,
+        token_mints_from: [Pubkey; 2],
+        token_mints_to: [Pubkey; 2],
+    ) -> Result<Self> {
+        let mut migration = Self {
+            amount_lp,
+            pool_from: get_pool_address(&token_mints_from[0], &token_mints_from[1]),
+            token_mints_from,
+            pool_to: get_pool_address(&token_mints_to[0], &token_mints_to[1]),
+            token_mints_to,
+            holdings: BTreeMap::new(),
+            removed: false,
+            balanced: false,
+            added: false,
+            pending: None,
+            log: vec![],
+        };
+
+        let path = migration.path();
+        if !path.exists() {
+            return Ok(migration);
+        }
+
+        let saved: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path)?)
+            .with_context(|| format!("Invalid migration file {}", path.display()))?;
+        if saved["amount_lp"].as_u64() != Some(amount_lp) {
+            bail!(
+                "{} is a migration of {} LP tokens, delete it to start another one",
+                path.display(),
+                saved["amount_lp"]
+            );
+        }
+        println!("Resuming the migration saved in {}", path.display());
+
+        for (mint, amount) in saved["holdings"].as_object().into_iter().flatten() {
+            migration.holdings.insert(
+                Pubkey::from_str(mint).context("Invalid mint in migration file")?,
+                amount
+                    .as_u64()
+                    .context("Invalid amount in migration file")?,
+            );
+        }
+        migration.removed = saved["removed"].as_bool().unwrap_or_default();
+        migration.balanced = saved["balanced"].as_bool().unwrap_or_default();
+        migration.added = saved["added"].as_bool().unwrap_or_default();
+        if !saved["pending"].is_null() {
+            migration.pending = Some(PendingStep::from_json(&saved["pending"])?);
+        }
+        migration.log = saved["log"].as_array().cloned().unwrap_or_default();
+
+        Ok(migration)
+    }
+
+    fn save(&self) -> Result<()> {
+        let holdings: serde_json::Map<_, _> = self
+            .holdings
+            .iter()
+            .map(|(mint, amount)| (mint.to_string(), serde_json::json!(amount)))
+            .collect();
+        let saved = serde_json::json!({
+            "amount_lp": self.amount_lp,
+            "from": self.token_mints_from.map(|mint| mint.to_string()),
+            "to": self.token_mints_to.map(|mint| mint.to_string()),
+            "holdings": holdings,
+            "removed": self.removed,
+            "balanced": self.balanced,
+            "added": self.added,
+            "pending": self.pending.as_ref().map(PendingStep::to_json),
+            "log": self.log,
+        });
+
+        fs::create_dir_all(MIGRATIONS_OUTPUT_DIR)?;
+        fs::write(self.path(), serde_json::to_string_pretty(&saved)?)?;
+        Ok(())
+    }
+
+    fn holding(&self, token_mint: &Pubkey) -> u64 {
+        self.holdings.get(token_mint).copied().unwrap_or_default()
+    }
+
+    /// Add `amount` to what the migration holds of its token
+    fn credit(&mut self, amount: TokenAmount) -> Result<()> {
+        let held = TokenAmount::new(
+            amount.mint(),
+            amount.decimals(),
+            self.holding(&amount.mint()),
+        );
+        self.holdings
+            .insert(amount.mint(), held.checked_add(amount)?.raw());
+        Ok(())
+    }
+
+    /// Take `amount` from what the migration holds of its token
+    fn debit(&mut self, amount: TokenAmount) -> Result<()> {
+        let held = TokenAmount::new(
+            amount.mint(),
+            amount.decimals(),
+            self.holding(&amount.mint()),
+        );
+        self.holdings
+            .insert(amount.mint(), held.checked_sub(amount)?.raw());
+        Ok(())
+    }
+
+    /// Save the step as pending with its transaction, then send it
+    fn send_step(
+        &mut self,
+        rpc_client: &RpcClient,
+        pending: PendingStep,
+        transaction: &VersionedTransaction,
+    ) -> Result<()> {
+        self.pending = Some(pending);
+        self.save()?;
+        send_and_confirm(rpc_client, transaction)?;
+        Ok(())
+    }
+
+    /// Record what a landed step moved, measured from the user's balances
+    fn complete_step(
+        &mut self,
+        rpc_client: &RpcClient,
+        user: &Pubkey,
+        token_infos: &HashMap<Pubkey, TokenInfo>,
+        pending: &PendingStep,
+    ) -> Result<()> {
+        let token_mints = pending.balances_before.map(|(mint, _)| mint);
+        let after = get_token_balances(rpc_client, user, &token_mints)?;
+        let moved = |i: usize| after[i].abs_diff(pending.balances_before[i].1);
+        let mut log = pending.step.to_json();
+        log["signature"] = serde_json::json!(pending.signature.to_string());
+
+        match pending.step {
+            MigrationStep::Remove => {
+                for (i, token_mint) in token_mints.iter().enumerate() {
+                    println!("  received {}", token_infos[token_mint].format(moved(i)));
+                    self.credit(token_infos[token_mint].amount(moved(i)))?;
+                }
+                self.removed = true;
+            }
+            MigrationStep::Swap {
+                amount_in, balance, ..
+            } => {
+                let (token_in, token_out) =
+                    (&token_infos[&token_mints[0]], &token_infos[&token_mints[1]]);
+                println!("  received {}", token_out.format(moved(1)));
+                self.debit(token_in.amount(amount_in))?;
+                self.credit(token_out.amount(moved(1)))?;
+                self.balanced |= balance;
+                log["amount_out"] = serde_json::json!(moved(1));
+            }
+            MigrationStep::Add { .. } => {
+                for (i, token_mint) in token_mints.iter().enumerate() {
+                    println!("  paid {}", token_infos[token_mint].format(moved(i)));
+                    self.debit(token_infos[token_mint].amount(moved(i)))?;
+                }
+                self.added = true;
+            }
+            MigrationStep::Done => bail!("A finished migration has no step to complete"),
+        }
+        self.log.push(log);
+        Ok(())
+    }
+
+    fn next_step(&self, rpc_client: &RpcClient) -> Result<MigrationStep> {
+        if !self.removed {
+            return Ok(MigrationStep::Remove);
+        }
+
+        // tokens of the old pair only, swapped into the new pair's token it doesn't share
+        // with the old one if there's a pool for it
+        let mut targets = self.token_mints_to;
+        targets.sort_by_key(|mint| self.token_mints_from.contains(mint));
+        for (token_mint, amount) in &self.holdings {
+            if *amount == 0 || self.token_mints_to.contains(token_mint) {
+                continue;
+            }
+            let pool_keys = targets.map(|target| get_pool_address(token_mint, &target));
+            let pools = rpc_client
+                .get_multiple_accounts(&pool_keys)
+                .context("Failed to get pool accounts")?;
+            return match targets.iter().zip(pools).find(|(_, pool)| pool.is_some()) {
+                Some((target, _)) => Ok(MigrationStep::Swap {
+                    token_mint_in: *token_mint,
+                    token_mint_out: *target,
+                    amount_in: *amount,
+                    balance: false,
+                }),
+                None => bail!(
+                    "No pool to swap {} into {} or {}",
+                    token_mint,
+                    targets[0],
+                    targets[1]
+                ),
+            };
+        }
+
+        if self.added {
+            return Ok(MigrationStep::Done);
+        }
+
+        let (pool, snapshot) = get_pool_snapshot(rpc_client, &self.pool_to)?;
+        let (reserve_x, reserve_y) = snapshot.available_reserves()?;
+        if reserve_x == 0 || reserve_y == 0 || pool.token_lp_supply == 0 {
+            bail!("The new pool has no liquidity to add to");
+        }
+        let (held_x, held_y) = (
+            self.holding(&pool.token_mint_x) as u128,
+            self.holding(&pool.token_mint_y) as u128,
+        );
+        let (reserve_x, reserve_y) = (reserve_x as u128, reserve_y as u128);
+
+        if !self.balanced {
+            // swapping half of what's held above the pool ratio buys about the other token
+            // that's short, fees and price impact aside
+            return Ok(if held_x * reserve_y > held_y * reserve_x {
+                MigrationStep::Swap {
+                    token_mint_in: pool.token_mint_x,
+                    token_mint_out: pool.token_mint_y,
+                    amount_in: ((held_x - held_y * reserve_x / reserve_y) / 2) as u64,
+                    balance: true,
+                }
+            } else {
+                MigrationStep::Swap {
+                    token_mint_in: pool.token_mint_y,
+                    token_mint_out: pool.token_mint_x,
+                    amount_in: ((held_y - held_x * reserve_y / reserve_x) / 2) as u64,
+                    balance: true,
+                }
+            });
+        }
+
+        let supply = pool.token_lp_supply as u128;
+        let amount_lp = (held_x * supply / reserve_x).min(held_y * supply / reserve_y);
+        let token_lp = get_token_infos(rpc_client, &[get_token_mint_lp(&self.pool_to)])?
+            .pop()
+            .context("Unexpected number of token mints returned")?;
+        let amount_lp = token_lp
+            .amount(u64::try_from(amount_lp).context("LP token amount overflows")?)
+            .less_bps(MIGRATION_SLIPPAGE_BPS)?;
+        Ok(MigrationStep::Add {
+            amount_lp: amount_lp.raw(),
+        })
+    }
+}
+
+/// Complete the pending step of a migration if its transaction landed, so it's never sent
+/// twice, otherwise leave the step to be sent again
+async fn resolve_pending_step(
+    migration: &mut Migration,
+    pending: &PendingStep,
+    sdk: &mut DarklakeSDK,
+    user_keypair: &Keypair,
+    rpc_client: &RpcClient,
+    token_infos: &HashMap<Pubkey, TokenInfo>,
+) -> Result<()> {
+    let status = rpc_client
+        .get_signature_status_with_commitment_and_history(
+            &pending.signature,
+            rpc_client.commitment(),
+            true,
+        )
+        .context("Failed to get the pending step's transaction status")?;
+    match status {
+        Some(Ok(())) => {
+            if let (
+                MigrationStep::Swap {
+                    token_mint_in,
+                    token_mint_out,
+                    amount_in,
+                    ..
+                },
+                Some((salt, min_out)),
+            ) = (pending.step, pending.swap)
+            {
+                let swap_params = SwapParamsIx {
+                    source_mint: token_mint_in,
+                    destination_mint: token_mint_out,
+                    token_transfer_authority: user_keypair.pubkey(),
+                    amount_in,
+                    swap_mode: SwapMode::ExactIn,
+                    min_out,
+                    salt,
+                };
+                finalize_swap_order(sdk, user_keypair, rpc_client, &swap_params).await?;
+            }
+            migration.complete_step(rpc_client, &user_keypair.pubkey(), token_infos, pending)
+        }
+        Some(Err(e)) => {
+            println!(
+                "Transaction {} of the pending step failed ({}), sending the step again",
+                pending.signature, e
+            );
+            Ok(())
+        }
+        None => {
+            // it can still land as long as its blockhash is valid
+            if rpc_client
+                .is_blockhash_valid(&pending.recent_blockhash, rpc_client.commitment())
+                .context("Failed to check the pending step's blockhash")?
+            {
+                bail!(
+                    "Transaction {} of the pending step may still land, run again in a minute",
+                    pending.signature
+                );
+            }
+            println!(
+                "Transaction {} of the pending step never landed, sending the step again",
+                pending.signature
+            );
+            Ok(())
+        }
+    }
+}
+
+async fn migrate_liquidity(
+    mut sdk: DarklakeSDK,
+    user_keypair: Keypair,
+    rpc_client: RpcClient,
+    amount_lp: u64,
+    token_mints_from: [Pubkey; 2],
+    token_mints_to: [Pubkey; 2],
+    dry_run: bool,
+) -> Result<()> {
+    println!("Darklake DEX SDK - Migrate Liquidity");
+    println!("=====================================");
+
+    let native = Pubkey::from_str(SOL_MINT).unwrap();
+    if token_mints_from.contains(&native) || token_mints_to.contains(&native) {
+        bail!("SOL pairs aren't supported, use remove_liquidity_sol and add_liquidity_sol");
+    }
+
+    let mut migration = Migration::load(amount_lp, token_mints_from, token_mints_to)?;
+    if dry_run {
+        println!("Dry run, quoting every step against the current reserves without sending");
+    }
+
+    let mut mints: Vec<Pubkey> = token_mints_from.into_iter().chain(token_mints_to).collect();
+    mints.sort();
+    mints.dedup();
+    let token_infos: HashMap<_, _> = mints
+        .iter()
+        .copied()
+        .zip(get_token_infos(&rpc_client, &mints)?)
+        .collect();
+    let user = user_keypair.pubkey();
+
+    loop {
+        if let Some(pending) = migration.pending.take() {
+            resolve_pending_step(
+                &mut migration,
+                &pending,
+                &mut sdk,
+                &user_keypair,
+                &rpc_client,
+                &token_infos,
+            )
+            .await?;
+            migration.save()?;
+            continue;
+        }
+
+        match migration.next_step(&rpc_client)? {
+            MigrationStep::Remove => {
+                println!(
+                    "Remove {} LP tokens from pool {}",
+                    amount_lp, migration.pool_from
+                );
+                if dry_run {
+                    let quote = quote_remove_liquidity(
+                        &rpc_client,
+                        &token_mints_from[0],
+                        &token_mints_from[1],
+                        amount_lp,
+                        0,
+                    )?;
+                    let token_mint_y = token_mints_from
+                        .into_iter()
+                        .find(|mint| *mint != quote.token_mint_x)
+                        .context("Old pair has the same token twice")?;
+                    for (token_mint, amount) in [
+                        (quote.token_mint_x, quote.expected_x),
+                        (token_mint_y, quote.expected_y),
+                    ] {
+                        println!("  received {}", token_infos[&token_mint].format(amount));
+                        migration.credit(token_infos[&token_mint].amount(amount))?;
+                    }
+                    migration.removed = true;
+                } else {
+                    let before = get_token_balances(&rpc_client, &user, &token_mints_from)?;
+
+                    sdk.load_pool(&token_mints_from[0], &token_mints_from[1])
+                        .await?;
+                    sdk.update_accounts().await?;
+                    let quote = quote_remove_liquidity(
+                        &rpc_client,
+                        &token_mints_from[0],
+                        &token_mints_from[1],
+                        amount_lp,
+                        REMOVE_LIQUIDITY_SLIPPAGE_BPS,
+                    )?;
+                    let remove_liquidity_ix = sdk
+                        .remove_liquidity_ix(&RemoveLiquidityParamsIx {
+                            user,
+                            amount_lp,
+                            min_amount_x: quote.min_amount_x,
+                            min_amount_y: quote.min_amount_y,
+                        })
+                        .await?;
+                    let transaction = sign_transaction(
+                        &rpc_client,
+                        &user_keypair,
+                        TxKind::RemoveLiquidity,
+                        vec![remove_liquidity_ix],
+                    )
+                    .await?;
+                    println!(
+                        "Remove liquidity transaction signature: {}",
+                        transaction.signatures[0]
+                    );
+                    migration.send_step(
+                        &rpc_client,
+                        PendingStep {
+                            step: MigrationStep::Remove,
+                            signature: transaction.signatures[0],
+                            recent_blockhash: *transaction.message.recent_blockhash(),
+                            balances_before: [0, 1].map(|i| (token_mints_from[i], before[i])),
+                            swap: None,
+                        },
+                        &transaction,
+                    )?;
+                }
+            }
+            step @ MigrationStep::Swap {
+                token_mint_in,
+                token_mint_out,
+                amount_in,
+                balance,
+            } => {
+                let (token_in, token_out) =
+                    (&token_infos[&token_mint_in], &token_infos[&token_mint_out]);
+                // only a balancing swap can be empty, when nothing is held above the ratio
+                if amount_in == 0 {
+                    println!("The held tokens already match the new pool's ratio");
+                    migration.balanced = true;
+                    if !dry_run {
+                        migration.save()?;
+                    }
+                    continue;
+                }
+                println!(
+                    "Swap {} to {}{}",
+                    token_in.format(amount_in),
+                    token_out.symbol,
+                    if balance {
+                        " to match the new pool's ratio"
+                    } else {
+                        ""
+                    }
+                );
+                if dry_run {
+                    let (pool, snapshot) = get_pool_snapshot(
+                        &rpc_client,
+                        &get_pool_address(&token_mint_in, &token_mint_out),
+                    )?;
+                    let amount_out =
+                        math::quote(&snapshot, amount_in, token_mint_in == pool.token_mint_x)?
+                            .to_amount;
+                    println!("  received {}", token_out.format(amount_out));
+                    migration.debit(token_in.amount(amount_in))?;
+                    migration.credit(token_out.amount(amount_out))?;
+                    migration.balanced |= balance;
+                } else {
+                    let token_mints = [token_mint_in, token_mint_out];
+                    let before = get_token_balances(&rpc_client, &user, &token_mints)?;
+
+                    sdk.load_pool(&token_mint_in, &token_mint_out).await?;
+                    sdk.update_accounts().await?;
+                    let swap_params = SwapRequest::builder()
+                        .from(token_mint_in)
+                        .to(token_mint_out)
+                        .amount(amount_in)
+                        .slippage_bps(MIGRATION_SLIPPAGE_BPS)
+                        .authority(user)
+                        .to_params(&mut sdk, &rpc_client)
+                        .await?;
+                    let swap_ix = sdk.swap_ix(&swap_params).await?;
+                    let transaction =
+                        sign_transaction(&rpc_client, &user_keypair, TxKind::Swap, vec![swap_ix])
+                            .await?;
+                    println!("Swap transaction signature: {}", transaction.signatures[0]);
+                    migration.send_step(
+                        &rpc_client,
+                        PendingStep {
+                            step,
+                            signature: transaction.signatures[0],
+                            recent_blockhash: *transaction.message.recent_blockhash(),
+                            balances_before: [0, 1].map(|i| (token_mints[i], before[i])),
+                            swap: Some((swap_params.salt, swap_params.min_out)),
+                        },
+                        &transaction,
+                    )?;
+                }
+            }
+            step @ MigrationStep::Add { amount_lp } => {
+                println!(
+                    "Add liquidity for {} LP tokens to pool {}",
+                    amount_lp, migration.pool_to
+                );
+                if amount_lp == 0 {
+                    bail!("The migrated tokens are too few to mint an LP token of the new pool");
+                }
+                let (pool, snapshot) = get_pool_snapshot(&rpc_client, &migration.pool_to)?;
+                let token_mints = [pool.token_mint_x, pool.token_mint_y];
+                if dry_run {
+                    let (reserve_x, reserve_y) = snapshot.available_reserves()?;
+                    let needed = |reserve: u64| {
+                        (amount_lp as u128 * reserve as u128).div_ceil(pool.token_lp_supply as u128)
+                            as u64
+                    };
+                    for (token_mint, amount) in token_mints
+                        .iter()
+                        .zip([needed(reserve_x), needed(reserve_y)])
+                    {
+                        println!("  paid {}", token_infos[token_mint].format(amount));
+                        migration.debit(token_infos[token_mint].amount(amount))?;
+                    }
+                    migration.added = true;
+                } else {
+                    let before = get_token_balances(&rpc_client, &user, &token_mints)?;
+
+                    sdk.load_pool(&token_mints[0], &token_mints[1]).await?;
+                    sdk.update_accounts().await?;
+                    let add_liquidity_ix = sdk
+                        .add_liquidity_ix(&AddLiquidityParamsIx {
+                            user,
+                            amount_lp,
+                            max_amount_x: migration.holding(&token_mints[0]),
+                            max_amount_y: migration.holding(&token_mints[1]),
+                        })
+                        .await?;
+                    let transaction = sign_transaction(
+                        &rpc_client,
+                        &user_keypair,
+                        TxKind::AddLiquidity,
+                        vec![add_liquidity_ix],
+                    )
+                    .await?;
+                    println!(
+                        "Add liquidity transaction signature: {}",
+                        transaction.signatures[0]
+                    );
+                    migration.send_step(
+                        &rpc_client,
+                        PendingStep {
+                            step,
+                            signature: transaction.signatures[0],
+                            recent_blockhash: *transaction.message.recent_blockhash(),
+                            balances_before: [0, 1].map(|i| (token_mints[i], before[i])),
+                            swap: None,
+                        },
+                        &transaction,
+                    )?;
+                }
+            }
+            MigrationStep::Done => break,
+        }
+
+        if !dry_run {
+            migration.save()?;
+        }
+    }
+
+    for (token_mint, amount) in &migration.holdings {
+        if *amount > 0 {
+            println!(
+                "Left over in the wallet: {}",
+                token_infos[token_mint].format(*amount)
+            );
+        }
+    }
+    if dry_run {
+        println!("Dry run done, nothing was sent");
+    } else {
+        println!(
+            "Migration done, its steps are recorded in {}",
+            migration.path().display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Account of the watched wallet and how its changes are reported
+enum WatchedAccount {
+    Order,
+    Token(&'static str, TokenInfo),
+}
+
+fn watch_wallet(
+    rpc_client: RpcClient,
+    owner: &Pubkey,
+    token_mint_a: &Pubkey,
+    token_mint_b: &Pubkey,
+) -> Result<()> {
+    println!("Darklake DEX SDK - Watch Wallet");
+    println!("================================");
+
+    let addresses = derive_order_addresses(owner, token_mint_a, token_mint_b);
+    let [token_x, token_y, mut token_lp] = <[_; 3]>::try_from(get_token_infos(
+        &rpc_client,
+        &[
+            addresses.token_mint_x,
+            addresses.token_mint_y,
+            addresses.token_mint_lp,
+        ],
+    )?)
+    .map_err(|_| anyhow!("Unexpected number of token infos"))?;
+    token_lp.symbol = "LP".to_string();
+    let [token_program_x, token_program_y, token_program_lp] =
+        <[_; 3]>::try_from(resolve_token_programs(
+            &rpc_client,
+            &[
+                addresses.token_mint_x,
+                addresses.token_mint_y,
+                addresses.token_mint_lp,
+            ],
+        )?)
+        .map_err(|_| anyhow!("Unexpected number of token mints returned"))?;
+
+    let mut watched = vec![
+        (addresses.order, WatchedAccount::Order),
+        (
+            get_associated_token_address_with_program_id(
+                owner,
+                &addresses.token_mint_x,
+                &token_program_x,
+            ),
+            WatchedAccount::Token("token X", token_x.clone()),
+        ),
+        (
+            get_associated_token_address_with_program_id(
+                owner,
+                &addresses.token_mint_y,
+                &token_program_y,
+            ),
+            WatchedAccount::Token("token Y", token_y.clone()),
+        ),
+        (
+            get_associated_token_address_with_program_id(
+                owner,
+                &addresses.token_mint_lp,
+                &token_program_lp,
+            ),
+            WatchedAccount::Token("LP", token_lp),
+        ),
+    ];
+    if let Some(order_wsol) = addresses.order_wsol {
+        let sol = if addresses.token_mint_x == native_mint::ID {
+            token_x.clone()
+        } else {
+            token_y.clone()
+        };
+        watched.push((order_wsol, WatchedAccount::Token("order WSOL", sol)));
+    }
+
+    let keys: Vec<Pubkey> = watched.iter().map(|(address, _)| *address).collect();
+    // subscribe before the initial read so no change falls in between
+    let (_subscriptions, updates) = subscribe_accounts(&rpc_client, &keys)?;
+    let mut last_accounts = rpc_client
+        .get_multiple_accounts(&keys)
+        .context("Failed to get wallet accounts")?;
+
+    // with --value-in, balances are followed by their value
+    let value_of = |info: &TokenInfo, amount: u64| -> Result<String> {
+        Ok(match value_mint() {
+            Some(value_mint) => format!(
+                " {}",
+                value_amounts(&rpc_client, &[(info.mint, amount)], &value_mint)?.format(0)
+            ),
+            None => String::new(),
+        })
+    };
+
+    println!("Watching {} on pool {}", owner, addresses.pool);
+    for ((address, account), previous) in watched.iter().zip(&last_accounts) {
+        match (account, previous) {
+            (WatchedAccount::Order, None) => println!("Order:       none open ({})", address),
+            (WatchedAccount::Order, Some(_)) => println!("Order:       open ({})", address),
+            (WatchedAccount::Token(label, _), None) => println!("{:<12} no account", label),
+            (WatchedAccount::Token(label, info), Some(previous)) => {
+                let amount = unpack_token_account(&previous.data)?.amount;
+                println!(
+                    "{:<12} {}{}",
+                    label,
+                    info.format(amount),
+                    value_of(info, amount)?
+                )
+            }
+        }
+    }
+
+    for (slot, index, account) in updates {
+        let (_, watched_account) = &watched[index];
+        let previous = &last_accounts[index];
+        match watched_account {
+            WatchedAccount::Order => match (previous, &account) {
+                (None, Some(order)) => {
+                    let order = parse_order_summary(&order.data)?;
+                    let (token_in, token_out) = if order.is_x_to_y {
+                        (&token_x, &token_y)
+                    } else {
+                        (&token_y, &token_x)
+                    };
+                    println!(
+                        "[slot {}] Order opened: {} -> {}, deadline slot {}",
+                        slot,
+                        token_in.format(order.actual_in),
+                        token_out.format(order.actual_out),
+                        order.deadline
+                    );
+                }
+                (Some(_), None) => println!(
+                    "[slot {}] Order closed (finalized, cancelled or slashed)",
+                    slot
+                ),
+                (Some(previous), Some(order)) if previous.data != order.data => {
+                    println!("[slot {}] Order updated", slot)
+                }
+                _ => {}
+            },
+            WatchedAccount::Token(label, info) => {
+                let amount_of = |account: &Option<Account>| -> Result<u64> {
+                    match account {
+                        Some(account) => Ok(unpack_token_account(&account.data)?.amount),
+                        None => Ok(0),
+                    }
+                };
+                let (before, after) = (amount_of(previous)?, amount_of(&account)?);
+                if before != after {
+                    println!(
+                        "[slot {}] {}: {} -> {} ({}){}",
+                        slot,
+                        label,
+                        info.format(before),
+                        info.format(after),
+                        info.format_change(before, after),
+                        value_of(info, after)?
+                    );
+                }
+            }
+        }
+        last_accounts[index] = account;
+    }
+
+    bail!("Account subscriptions closed")
+}
+
+/// Point at the leftover mints after a failed pool init and how to resume or clean them up
+fn print_init_pool_recovery(function: &str, mints: &[Pubkey]) {
+    let mints = mints
+        .iter()
+        .map(Pubkey::to_string)
+        .collect::<Vec<_>>()
+        .join(" ");
+    println!("Pool initialization failed, the token mints were kept:");
+    println!("  resume:   cargo run -- {} {}", function, mints);
+    println!("  clean up: cargo run -- cleanup_mints {}", mints);
+}
+
+async fn cleanup_mints(
+    user_keypair: Keypair,
+    rpc_client: RpcClient,
+    mints: &[Pubkey],
+) -> Result<()> {
+    println!("Darklake DEX SDK - Cleanup Mints");
+    println!("=================================");
+
+    let reclaimed = close_token_accounts(&rpc_client, &user_keypair, mints).await?;
+    println!(
+        "Closed token accounts, reclaimed {} SOL (mints themselves can't be closed)",
+        lamports_to_sol(reclaimed)
+    );
+
+    Ok(())
+}
+
+/// Initialize the pool of a pair with its initial liquidity in base units, `None` if the
+/// pool already exists
+async fn init_pool_if_missing(
+    sdk: &mut DarklakeSDK,
+    user_keypair: &Keypair,
+    rpc_client: &RpcClient,
+    (token_mint_x, amount_x): (Pubkey, u64),
+    (token_mint_y, amount_y): (Pubkey, u64),
+) -> Result<Option<Signature>> {
+    let pool_key = get_pool_address(&to_pool_mint(&token_mint_x), &to_pool_mint(&token_mint_y));
+    if rpc_client
+        .get_account_with_commitment(&pool_key, rpc_client.commitment())
+        .context("Failed to get pool account")?
+        .value
+        .is_some()
+    {
+        return Ok(None);
+    }
+
+    let initialize_pool_tx = sdk
+        .initialize_pool_tx(
+            &token_mint_x,
+            &token_mint_y,
+            amount_x,
+            amount_y,
+            &user_keypair.pubkey(),
+        )
+        .await?;
+    let tx = VersionedTransaction::try_new(initialize_pool_tx.message, &[user_keypair])?;
+    send_and_confirm(rpc_client, &tx).map(Some)
+}
+
+/// A pool to initialize with its initial liquidity in base units, from a row of init_pools
+struct PoolRow {
+    line: usize,
+    token_mint_x: Pubkey,
+    token_mint_y: Pubkey,
+    amount_x: u64,
+    amount_y: u64,
+}
+
+/// Rows of `mint_x,mint_y,amount_x,amount_y`, skipping blank lines, `#` comments and a
+/// header row
+fn parse_pool_rows(contents: &str) -> Result<Vec<PoolRow>> {
+    let mut rows = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || (index == 0 && line.starts_with("mint")) {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let [mint_x, mint_y, amount_x, amount_y] = fields[..] else {
+            bail!(
+                "Line {}: expected mint_x,mint_y,amount_x,amount_y, got {} fields",
+                line_number,
+                fields.len()
+            );
+        };
+        rows.push(PoolRow {
+            line: line_number,
+            token_mint_x: Pubkey::from_str(mint_x)
+                .with_context(|| format!("Line {}: invalid mint_x", line_number))?,
+            token_mint_y: Pubkey::from_str(mint_y)
+                .with_context(|| format!("Line {}: invalid mint_y", line_number))?,
+            amount_x: amount_x
+                .parse()
+                .with_context(|| format!("Line {}: invalid amount_x", line_number))?,
+            amount_y: amount_y
+                .parse()
+                .with_context(|| format!("Line {}: invalid amount_y", line_number))?,
+        });
+    }
+    Ok(rows)
+}
+
+/// Initialize the pool of each row of a CSV file in order, skipping pools that exist
+///
+/// Every row is parsed before the first pool is sent. A failed row is reported and the
+/// next one is tried, so a rerun picks up the failed ones as the others now exist.
+async fn init_pools(
+    mut sdk: DarklakeSDK,
+    user_keypair: Keypair,
+    rpc_client: RpcClient,
+    path: &Path,
+) -> Result<()> {
+    println!("Darklake DEX SDK - Init Pools");
+    println!("==============================");
+
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let rows = parse_pool_rows(&contents)?;
+    println!("{} pools in {}", rows.len(), path.display());
+
+    let (mut created, mut skipped, mut failed) = (0, 0, Vec::new());
+    for (index, row) in rows.iter().enumerate() {
+        let pool_key = get_pool_address(
+            &to_pool_mint(&row.token_mint_x),
+            &to_pool_mint(&row.token_mint_y),
+        );
+        let progress = format!(
+            "[{}/{}] {} / {}",
+            index + 1,
+            rows.len(),
+            row.token_mint_x,
+            row.token_mint_y
+        );
+
+        match init_pool_if_missing(
+            &mut sdk,
+            &user_keypair,
+            &rpc_client,
+            (row.token_mint_x, row.amount_x),
+            (row.token_mint_y, row.amount_y),
+        )
+        .await
+        {
+            Ok(Some(signature)) => {
+                created += 1;
+                println!("{}: created pool {} ({})", progress, pool_key, signature);
+            }
+            Ok(None) => {
+                skipped += 1;
+                println!("{}: pool {} exists, skipping", progress, pool_key);
+            }
+            Err(e) => {
+                println!("{}: failed ({:#})", progress, e);
+                failed.push(row.line);
+            }
+        }
+    }
+
+    println!(
+        "Created {}, skipped {} existing, {} failed",
+        created,
+        skipped,
+        failed.len()
+    );
+    if !failed.is_empty() {
+        bail!(
+            "{} pools failed to initialize (lines {:?}), rerun to retry them",
+            failed.len(),
+            failed
+        );
+    }
+    Ok(())
+}
+
+/// Lamports the SOL flows keep in the wallet after wrapping, `--fee-buffer` or the default
+fn fee_buffer() -> u64 {
+    FEE_BUFFER
+        .get()
+        .copied()
+        .unwrap_or(DEFAULT_FEE_BUFFER_LAMPORTS)
+}
+
+/// Token X of the example flows: the pair recorded by demo_setup, else the shared devnet one
+fn default_mint_x() -> Pubkey {
+    DEMO_MINTS.get().map_or_else(
+        || Pubkey::from_str(DEVNET_TOKEN_MINT_X).unwrap(),
+        |(x, _)| *x,
+    )
+}
+
+/// Token Y of the example flows, see `default_mint_x`
+fn default_mint_y() -> Pubkey {
+    DEMO_MINTS.get().map_or_else(
+        || Pubkey::from_str(DEVNET_TOKEN_MINT_Y).unwrap(),
+        |(_, y)| *y,
+    )
+}
+
+/// Pair recorded in `DEMO_CONFIG_PATH`, `None` before the first demo_setup
+fn read_demo_config() -> Result<Option<(Pubkey, Pubkey)>> {
+    let path = Path::new(DEMO_CONFIG_PATH);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let json = read_json(path.to_path_buf())?;
+    let mint = |field: &str| {
+        json[field]
+            .as_str()
+            .and_then(|mint| Pubkey::from_str(mint).ok())
+            .with_context(|| format!("Missing or invalid {} in {}", field, path.display()))
+    };
+    Ok(Some((mint("token_mint_x")?, mint("token_mint_y")?)))
+}
+
+fn write_demo_config(token_mint_x: &Pubkey, token_mint_y: &Pubkey) -> Result<()> {
+    let json = serde_json::json!({
+        "token_mint_x": token_mint_x.to_string(),
+        "token_mint_y": token_mint_y.to_string(),
+    });
+    fs::write(DEMO_CONFIG_PATH, serde_json::to_string_pretty(&json)?)
+        .with_context(|| format!("Failed to write {}", DEMO_CONFIG_PATH))
+}
+
+/// Create a token pair owned by the user with its X / Y and X / SOL pools, and record the
+/// pair so the other commands use it instead of the shared devnet one
+///
+/// Each step is skipped when already done: recorded mints that still exist are reused and
+/// existing pools are kept. The mints are recorded as soon as they're created, so a run
+/// that fails on a pool resumes with the same mints.
+async fn demo_setup(
+    mut sdk: DarklakeSDK,
+    user_keypair: Keypair,
+    rpc_client: RpcClient,
+    mint_seed: Option<&str>,
+) -> Result<()> {
+    println!("Darklake DEX SDK - Demo Setup");
+    println!("==============================");
+
+    let recorded = match read_demo_config()? {
+        Some((token_mint_x, token_mint_y)) => {
+            let exists = rpc_client
+                .get_multiple_accounts(&[token_mint_x, token_mint_y])
+                .context("Failed to get demo mints")?
+                .iter()
+                .all(Option::is_some);
+            if !exists {
+                println!(
+                    "Mints recorded in {} no longer exist, creating new ones",
+                    DEMO_CONFIG_PATH
+                );
+            }
+            exists.then_some((token_mint_x, token_mint_y))
+        }
+        None => None,
+    };
+
+    let (token_mint_x, token_mint_y) = match recorded {
+        Some(mints) => {
+            println!("Reusing the demo mints recorded in {}", DEMO_CONFIG_PATH);
+            mints
+        }
+        None => {
+            let (token_mint_a, token_mint_b) = create_new_tokens(
+                &rpc_client,
+                &user_keypair,
+                mint_seed,
+                "demo-",
+                DEMO_MINT_AMOUNT,
+            )
+            .await?;
+            // ordered like the pool's mints
+            let mints = if token_mint_a < token_mint_b {
+                (token_mint_a, token_mint_b)
+            } else {
+                (token_mint_b, token_mint_a)
+            };
+            write_demo_config(&mints.0, &mints.1)?;
+            println!("Recorded the demo mints in {}", DEMO_CONFIG_PATH);
+            mints
+        }
+    };
+
+    println!("Token X Mint: {}", token_mint_x);
+    println!("Token Y Mint: {}", token_mint_y);
+
+    let sol_mint = Pubkey::from_str(SOL_MINT).unwrap();
+    for (token_mint_b, amount_b) in [
+        (token_mint_y, DEMO_POOL_AMOUNT),
+        (sol_mint, DEMO_POOL_SOL_AMOUNT),
+    ] {
+        let pool_key = get_pool_address(&token_mint_x, &to_pool_mint(&token_mint_b));
+        match init_pool_if_missing(
+            &mut sdk,
+            &user_keypair,
+            &rpc_client,
+            (token_mint_x, DEMO_POOL_AMOUNT),
+            (token_mint_b, amount_b),
+        )
+        .await
+        .with_context(|| format!("Failed to initialize pool {}, rerun to retry", pool_key))?
+        {
+            Some(signature) => println!("Created pool {} ({})", pool_key, signature),
+            None => println!("Pool {} exists, skipping", pool_key),
+        }
+    }
+
+    println!(
+        "Demo setup done, the other commands now default to this pair. Delete {} to go back to the shared devnet pair",
+        DEMO_CONFIG_PATH
+    );
+    Ok(())
+}
+
+/// SOL, SPL Token and Token-2022 balances of a wallet, SOL under the WSOL mint
+fn get_wallet_holdings(rpc_client: &RpcClient, wallet: &Pubkey) -> Result<Vec<(Pubkey, u64)>> {
+    let mut holdings = vec![(native_mint::ID, rpc_client.get_balance(wallet)?)];
+
+    let mut token_accounts = Vec::new();
+    for token_program in [spl_token::ID, spl_token_2022::ID] {
+        token_accounts.extend(
+            rpc_client
+                .get_token_accounts_by_owner(wallet, TokenAccountsFilter::ProgramId(token_program))
+                .context("Failed to get token accounts")?,
+        );
+    }
+    for token_account in token_accounts {
+        // the RPC returns token accounts as parsed JSON
+        let data = serde_json::to_value(&token_account.account.data)?;
+        let info = &data["parsed"]["info"];
+        let mint = info["mint"]
+            .as_str()
+            .context("Token account without mint")?;
+        let amount = info["tokenAmount"]["amount"]
+            .as_str()
+            .context("Token account without amount")?;
+        holdings.push((Pubkey::from_str(mint)?, amount.parse()?));
+    }
+
+    holdings.retain(|(_, amount)| *amount > 0);
+    Ok(holdings)
+}
+
+fn treasury_report(rpc_client: RpcClient, wallets: &[Pubkey], quote_mint: &Pubkey) -> Result<()> {
+    println!("Darklake DEX SDK - Treasury Report");
+    println!("===================================");
+
+    let quote_token = get_token_infos(&rpc_client, &[*quote_mint])?.remove(0);
+    let mut total_value = 0u64;
+    let mut entries = Vec::new();
+
+    for wallet in wallets {
+        println!("\nWallet {}", wallet);
+        let holdings = get_wallet_holdings(&rpc_client, wallet)?;
+        let mints: Vec<Pubkey> = holdings.iter().map(|(mint, _)| *mint).collect();
+        let tokens = get_token_infos(&rpc_client, &mints)?;
+        // valued at what swapping the whole position would return, price impact included
+        let valuations = value_amounts(&rpc_client, &holdings, quote_mint)?;
+        total_value = total_value
+            .checked_add(valuations.total()?)
+            .context("Total value overflows")?;
+
+        for (index, ((mint, amount), token)) in holdings.iter().zip(&tokens).enumerate() {
+            println!(
+                "  {:<30} {}",
+                token.format(*amount),
+                valuations.format(index)
+            );
+            entries.push(serde_json::json!({
+                "wallet": wallet.to_string(),
+                "mint": mint.to_string(),
+                "amount": amount,
+                "decimals": token.decimals,
+                "value": valuations.values[index],
+                "value_slot": valuations.slot,
+                "value_stale": valuations.stale,
+            }));
+        }
+    }
+
+    println!("\nTotal: {}", quote_token.format(total_value));
+
+    // one file per run, so the history can be charted
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let snapshot = serde_json::json!({
+        "timestamp": timestamp,
+        "quote_mint": quote_mint.to_string(),
+        "quote_decimals": quote_token.decimals,
+        "total_value": total_value,
+        "holdings": entries,
+    });
+    fs::create_dir_all(TREASURY_OUTPUT_DIR)?;
+    let path = Path::new(TREASURY_OUTPUT_DIR).join(format!("{}.json", timestamp));
+    fs::write(&path, serde_json::to_string_pretty(&snapshot)?)?;
+    println!("Wrote {}", path.display());
+
+    Ok(())
+}
+
+/// Rank the endpoints fan-out sent to by how often each confirmed a transaction first
+fn endpoint_ranking() -> Result<()> {
+    println!("Darklake DEX SDK - Endpoint Ranking");
+    println!("====================================");
+
+    let ranking = rank_endpoints(read_endpoint_metrics()?);
+    if ranking.is_empty() {
+        println!(
+            "No fan-out recorded in {} yet, send with --submit-via fan-out --record-stats first",
+            ENDPOINT_METRICS_PATH
+        );
+        return Ok(());
+    }
+
+    println!(
+        "{:<4} {:<48} {:>6} {:>9} {:>11} {:>12}",
+        "Rank", "Endpoint", "Sent", "Accepted", "First conf.", "Avg confirm"
+    );
+    for (rank, (endpoint, metrics)) in ranking.iter().enumerate() {
+        println!(
+            "{:<4} {:<48} {:>6} {:>8.0}% {:>10.0}% {:>12}",
+            rank + 1,
+            endpoint,
+            metrics.sent,
+            metrics.accept_rate() * 100.0,
+            metrics.win_rate() * 100.0,
+            metrics
+                .average_confirm_ms()
+                .map_or_else(|| "-".to_string(), |ms| format!("{} ms", ms))
+        );
+    }
+    Ok(())
+}
+
+/// Spend of a group of transactions in lamports
+#[derive(Debug, Default)]
+struct CostTotals {
+    transactions: u64,
+    failed: u64,
+    network_fee: u64,
+    priority_fee: u64,
+    rent: u64,
+    tips: u64,
+}
+
+impl CostTotals {
+    fn add(&mut self, cost: &TransactionCost, tip: u64) {
+        self.transactions += 1;
+        self.failed += u64::from(cost.failed);
+        self.network_fee += cost.network_fee;
+        self.priority_fee += cost.priority_fee;
+        self.rent += cost.rent;
+        self.tips += tip;
+    }
+
+    fn print_row(&self, label: &str) {
+        println!(
+            "{:<46} {:>6} {:>6} {:>14} {:>14} {:>14} {:>14} {:>14}",
+            label,
+            self.transactions,
+            self.failed,
+            lamports_to_sol(self.network_fee),
+            lamports_to_sol(self.priority_fee),
+            lamports_to_sol(self.rent),
+            lamports_to_sol(self.tips),
+            lamports_to_sol(self.network_fee + self.priority_fee + self.rent + self.tips)
+        );
+    }
+}
+
+/// UTC date of a unix timestamp as `YYYY-MM-DD`
+fn utc_date(timestamp: u64) -> String {
+    // civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let z = (timestamp / 86_400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Sum up the fees, rent and tips of every transaction recorded in the costs ledger by
+/// operation, pool and day
+///
+/// The costs are read from each transaction's metadata, so transactions that never landed
+/// are only counted. A Jito tip is counted with the transaction it was paid for.
+fn costs_report(rpc_client: RpcClient) -> Result<()> {
+    println!("Darklake DEX SDK - Costs Report");
+    println!("================================");
+
+    let transactions = read_costs_ledger()?;
+    if transactions.is_empty() {
+        println!(
+            "No transactions recorded in {} yet, send with --record-stats first",
+            COSTS_LEDGER_PATH
+        );
+        return Ok(());
+    }
+    println!(
+        "{} transactions recorded in {}",
+        transactions.len(),
+        COSTS_LEDGER_PATH
+    );
+
+    let mut by_operation: BTreeMap<String, CostTotals> = BTreeMap::new();
+    let mut by_pool: BTreeMap<String, CostTotals> = BTreeMap::new();
+    let mut by_day: BTreeMap<String, CostTotals> = BTreeMap::new();
+    let mut total = CostTotals::default();
+    let mut not_landed = 0;
+
+    for transaction in &transactions {
+        let cost = match get_transaction_cost(&rpc_client, &transaction.signature) {
+            Ok(Some(cost)) => cost,
+            Ok(None) => {
+                not_landed += 1;
+                continue;
+            }
+            Err(e) => {
+                println!("Skipping {}: {:#}", transaction.signature, e);
+                continue;
+            }
+        };
+
+        // transactions without a Darklake instruction are named after the command
+        let operation = cost
+            .instruction
+            .clone()
+            .unwrap_or_else(|| transaction.command.clone());
+        let pool = cost
+            .pool
+            .map_or_else(|| "none".to_string(), |pool| pool.to_string());
+        let tip = transaction.tip;
+        by_operation.entry(operation).or_default().add(&cost, tip);
+        by_pool.entry(pool).or_default().add(&cost, tip);
+        by_day
+            .entry(utc_date(transaction.timestamp))
+            .or_default()
+            .add(&cost, tip);
+        total.add(&cost, tip);
+    }
+
+    for (title, groups) in [
+        ("Operation", &by_operation),
+        ("Pool", &by_pool),
+        ("Day (UTC)", &by_day),
+    ] {
+        println!(
+            "\n{:<46} {:>6} {:>6} {:>14} {:>14} {:>14} {:>14} {:>14}",
+            title, "Txs", "Failed", "Network fee", "Priority fee", "Rent", "Tips", "Total SOL"
+        );
+        for (label, totals) in groups {
+            totals.print_row(label);
+        }
+    }
+    println!();
+    total.print_row("Total");
+
+    if not_landed > 0 {
+        println!(
+            "{} recorded transactions never landed and cost nothing",
+            not_landed
+        );
+    }
+    Ok(())
+}
+
+async fn fork_pool(
+    rpc_client: RpcClient,
+    token_mint_a: &Pubkey,
+    token_mint_b: &Pubkey,
+) -> Result<()> {
+    println!("Darklake DEX SDK - Fork Pool");
+    println!("=============================");
+
+    let pool_key = get_pool_address(&to_pool_mint(token_mint_a), &to_pool_mint(token_mint_b));
+    println!("Pool: {}", pool_key);
+
+    let pool_account = rpc_client
+        .get_account(&pool_key)
+        .context("Pool account not found")?;
+    let pool = parse_pool_state(&pool_account.data)?;
+
+    let addresses = [
+        pool.amm_config,
+        pool.token_mint_x,
+        pool.token_mint_y,
+        pool.reserve_x,
+        pool.reserve_y,
+        get_token_mint_lp(&pool_key),
+    ];
+    let accounts = rpc_client.get_multiple_accounts(&addresses)?;
+
+    let dir = Path::new(FORK_OUTPUT_DIR).join(pool_key.to_string());
+    fs::create_dir_all(&dir)?;
+
+    let mut validator_args = String::new();
+
+    let path = write_account_json(&dir, &pool_key, &pool_account)?;
+    println!("Wrote {}", path.display());
+    validator_args.push_str(&format!(" --account {} {}", pool_key, path.display()));
+
+    for (address, account) in addresses.iter().zip(accounts) {
+        let account = account.with_context(|| format!("Account {} not found", address))?;
+        let path = write_account_json(&dir, address, &account)?;
+        println!("Wrote {}", path.display());
+        validator_args.push_str(&format!(" --account {} {}", address, path.display()));
+    }
+
+    println!("Start a localnet mirroring the pool with:");
+    println!(
+        "  solana-test-validator --url {} --clone-upgradeable-program {}{}",
+        rpc_client.url(),
+        pool_account.owner,
+        validator_args
+    );
+
+    Ok(())
+}
+
+/// Instruction as JSON, accounts with their signer / writable flags and data in base64
+fn instruction_json(instruction: &Instruction) -> serde_json::Value {
+    serde_json::json!({
+        "program_id": instruction.program_id.to_string(),
+        "accounts": instruction
+            .accounts
+            .iter()
+            .map(|meta| serde_json::json!({
+                "pubkey": meta.pubkey.to_string(),
+                "is_signer": meta.is_signer,
+                "is_writable": meta.is_writable,
+            }))
+            .collect::<Vec<_>>(),
+        "data": STANDARD.encode(&instruction.data),
+    })
+}
+
+/// The instructions `generate_fixtures` records, built with the SDK version in use
+async fn build_fixture_instructions(
+    sdk: &mut DarklakeSDK,
+    token_mint_x: &Pubkey,
+    token_mint_y: &Pubkey,
+) -> Result<Vec<(&'static str, Instruction)>> {
+    sdk.load_pool(token_mint_x, token_mint_y).await?;
+    sdk.update_accounts().await?;
+
+    let swap_params = |source_mint: Pubkey, destination_mint: Pubkey| SwapParamsIx {
+        source_mint,
+        destination_mint,
+        token_transfer_authority: FIXTURE_OWNER,
+        amount_in: 1_000,
+        swap_mode: SwapMode::ExactIn,
+        min_out: 1,
+        salt: FIXTURE_SALT,
+    };
+
+    Ok(vec![
+        (
+            "swap_x_to_y",
+            sdk.swap_ix(&swap_params(*token_mint_x, *token_mint_y))
+                .await?,
+        ),
+        (
+            "swap_y_to_x",
+            sdk.swap_ix(&swap_params(*token_mint_y, *token_mint_x))
+                .await?,
+        ),
+        (
+            "add_liquidity",
+            sdk.add_liquidity_ix(&AddLiquidityParamsIx {
+                user: FIXTURE_OWNER,
+                amount_lp: 20,
+                max_amount_x: 1_000,
+                max_amount_y: 1_000,
+            })
+            .await?,
+        ),
+        (
+            "remove_liquidity",
+            sdk.remove_liquidity_ix(&RemoveLiquidityParamsIx {
+                user: FIXTURE_OWNER,
+                amount_lp: 20,
+                min_amount_x: 1,
+                min_amount_y: 1,
+            })
+            .await?,
+        ),
+    ])
+}
+
+async fn generate_fixtures(
+    mut sdk: DarklakeSDK,
+    rpc_client: RpcClient,
+    token_mint_a: &Pubkey,
+    token_mint_b: &Pubkey,
+) -> Result<()> {
+    println!("Darklake DEX SDK - Generate Fixtures");
+    println!("=====================================");
+
+    let pool_key = get_pool_address(&to_pool_mint(token_mint_a), &to_pool_mint(token_mint_b));
+    println!("Pool: {}", pool_key);
+
+    let dir = Path::new(FIXTURES_OUTPUT_DIR).join(pool_key.to_string());
+    let write_json = |name: &str, json: &serde_json::Value| -> Result<()> {
+        let path = dir.join(name);
+        fs::write(&path, serde_json::to_string_pretty(json)?)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        println!("Wrote {}", path.display());
+        Ok(())
+    };
+
+    // the accounts the snapshot and the instructions were built from
+    let (pool, snapshot) = get_pool_snapshot(&rpc_client, &pool_key)?;
+    let addresses = [
+        pool_key,
+        pool.amm_config,
+        pool.token_mint_x,
+        pool.token_mint_y,
+        pool.reserve_x,
+        pool.reserve_y,
+        get_token_mint_lp(&pool_key),
+    ];
+    let accounts_dir = dir.join("accounts");
+    fs::create_dir_all(&accounts_dir)?;
+    for (address, account) in addresses
+        .iter()
+        .zip(rpc_client.get_multiple_accounts(&addresses)?)
+    {
+        let account = account.with_context(|| format!("Account {} not found", address))?;
+        let path = write_account_json(&accounts_dir, address, &account)?;
+        println!("Wrote {}", path.display());
+    }
+
+    write_json(
+        "snapshot.json",
+        &serde_json::json!({
+            "pool": pool_key.to_string(),
+            "token_mint_x": pool.token_mint_x.to_string(),
+            "token_mint_y": pool.token_mint_y.to_string(),
+            "reserve_x_balance": snapshot.reserve_x_balance,
+            "reserve_y_balance": snapshot.reserve_y_balance,
+            "protocol_fee_x": snapshot.protocol_fee_x,
+            "protocol_fee_y": snapshot.protocol_fee_y,
+            "locked_x": snapshot.locked_x,
+            "locked_y": snapshot.locked_y,
+            "user_locked_x": snapshot.user_locked_x,
+            "user_locked_y": snapshot.user_locked_y,
+            "trade_fee_rate": snapshot.trade_fee_rate,
+            "protocol_fee_rate": snapshot.protocol_fee_rate,
+            "ratio_change_tolerance_rate": snapshot.ratio_change_tolerance_rate,
+        }),
+    )?;
+
+    // expected quotes of the snapshot, failures included since integrations have to match those too
+    let mut quotes = Vec::new();
+    for is_swap_x_to_y in [true, false] {
+        for amount_in in FIXTURE_QUOTE_AMOUNTS {
+            let expected = match math::quote(&snapshot, amount_in, is_swap_x_to_y) {
+                Ok(quote) => serde_json::json!({
+                    "from_amount": quote.from_amount,
+                    "to_amount": quote.to_amount,
+                    "trade_fee": quote.trade_fee,
+                    "protocol_fee": quote.protocol_fee,
+                    "from_to_lock": quote.from_to_lock,
+                }),
+                Err(e) => serde_json::json!({ "error": e.to_string() }),
+            };
+            quotes.push(serde_json::json!({
+                "amount_in": amount_in,
+                "is_swap_x_to_y": is_swap_x_to_y,
+                "expected": expected,
+            }));
+        }
+    }
+    write_json("quotes.json", &serde_json::Value::Array(quotes))?;
+
+    let instructions =
+        build_fixture_instructions(&mut sdk, &pool.token_mint_x, &pool.token_mint_y).await?;
+
+    let address_lookup_table = get_address_lookup_table(&rpc_client, DEVNET_LOOKUP).await?;
+    let transactions_dir = dir.join("transactions");
+    fs::create_dir_all(&transactions_dir)?;
+    for (name, instruction) in instructions {
+        let message = compile_message(
+            &FIXTURE_OWNER,
+            std::slice::from_ref(&instruction),
+            &address_lookup_table,
+            Hash::default(),
+            false,
+        )?;
+        write_json(
+            &format!("transactions/{}.json", name),
+            &serde_json::json!({
+                "owner": FIXTURE_OWNER.to_string(),
+                "salt": FIXTURE_SALT,
+                "lookup_table": DEVNET_LOOKUP.to_string(),
+                "message": STANDARD.encode(message.serialize()),
+                "instruction": instruction_json(&instruction),
+            }),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Field-by-field differences between a recorded and a rebuilt `instruction_json`
+fn diff_instruction_json(recorded: &serde_json::Value, current: &serde_json::Value) -> Vec<String> {
+    let mut differences = Vec::new();
+
+    if recorded["program_id"] != current["program_id"] {
+        differences.push(format!(
+            "program_id: {} -> {}",
+            recorded["program_id"], current["program_id"]
+        ));
+    }
+
+    let empty = Vec::new();
+    let recorded_accounts = recorded["accounts"].as_array().unwrap_or(&empty);
+    let current_accounts = current["accounts"].as_array().unwrap_or(&empty);
+    for index in 0..recorded_accounts.len().max(current_accounts.len()) {
+        match (recorded_accounts.get(index), current_accounts.get(index)) {
+            (Some(recorded), Some(current)) => {
+                for field in ["pubkey", "is_signer", "is_writable"] {
+                    if recorded[field] != current[field] {
+                        differences.push(format!(
+                            "account {} {}: {} -> {}",
+                            index, field, recorded[field], current[field]
+                        ));
+                    }
+                }
+            }
+            (Some(recorded), None) => {
+                differences.push(format!("account {} removed: {}", index, recorded["pubkey"]))
+            }
+            (None, Some(current)) => {
+                differences.push(format!("account {} added: {}", index, current["pubkey"]))
+            }
+            (None, None) => {}
+        }
+    }
+
+    let decode = |json: &serde_json::Value| {
+        STANDARD
+            .decode(json["data"].as_str().unwrap_or_default())
+            .unwrap_or_default()
+    };
+    let (recorded_data, current_data) = (decode(recorded), decode(current));
+    if recorded_data != current_data {
+        let first_difference = recorded_data
+            .iter()
+            .zip(&current_data)
+            .position(|(a, b)| a != b)
+            .unwrap_or(recorded_data.len().min(current_data.len()));
+        differences.push(format!(
+            "data: {} -> {} bytes, first difference at byte {}",
+            recorded_data.len(),
+            current_data.len(),
+            first_difference
+        ));
+    }
+
+    differences
+}
+
+/// A JSON file, e.g. written by `generate_fixtures`
+fn read_json(path: PathBuf) -> Result<serde_json::Value> {
+    let contents =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_str(&contents).with_context(|| format!("Invalid JSON in {}", path.display()))
+}
+
+async fn diff_ix(mut sdk: DarklakeSDK, fixtures_dir: &Path) -> Result<()> {
+    println!("Darklake DEX SDK - Diff Instructions");
+    println!("=====================================");
 
-    println!("Creating Token X Mint...");
-    let token_mint_x = create_token_mint(&rpc_client, &user_keypair, &token_mint_x_keypair).await?;
+    let snapshot = read_json(fixtures_dir.join("snapshot.json"))?;
+    let mint_of = |field: &str| -> Result<Pubkey> {
+        Pubkey::from_str(snapshot[field].as_str().unwrap_or_default())
+            .with_context(|| format!("Invalid {} in snapshot.json", field))
+    };
 
-    println!("Token X Mint: {}", token_mint_x);
+    let instructions = build_fixture_instructions(
+        &mut sdk,
+        &mint_of("token_mint_x")?,
+        &mint_of("token_mint_y")?,
+    )
+    .await?;
+
+    let mut changed = 0;
+    for (name, instruction) in instructions {
+        let recorded = read_json(fixtures_dir.join(format!("transactions/{}.json", name)))?;
+        let differences =
+            diff_instruction_json(&recorded["instruction"], &instruction_json(&instruction));
+        if differences.is_empty() {
+            println!("{}: unchanged", name);
+            continue;
+        }
+        changed += 1;
+        println!("{}:", name);
+        for difference in differences {
+            println!("  {}", difference);
+        }
+    }
 
-    println!("Minting Token X to user...");
-    mint_tokens_to_user(&rpc_client, &user_keypair, &token_mint_x, mint_amount).await?;
+    if changed > 0 {
+        bail!("{} instructions differ from the recorded fixtures", changed);
+    }
+    Ok(())
+}
 
-    println!("Token X Mint: {}", token_mint_x);
+/// The pool snapshot recorded in a `generate_fixtures` snapshot.json
+fn parse_fixture_snapshot(json: &serde_json::Value) -> Result<PoolSnapshot> {
+    let field = |name: &str| -> Result<u64> {
+        json[name]
+            .as_u64()
+            .with_context(|| format!("Invalid {} in snapshot.json", name))
+    };
+    Ok(PoolSnapshot {
+        reserve_x_balance: field("reserve_x_balance")?,
+        reserve_y_balance: field("reserve_y_balance")?,
+        protocol_fee_x: field("protocol_fee_x")?,
+        protocol_fee_y: field("protocol_fee_y")?,
+        locked_x: field("locked_x")?,
+        locked_y: field("locked_y")?,
+        user_locked_x: field("user_locked_x")?,
+        user_locked_y: field("user_locked_y")?,
+        trade_fee_rate: field("trade_fee_rate")?,
+        protocol_fee_rate: field("protocol_fee_rate")?,
+        ratio_change_tolerance_rate: field("ratio_change_tolerance_rate")?,
+    })
+}
 
-    let token_mint_y = Pubkey::from_str(SOL_MINT).unwrap();
+/// Basis points by which `actual` differs from `expected`
+fn divergence_bps(expected: u64, actual: u64) -> f64 {
+    if expected == actual {
+        return 0.0;
+    }
+    expected.abs_diff(actual) as f64 / expected.max(1) as f64 * 10_000.0
+}
 
-    println!("Initializing pool...");
-    let initialize_pool_tx = sdk
-        .initialize_pool_tx(
-            &token_mint_x,
-            &token_mint_y,
-            1_000,
-            1_001,
-            &user_keypair.pubkey(),
-        )
-        .await?;
+async fn quote_regress(
+    mut sdk: DarklakeSDK,
+    rpc_client: RpcClient,
+    fixtures_dir: &Path,
+    tolerance_bps: f64,
+) -> Result<()> {
+    println!("Darklake DEX SDK - Quote Regression");
+    println!("====================================");
 
-    let tx = VersionedTransaction::try_new(initialize_pool_tx.message, &[&user_keypair])?;
+    let snapshot_json = read_json(fixtures_dir.join("snapshot.json"))?;
+    let recorded_snapshot = parse_fixture_snapshot(&snapshot_json)?;
+    let mint_of = |field: &str| -> Result<Pubkey> {
+        Pubkey::from_str(snapshot_json[field].as_str().unwrap_or_default())
+            .with_context(|| format!("Invalid {} in snapshot.json", field))
+    };
+    let (token_mint_x, token_mint_y) = (mint_of("token_mint_x")?, mint_of("token_mint_y")?);
+    let cases = read_json(fixtures_dir.join("quotes.json"))?;
+    let cases = cases.as_array().context("quotes.json isn't a list")?;
+
+    // the SDK only quotes the pool as it is on chain, so it's compared with the offline
+    // math on a fresh snapshot while the recorded snapshot checks the math itself
+    let pool_key = get_pool_address(&token_mint_x, &token_mint_y);
+    let (_, live_snapshot) = get_pool_snapshot(&rpc_client, &pool_key)?;
+    println!(
+        "{} recorded cases of pool {}, tolerance {} bps",
+        cases.len(),
+        pool_key,
+        tolerance_bps
+    );
 
-    let res = rpc_client.send_and_confirm_transaction_with_spinner(&tx)?;
-    println!("Initialize Pool: {:?}", res);
+    let mut divergent = 0;
+    for case in cases {
+        let amount_in = case["amount_in"]
+            .as_u64()
+            .context("Invalid amount_in in quotes.json")?;
+        let is_swap_x_to_y = case["is_swap_x_to_y"]
+            .as_bool()
+            .context("Invalid is_swap_x_to_y in quotes.json")?;
+        let (token_mint_in, token_mint_out, direction) = if is_swap_x_to_y {
+            (token_mint_x, token_mint_y, "X -> Y")
+        } else {
+            (token_mint_y, token_mint_x, "Y -> X")
+        };
+        let mut problems = Vec::new();
+
+        let expected = &case["expected"];
+        match math::quote(&recorded_snapshot, amount_in, is_swap_x_to_y) {
+            Ok(quote) => match expected["to_amount"].as_u64() {
+                Some(recorded_out) => {
+                    let bps = divergence_bps(recorded_out, quote.to_amount);
+                    if bps > tolerance_bps {
+                        problems.push(format!(
+                            "offline math now pays out {} instead of the recorded {} ({:.2} bps)",
+                            quote.to_amount, recorded_out, bps
+                        ));
+                    }
+                }
+                None => problems.push(format!(
+                    "offline math now pays out {}, the recorded quote failed: {}",
+                    quote.to_amount, expected["error"]
+                )),
+            },
+            Err(e) => {
+                if expected["error"].is_null() {
+                    problems.push(format!("offline math now fails: {}", e));
+                }
+            }
+        }
+
+        let offline = math::quote(&live_snapshot, amount_in, is_swap_x_to_y);
+        let sdk_quote = sdk.quote(&token_mint_in, &token_mint_out, amount_in).await;
+        match (offline, sdk_quote) {
+            (Ok(offline), Ok(sdk_quote)) => {
+                let bps = divergence_bps(offline.to_amount, sdk_quote.out_amount);
+                if bps > tolerance_bps {
+                    problems.push(format!(
+                        "SDK pays out {}, offline math {} ({:.2} bps)",
+                        sdk_quote.out_amount, offline.to_amount, bps
+                    ));
+                }
+            }
+            (Ok(offline), Err(e)) => problems.push(format!(
+                "SDK fails ({}), offline math pays out {}",
+                e, offline.to_amount
+            )),
+            (Err(e), Ok(sdk_quote)) => problems.push(format!(
+                "SDK pays out {}, offline math fails ({})",
+                sdk_quote.out_amount, e
+            )),
+            (Err(_), Err(_)) => {}
+        }
+
+        if problems.is_empty() {
+            println!("{} {}: ok", direction, amount_in);
+            continue;
+        }
+        divergent += 1;
+        println!("{} {}:", direction, amount_in);
+        for problem in problems {
+            println!("  {}", problem);
+        }
+    }
 
+    if divergent > 0 {
+        bail!(
+            "{} of {} quotes diverge beyond {} bps",
+            divergent,
+            cases.len(),
+            tolerance_bps
+        );
+    }
+    println!("All quotes agree");
     Ok(())
 }
 
+/// Optional pair of mints given as positional args, both or none
+fn parse_mint_pair(
+    mint_x: Option<&String>,
+    mint_y: Option<&String>,
+) -> Result<Option<(Pubkey, Pubkey)>> {
+    match (mint_x, mint_y) {
+        (Some(x), Some(y)) if !x.starts_with("--") && !y.starts_with("--") => Ok(Some((
+            Pubkey::from_str(x).map_err(|_| usage_error("Invalid token mint X"))?,
+            Pubkey::from_str(y).map_err(|_| usage_error("Invalid token mint Y"))?,
+        ))),
+        _ => Ok(None),
+    }
+}
+
+/// Print a status line, to stderr under `--json` so stdout stays valid JSON
+fn print_status(json: bool, message: &str) {
+    if json {
+        eprintln!("{}", message);
+    } else {
+        println!("{}", message);
+    }
+}
+
+/// Error for a missing or malformed command line argument
+fn usage_error(message: &str) -> anyhow::Error {
+    anyhow::Error::new(FailureClass::Config).context(message.to_string())
+}
+
+/// Exits with the code of the error's failure class, see `FailureClass`
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(classify_error(&e).exit_code())
+        }
+    }
+}
+
+async fn run() -> Result<()> {
     let args: Vec<String> = std::env::args().collect();
 
     if args.len() < 2 {
-        println!("Usage: {} <function_name>", args[0]);
+        println!("Usage: {} <function_name> [--latency-report]", args[0]);
         println!("Available functions:");
         println!("  quote  - returns a quote");
+        println!("  mid_price  - publishes mid prices of the configured pairs as JSON lines");
+        println!(
+            "  spread <size> [mint_a mint_b]  - round trip cost and spread of quoting both directions (defaults to token X / Y)"
+        );
+        println!(
+            "  simulate_swap <amount> [mint_in mint_out]  - simulates a swap of the user key and prints its token account and pool reserve changes (defaults to token X -> Y)"
+        );
+        println!(
+            "  chunk_plan <amount> <max_impact_bps> [mint_in mint_out]  - fewest equal chunks keeping each swap's price impact under the limit"
+        );
+        println!(
+            "  depth [mint_base mint_quote] [--json] [--chart]  - base that can be sold / bought within 0.1%, 0.5% and 1% price impact, from the offline quote math (defaults to token X / Y)"
+        );
+        println!(
+            "  quotes <mint_in> <mint_out> <amount> [<mint_in> <mint_out> <amount>...]  - offline quotes of many pairs and amounts, reading every pool once"
+        );
+        println!(
+            "  verify_quote <amount> [mint_in mint_out]  - compares the SDK quote with the offline quote math (defaults to token X -> Y)"
+        );
         println!("  manual_swap  - swaps using swap_ix");
         println!("  manual_swap_slash  - swaps using swap_ix with slash");
         println!("  swap  - swaps using swap_tx");
@@ -1372,6 +5055,12 @@ async fn main() -> Result<()> {
         println!("  remove_liquidity  - remove liquidity using remove_liquidity_tx");
 
         println!("  manual_swap_different_settler  - swaps using swap_ix with a different settler");
+        println!(
+            "  finalize_build <settler> <file>  - swaps, then writes its finalize paid for by the settler and signed by the user"
+        );
+        println!(
+            "  finalize_cosign <file>  - cosigns a finalize_build file with the settler key and sends it"
+        );
         println!("  swap_different_settler  - swaps using swap_tx with a different settler");
 
         println!("  manual_add_liquidity_sol  - add liquidity using add_liquidity_ix with SOL");
@@ -1390,16 +5079,236 @@ async fn main() -> Result<()> {
         println!("  swap_from_sol  - swaps from SOL using swap_tx");
         println!("  swap_to_sol  - swaps to SOL using swap_tx");
 
-        println!("  init_pool  - creates new tokens X and Y and initializes a pool");
-        println!("  init_pool_sol  - creates new token X and SOL and initializes a pool");
         println!(
-            "  manual_init_pool  - manually creates new tokens X and Y and initializes a pool"
+            "  init_pool [mint_x mint_y]  - creates new tokens X and Y (or reuses the given ones) and initializes a pool"
+        );
+        println!(
+            "  init_pool_sol [mint_x]  - creates new token X (or reuses the given one) and initializes its pool with SOL"
+        );
+        println!(
+            "  init_pools <pools.csv>  - initializes the pool of each mint_x,mint_y,amount_x,amount_y row, skipping existing pools"
+        );
+        println!(
+            "  demo_setup  - creates or reuses a token pair of the user with its X / Y and X / SOL pools, which the other commands then default to"
+        );
+        println!(
+            "  manual_init_pool [mint_x mint_y]  - manually creates new tokens X and Y (or reuses the given ones) and initializes a pool"
+        );
+        println!(
+            "  cleanup_mints <mint> [mint...]  - burns and closes the user's token accounts of mints left by a failed init"
+        );
+
+        println!(
+            "  fees_estimate <swap|finalize|add_liquidity|init_pool>  - estimates the SOL cost of an operation"
+        );
+
+        println!(
+            "  treasury_report [wallet...] [--quote-mint <mint>]  - holdings of the wallets (default: user) valued via Darklake quotes, saved to treasury/"
+        );
+        println!(
+            "  costs_report  - network fees, priority fees, rent and tips of the transactions sent so far, by operation, pool and day"
+        );
+        println!(
+            "  endpoint_ranking  - endpoints used by --submit-via fan-out, ranked by how often each confirmed a transaction first"
+        );
+        println!(
+            "  fork_pool [mint_a] [mint_b]  - writes a pool's accounts as solana-test-validator --account files"
+        );
+        println!(
+            "  generate_fixtures [mint_a mint_b]  - writes a pool's accounts, snapshot, expected quotes and example transactions as test vectors (defaults to token X / Y)"
+        );
+        println!(
+            "  diff_ix <fixtures_dir>  - rebuilds the instructions of generate_fixtures and prints what changed since they were recorded"
+        );
+        println!(
+            "  quote_regress <fixtures_dir> [--tolerance-bps <bps>]  - checks the offline math against the recorded quotes and the SDK on the live pool (default tolerance 0 bps)"
+        );
+
+        println!(
+            "  pool_health [mint_a mint_b]  - checks a pool is tradeable (defaults to token X / Y), exits non-zero otherwise"
+        );
+        println!(
+            "  derive_order <owner> [mint_a mint_b]  - prints the order, pool and vault addresses of an owner on a pair (defaults to token X / Y)"
+        );
+        println!(
+            "  prepare [mint_a mint_b]  - creates the user's token accounts of a pair and its LP mint ahead of swaps (defaults to token X / Y)"
+        );
+        println!(
+            "  migrate_liquidity <amount_lp> <from_mint_a> <from_mint_b> <to_mint_a> <to_mint_b> [--dry-run]  - moves liquidity from one pool to another, swapping in between, resumable"
+        );
+        println!(
+            "  watch_wallet <owner> [mint_a mint_b]  - prints an owner's order and token / LP balance changes on a pair as they happen (defaults to token X / Y)"
+        );
+        println!(
+            "  verify_blob <base64>  - decodes and prints an unsigned / signed transaction blob"
+        );
+
+        println!("Options:");
+        println!("  --latency-report  - print a per-stage timing breakdown (manual_swap, swap)");
+        println!("  --force  - swap even if the pool health check fails");
+        println!(
+            "  --amount-out <amount>  - size the input from the output to receive instead, plus 1% slippage on the input (quote, swap)"
+        );
+        println!(
+            "  --raw  - print token amounts in base units, not scaled by the mint decimals, as plain integers instead of e.g. 1,234.5 SOL"
+        );
+        println!(
+            "  --assert-received  - after the finalize of manual_swap / swap (and their _different_settler variants), fail with exit code 8 if the output balance grew by less than the min out"
+        );
+        println!(
+            "  --record-stats  - record sent transactions in costs.jsonl (costs_report) and fan-out results in endpoints.json (endpoint_ranking)"
+        );
+        println!(
+            "  --value-in <mint>  - value balances in this token via Darklake quotes (watch_wallet, default of treasury_report's --quote-mint)"
+        );
+        println!(
+            "  --keypair-file <path> / --keypair-env <VAR>  - load the user key from a file (e.g. /run/secrets/user_key) or environment variable, as a JSON array or base64"
+        );
+        println!(
+            "  --settler-keypair-file <path> / --settler-keypair-env <VAR>  - same for the settler key"
+        );
+        println!(
+            "  --non-interactive  - no prompts (also the default when stdout isn't a terminal)"
+        );
+        println!(
+            "  --skip-simulation  - send transactions without simulating them first (failing ones land on-chain and pay fees)"
+        );
+        println!(
+            "  --cost-preview  - print fees and the accounts (with rent) a transaction creates before sending it (manual_swap, manual_add_liquidity, manual_init_pool)"
+        );
+        println!(
+            "  --preview  - show what the transaction writes to and how the wallet's balances change, and ask before signing (manual_swap, manual_add_liquidity, manual_remove_liquidity)"
+        );
+        println!(
+            "  --blockhash <hash>  - build with the given blockhash and print the unsigned message instead of sending (manual_swap, manual_add_liquidity, manual_remove_liquidity), needs --lookup-table-file or --legacy-tx"
+        );
+        println!(
+            "  --lookup-table-file <path>  - the lookup table for --blockhash, saved by `solana address-lookup-table get <address> --output json`"
+        );
+        println!(
+            "  --legacy-tx  - send legacy transactions without the lookup table instead of V0 (manual_swap, manual_add_liquidity, manual_remove_liquidity)"
+        );
+        println!(
+            "  --wallet-blob  - print the unsigned transaction as base64 for a web wallet adapter instead of sending (manual_swap, manual_add_liquidity, manual_remove_liquidity)"
+        );
+        println!(
+            "  --rpc-timeout / --confirm-timeout / --order-timeout / --ws-timeout <secs>  - per-operation timeouts (defaults 30 / 60 / 25 / 10)"
+        );
+        println!(
+            "  --reference-rpc <url>  - compare slots with a second endpoint and switch to it when the default one lags"
+        );
+        println!(
+            "  --cu-limit <units> / --cu-price <micro-lamports>  - compute budget of every manual flow transaction (defaults: 500k limit for finalize / init_pool, no price)"
+        );
+        println!(
+            "  --mint-seed <seed>  - derive the created mints from a seed for reproducible addresses (init_pool*, manual_init_pool, demo_setup), each command under its own names"
+        );
+        println!(
+            "  --submit-via <rpc|fan-out|jito>  - how transactions are sent: the RPC endpoint (default), also every --fan-out-rpc <url,...>, or as a Jito bundle to --jito-url <url> tipping --jito-tip <lamports> (default 1000)"
+        );
+        println!(
+            "  --fee-buffer <lamports>  - SOL kept in the wallet after wrapping, to pay for the finalize (default 10000000, *_sol flows)"
         );
         return Ok(());
     }
 
     let is_devnet = true;
 
+    let timeout_arg = |flag: &str, default: Duration| -> Result<Duration> {
+        match args.iter().position(|arg| arg == flag) {
+            Some(i) => {
+                let secs = args
+                    .get(i + 1)
+                    .and_then(|v| v.parse().ok())
+                    .ok_or_else(|| usage_error(&format!("{flag} needs a number of seconds")))?;
+                Ok(Duration::from_secs(secs))
+            }
+            None => Ok(default),
+        }
+    };
+    let defaults = Timeouts::default();
+    let timeouts = Timeouts {
+        rpc: timeout_arg("--rpc-timeout", defaults.rpc)?,
+        confirm: timeout_arg("--confirm-timeout", defaults.confirm)?,
+        order: timeout_arg("--order-timeout", defaults.order)?,
+        websocket: timeout_arg("--ws-timeout", defaults.websocket)?,
+    };
+    set_timeouts(timeouts);
+
+    let cu_arg = |flag: &str| -> Result<Option<String>> {
+        match args.iter().position(|arg| arg == flag) {
+            Some(i) => Ok(Some(
+                args.get(i + 1)
+                    .ok_or_else(|| usage_error(&format!("{flag} needs a number")))?
+                    .clone(),
+            )),
+            None => Ok(None),
+        }
+    };
+    set_compute_budget(ComputeBudget {
+        cu_limit: cu_arg("--cu-limit")?
+            .map(|v| v.parse())
+            .transpose()
+            .map_err(|_| usage_error("Invalid --cu-limit"))?,
+        cu_price: cu_arg("--cu-price")?
+            .map(|v| v.parse())
+            .transpose()
+            .map_err(|_| usage_error("Invalid --cu-price"))?,
+    });
+
+    // status lines go to stderr under --json, stdout only carries the JSON
+    let json = args.iter().any(|arg| arg == "--json");
+
+    // the Solana CLI cluster replaces the default endpoint if the examples can run on it
+    let solana_cli = SolanaCliConfig::load();
+    let mut rpc_endpoint = RPC_ENDPOINT.to_string();
+    if let Some(url) = solana_cli
+        .as_ref()
+        .and_then(|config| config.json_rpc_url.as_ref())
+        && url != RPC_ENDPOINT
+    {
+        match check_solana_cli_cluster(url, timeouts.rpc) {
+            Ok(()) => {
+                print_status(json, &format!("Using the Solana CLI cluster {}", url));
+                rpc_endpoint = url.clone();
+            }
+            Err(e) => print_status(
+                json,
+                &format!(
+                    "Warning: not using the Solana CLI cluster {} ({:#}), using {}",
+                    url, e, RPC_ENDPOINT
+                ),
+            ),
+        }
+    }
+
+    // Switch to the reference endpoint when the primary node lags behind it
+    if let Some(reference_url) = args
+        .iter()
+        .position(|arg| arg == "--reference-rpc")
+        .and_then(|i| args.get(i + 1))
+    {
+        let primary_client = RpcClient::new_with_timeout_and_commitment(
+            rpc_endpoint.clone(),
+            timeouts.rpc,
+            CommitmentConfig::processed(),
+        );
+        match get_rpc_slot_lag(&primary_client, reference_url) {
+            Ok(lag) if lag > MAX_RPC_SLOT_LAG => {
+                print_status(
+                    json,
+                    &format!(
+                        "Warning: {} is {} slots behind {}, its quotes and slots may be stale. Switching to {}",
+                        rpc_endpoint, lag, reference_url, reference_url
+                    ),
+                );
+                rpc_endpoint = reference_url.clone();
+            }
+            Ok(_) => {}
+            Err(e) => print_status(json, &format!("Warning: slot lag check failed ({:#})", e)),
+        }
+    }
+
     // let sdk_finalized = DarklakeSDK::new(
     //     RPC_ENDPOINT,
     //     CommitmentLevel::Finalized,
@@ -1412,125 +5321,622 @@ async fn main() -> Result<()> {
     //     RpcClient::new_with_commitment(RPC_ENDPOINT.to_string(), CommitmentConfig::finalized());
 
     let sdk_processed = DarklakeSDK::new(
-        RPC_ENDPOINT,
+        &rpc_endpoint,
         CommitmentLevel::Processed,
         is_devnet,
         Some(LABEL),
         Some(REF_CODE),
     )?;
 
-    let rpc_client_processed =
-        RpcClient::new_with_commitment(RPC_ENDPOINT.to_string(), CommitmentConfig::processed());
+    let rpc_client_processed = RpcClient::new_with_timeouts_and_commitment(
+        rpc_endpoint.clone(),
+        timeouts.rpc,
+        CommitmentConfig::processed(),
+        timeouts.confirm,
+    );
 
     let sdk = sdk_processed;
     let rpc_client = rpc_client_processed;
 
-    let user_key_filename = "user_key.json";
-    let settler_key_filename = "settler_key.json";
+    let latency_report = args.iter().any(|arg| arg == "--latency-report");
+    let force = args.iter().any(|arg| arg == "--force");
+    let amount_out = args
+        .iter()
+        .position(|arg| arg == "--amount-out")
+        .map(|i| {
+            args.get(i + 1)
+                .ok_or_else(|| usage_error("--amount-out needs an amount"))?
+                .parse::<u64>()
+                .map_err(|_| usage_error("Invalid --amount-out"))
+        })
+        .transpose()?;
+    set_command(&args[1]);
+    set_non_interactive(args.iter().any(|arg| arg == "--non-interactive"));
+    set_raw_amounts(args.iter().any(|arg| arg == "--raw"));
+    set_assert_received(args.iter().any(|arg| arg == "--assert-received"));
+    set_record_stats(args.iter().any(|arg| arg == "--record-stats"));
+    if let Some(i) = args.iter().position(|arg| arg == "--value-in") {
+        set_value_mint(
+            Pubkey::from_str(
+                args.get(i + 1)
+                    .ok_or_else(|| usage_error("--value-in needs a token mint"))?,
+            )
+            .map_err(|_| usage_error("Invalid --value-in mint"))?,
+        );
+    }
+    set_skip_simulation(args.iter().any(|arg| arg == "--skip-simulation"));
+    if let Some(i) = args.iter().position(|arg| arg == "--fee-buffer") {
+        let lamports = args
+            .get(i + 1)
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(|| usage_error("--fee-buffer needs a number of lamports"))?;
+        FEE_BUFFER.set(lamports).unwrap();
+    }
+    if let Some(mints) = read_demo_config().context(FailureClass::Config)? {
+        print_status(
+            json,
+            &format!(
+                "Using the demo pair from {}: {} / {}",
+                DEMO_CONFIG_PATH, mints.0, mints.1
+            ),
+        );
+        DEMO_MINTS.set(mints).unwrap();
+    }
+    if let Some(i) = args.iter().position(|arg| arg == "--lookup-table-file") {
+        let path = args
+            .get(i + 1)
+            .ok_or_else(|| usage_error("--lookup-table-file needs a path"))?;
+        OFFLINE_LOOKUP_TABLE
+            .set(
+                read_address_lookup_table_file(Path::new(path), DEVNET_LOOKUP)
+                    .context(FailureClass::Config)?,
+            )
+            .unwrap();
+    }
+    let tx_options = TxOptions {
+        cost_preview: args.iter().any(|arg| arg == "--cost-preview"),
+        offline_blockhash: args
+            .iter()
+            .position(|arg| arg == "--blockhash")
+            .and_then(|i| args.get(i + 1))
+            .map(|hash| Hash::from_str(hash).map_err(|_| usage_error("Invalid blockhash")))
+            .transpose()?,
+        legacy_tx: args.iter().any(|arg| arg == "--legacy-tx"),
+        wallet_blob: args.iter().any(|arg| arg == "--wallet-blob"),
+        preview: args.iter().any(|arg| arg == "--preview"),
+    };
+    if tx_options.offline_blockhash.is_some()
+        && OFFLINE_LOOKUP_TABLE.get().is_none()
+        && !tx_options.legacy_tx
+    {
+        return Err(usage_error(
+            "--blockhash needs --lookup-table-file <path> or --legacy-tx, the lookup table isn't read over RPC",
+        ));
+    }
+    let mint_seed = args
+        .iter()
+        .position(|arg| arg == "--mint-seed")
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str);
+
+    let user_key =
+        KeySource::from_args(&args, "keypair", "user_key.json").or_solana_cli(solana_cli.as_ref());
+    let settler_key = KeySource::from_args(&args, "settler-keypair", "settler_key.json");
+
+    let flag_value = |flag: &str| -> Result<Option<&String>> {
+        match args.iter().position(|arg| arg == flag) {
+            Some(i) => {
+                Ok(Some(args.get(i + 1).ok_or_else(|| {
+                    usage_error(&format!("{flag} needs a value"))
+                })?))
+            }
+            None => Ok(None),
+        }
+    };
+    let submitter: Box<dyn TxSubmitter> = match flag_value("--submit-via")?
+        .map_or("rpc", String::as_str)
+    {
+        "rpc" => Box::new(RpcSubmitter),
+        "fan-out" => {
+            let endpoints: Vec<String> = flag_value("--fan-out-rpc")?
+                .ok_or_else(|| usage_error("--submit-via fan-out needs --fan-out-rpc <url,...>"))?
+                .split(',')
+                .map(|url| url.trim().to_string())
+                .filter(|url| !url.is_empty())
+                .collect();
+            Box::new(FanOutSubmitter::new(endpoints))
+        }
+        "jito" => {
+            let url = flag_value("--jito-url")?
+                .ok_or_else(|| usage_error("--submit-via jito needs --jito-url <url>"))?;
+            let tip = match flag_value("--jito-tip")? {
+                Some(tip) => tip.parse().map_err(|_| usage_error("Invalid --jito-tip"))?,
+                None => DEFAULT_JITO_TIP,
+            };
+            check_jito_cluster(&rpc_endpoint, timeouts.rpc).context(FailureClass::Config)?;
+            // the user pays the tips, whoever signs the transactions
+            Box::new(JitoSubmitter::new(url, load_keypair(&user_key)?, tip))
+        }
+        other => {
+            return Err(usage_error(&format!(
+                "Unknown --submit-via {}, expected rpc, fan-out or jito",
+                other
+            )));
+        }
+    };
+    if submitter.name() != "rpc" {
+        print_status(
+            json,
+            &format!("Sending transactions via {}", submitter.name()),
+        );
+    }
+    set_tx_submitter(submitter);
+
+    // Swaps only go ahead on a tradeable pool, unless --force is given (simulations send nothing)
+    if args[1].contains("swap") && args[1] != "simulate_swap" {
+        let token_mint_y = if args[1].ends_with("_sol") {
+            native_mint::ID
+        } else {
+            default_mint_y()
+        };
+        let pool_key = get_pool_address(&default_mint_x(), &token_mint_y);
+
+        let health = check_pool_health(&rpc_client, &pool_key)?;
+        if !health.is_healthy() {
+            health.print();
+            if !force {
+                bail!("Pool is not tradeable, pass --force to swap anyway");
+            }
+            println!("--force given, swapping anyway");
+        }
+    }
 
     match args[1].as_str() {
         "quote" => {
             println!("Running quote()...");
-            quote(sdk).await
+            quote(sdk, rpc_client, amount_out).await
+        }
+        "mid_price" => {
+            println!("Running mid_price()...");
+            mid_price(sdk, rpc_client).await
         }
         "manual_swap" => {
             println!("Running manual_swap()...");
-            manual_swap(sdk, load_keypair(user_key_filename)?, rpc_client).await
+            manual_swap(
+                sdk,
+                load_keypair(&user_key)?,
+                rpc_client,
+                latency_report,
+                tx_options,
+            )
+            .await
         }
         "manual_swap_different_settler" => {
             println!("Running manual_swap_different_settler()...");
             manual_swap_different_settler(
                 sdk,
-                load_keypair(user_key_filename)?,
-                load_keypair(settler_key_filename)?,
+                load_keypair(&user_key)?,
+                load_keypair(&settler_key)?,
+                rpc_client,
+            )
+            .await
+        }
+        "finalize_build" => {
+            println!("Running finalize_build()...");
+            let settler = Pubkey::from_str(
+                args.get(2)
+                    .ok_or_else(|| usage_error("finalize_build needs the settler's address"))?,
+            )
+            .map_err(|_| usage_error("Invalid settler address"))?;
+            let path = args
+                .get(3)
+                .ok_or_else(|| usage_error("finalize_build needs an output file"))?;
+            finalize_build(
+                sdk,
+                load_keypair(&user_key)?,
                 rpc_client,
+                &settler,
+                Path::new(path),
             )
             .await
         }
+        "finalize_cosign" => {
+            println!("Running finalize_cosign()...");
+            let path = args
+                .get(2)
+                .ok_or_else(|| usage_error("finalize_cosign needs the file of finalize_build"))?;
+            finalize_cosign(load_keypair(&settler_key)?, rpc_client, Path::new(path))
+        }
         "manual_swap_slash" => {
             println!("Running manual_swap_slash()...");
-            manual_swap_slash(sdk, load_keypair(user_key_filename)?, rpc_client).await
+            manual_swap_slash(sdk, load_keypair(&user_key)?, rpc_client).await
         }
         "swap" => {
             println!("Running swap()...");
-            swap(sdk, load_keypair(user_key_filename)?, rpc_client).await
+            swap(
+                sdk,
+                load_keypair(&user_key)?,
+                rpc_client,
+                latency_report,
+                amount_out,
+            )
+            .await
         }
         "swap_different_settler" => {
             println!("Running swap_different_settler()...");
             swap_different_settler(
                 sdk,
-                load_keypair(user_key_filename)?,
-                load_keypair(settler_key_filename)?,
+                load_keypair(&user_key)?,
+                load_keypair(&settler_key)?,
                 rpc_client,
             )
             .await
         }
         "manual_add_liquidity" => {
             println!("Running manual_add_liquidity()...");
-            manual_add_liquidity(sdk, load_keypair(user_key_filename)?, rpc_client).await
+            manual_add_liquidity(sdk, load_keypair(&user_key)?, rpc_client, tx_options).await
         }
         "add_liquidity" => {
             println!("Running add_liquidity()...");
-            add_liquidity(sdk, load_keypair(user_key_filename)?, rpc_client).await
+            add_liquidity(sdk, load_keypair(&user_key)?, rpc_client).await
         }
         "manual_remove_liquidity" => {
             println!("Running manual_remove_liquidity()...");
-            manual_remove_liquidity(sdk, load_keypair(user_key_filename)?, rpc_client).await
+            manual_remove_liquidity(sdk, load_keypair(&user_key)?, rpc_client, tx_options).await
         }
 
         "remove_liquidity" => {
             println!("Running remove_liquidity()...");
-            remove_liquidity(sdk, load_keypair(user_key_filename)?, rpc_client).await
+            remove_liquidity(sdk, load_keypair(&user_key)?, rpc_client).await
         }
 
         // SOL
         "manual_swap_from_sol" => {
             println!("Running manual_swap_from_sol()...");
-            manual_swap_from_sol(sdk, load_keypair(user_key_filename)?, rpc_client).await
+            manual_swap_from_sol(sdk, load_keypair(&user_key)?, rpc_client).await
         }
         "manual_swap_to_sol" => {
             println!("Running manual_swap_to_sol()...");
-            manual_swap_to_sol(sdk, load_keypair(user_key_filename)?, rpc_client).await
+            manual_swap_to_sol(sdk, load_keypair(&user_key)?, rpc_client).await
         }
         "swap_from_sol" => {
             println!("Running swap_from_sol()...");
-            swap_from_sol(sdk, load_keypair(user_key_filename)?, rpc_client).await
+            swap_from_sol(sdk, load_keypair(&user_key)?, rpc_client).await
         }
         "swap_to_sol" => {
             println!("Running swap_to_sol()...");
-            swap_to_sol(sdk, load_keypair(user_key_filename)?, rpc_client).await
+            swap_to_sol(sdk, load_keypair(&user_key)?, rpc_client).await
         }
         "manual_add_liquidity_sol" => {
             println!("Running manual_add_liquidity_sol()...");
-            manual_add_liquidity_sol(sdk, load_keypair(user_key_filename)?, rpc_client).await
+            manual_add_liquidity_sol(sdk, load_keypair(&user_key)?, rpc_client).await
         }
         "manual_remove_liquidity_sol" => {
             println!("Running manual_remove_liquidity_sol()...");
-            manual_remove_liquidity_sol(sdk, load_keypair(user_key_filename)?, rpc_client).await
+            manual_remove_liquidity_sol(sdk, load_keypair(&user_key)?, rpc_client).await
         }
         "remove_liquidity_sol" => {
             println!("Running remove_liquidity_sol()...");
-            remove_liquidity_sol(sdk, load_keypair(user_key_filename)?, rpc_client).await
+            remove_liquidity_sol(sdk, load_keypair(&user_key)?, rpc_client).await
         }
         "add_liquidity_sol" => {
             println!("Running add_liquidity_sol()...");
-            add_liquidity_sol(sdk, load_keypair(user_key_filename)?, rpc_client).await
+            add_liquidity_sol(sdk, load_keypair(&user_key)?, rpc_client).await
         }
         "manual_init_pool" => {
             println!("Running manual_init_pool()...");
-            manual_init_pool(sdk, load_keypair(user_key_filename)?, rpc_client).await
+            let existing_mints = parse_mint_pair(args.get(2), args.get(3))?;
+            manual_init_pool(
+                sdk,
+                load_keypair(&user_key)?,
+                rpc_client,
+                existing_mints,
+                mint_seed,
+                tx_options.cost_preview,
+            )
+            .await
         }
         "init_pool" => {
             println!("Running init_pool()...");
-            init_pool(sdk, load_keypair(user_key_filename)?, rpc_client).await
+            let existing_mints = parse_mint_pair(args.get(2), args.get(3))?;
+            init_pool(
+                sdk,
+                load_keypair(&user_key)?,
+                rpc_client,
+                existing_mints,
+                mint_seed,
+            )
+            .await
+        }
+        "cleanup_mints" => {
+            println!("Running cleanup_mints()...");
+            let mints = args[2..]
+                .iter()
+                .enumerate()
+                .filter(|(i, a)| {
+                    !a.starts_with("--") && !FLAGS_WITH_VALUE.contains(&args[i + 1].as_str())
+                })
+                .map(|(_, a)| Pubkey::from_str(a).map_err(|_| usage_error("Invalid token mint")))
+                .collect::<Result<Vec<_>>>()?;
+            if mints.is_empty() {
+                return Err(usage_error("cleanup_mints needs at least one token mint"));
+            }
+            cleanup_mints(load_keypair(&user_key)?, rpc_client, &mints).await
+        }
+        "init_pools" => {
+            println!("Running init_pools()...");
+            let path = args
+                .get(2)
+                .ok_or_else(|| usage_error("init_pools needs a CSV file"))?;
+            init_pools(sdk, load_keypair(&user_key)?, rpc_client, Path::new(path)).await
+        }
+        "demo_setup" => {
+            println!("Running demo_setup()...");
+            demo_setup(sdk, load_keypair(&user_key)?, rpc_client, mint_seed).await
         }
         "init_pool_sol" => {
             println!("Running init_pool_sol()...");
-            init_pool_sol(sdk, load_keypair(user_key_filename)?, rpc_client).await
+            let existing_mint = args
+                .get(2)
+                .filter(|arg| !arg.starts_with("--"))
+                .map(|mint| Pubkey::from_str(mint).map_err(|_| usage_error("Invalid token mint X")))
+                .transpose()?;
+            init_pool_sol(
+                sdk,
+                load_keypair(&user_key)?,
+                rpc_client,
+                existing_mint,
+                mint_seed,
+            )
+            .await
+        }
+        "fees_estimate" => {
+            println!("Running fees_estimate()...");
+            let operation = args.get(2).map(String::as_str).unwrap_or("swap");
+            fees_estimate(sdk, load_keypair(&user_key)?, rpc_client, operation).await
+        }
+        "verify_quote" => {
+            println!("Running verify_quote()...");
+            let amount_in = args
+                .get(2)
+                .ok_or_else(|| usage_error("verify_quote needs an amount"))?
+                .parse()
+                .map_err(|_| usage_error("Invalid amount"))?;
+            let (token_mint_in, token_mint_out) = parse_mint_pair(args.get(3), args.get(4))?
+                .unwrap_or((default_mint_x(), default_mint_y()));
+            verify_quote(sdk, rpc_client, &token_mint_in, &token_mint_out, amount_in).await
+        }
+        "quotes" => {
+            println!("Running quotes()...");
+            let entries: Vec<_> = args[2..]
+                .iter()
+                .take_while(|arg| !arg.starts_with("--"))
+                .collect();
+            if entries.is_empty() || entries.len() % 3 != 0 {
+                return Err(usage_error(
+                    "quotes needs one or more <mint_in> <mint_out> <amount> triples",
+                ));
+            }
+            let requests = entries
+                .chunks(3)
+                .map(|entry| {
+                    Ok((
+                        Pubkey::from_str(entry[0])
+                            .map_err(|_| usage_error("Invalid input token mint"))?,
+                        Pubkey::from_str(entry[1])
+                            .map_err(|_| usage_error("Invalid output token mint"))?,
+                        entry[2]
+                            .parse()
+                            .map_err(|_| usage_error("Invalid amount"))?,
+                    ))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            quotes(rpc_client, &requests)
+        }
+        "simulate_swap" => {
+            println!("Running simulate_swap()...");
+            let amount_in = args
+                .get(2)
+                .ok_or_else(|| usage_error("simulate_swap needs an amount"))?
+                .parse()
+                .map_err(|_| usage_error("Invalid amount"))?;
+            let (token_mint_in, token_mint_out) = parse_mint_pair(args.get(3), args.get(4))?
+                .unwrap_or((default_mint_x(), default_mint_y()));
+            simulate_swap(
+                sdk,
+                load_keypair(&user_key)?,
+                rpc_client,
+                &token_mint_in,
+                &token_mint_out,
+                amount_in,
+            )
+            .await
+        }
+        "depth" => {
+            print_status(json, "Running depth()...");
+            let (token_mint_base, token_mint_quote) = parse_mint_pair(args.get(2), args.get(3))?
+                .unwrap_or((default_mint_x(), default_mint_y()));
+            depth(
+                rpc_client,
+                &token_mint_base,
+                &token_mint_quote,
+                json,
+                args.iter().any(|arg| arg == "--chart"),
+            )
+        }
+        "chunk_plan" => {
+            println!("Running chunk_plan()...");
+            let amount_in = args
+                .get(2)
+                .ok_or_else(|| usage_error("chunk_plan needs an amount"))?
+                .parse()
+                .map_err(|_| usage_error("Invalid amount"))?;
+            let max_impact_bps = args
+                .get(3)
+                .ok_or_else(|| usage_error("chunk_plan needs a maximum price impact in bps"))?
+                .parse()
+                .map_err(|_| usage_error("Invalid maximum price impact"))?;
+            let (token_mint_in, token_mint_out) = parse_mint_pair(args.get(4), args.get(5))?
+                .unwrap_or((default_mint_x(), default_mint_y()));
+            chunk_plan(
+                rpc_client,
+                &token_mint_in,
+                &token_mint_out,
+                amount_in,
+                max_impact_bps,
+            )
+            .await
+        }
+        "spread" => {
+            println!("Running spread()...");
+            let size = args
+                .get(2)
+                .ok_or_else(|| usage_error("spread needs a size"))?
+                .parse()
+                .map_err(|_| usage_error("Invalid size"))?;
+            let (token_mint_a, token_mint_b) = parse_mint_pair(args.get(3), args.get(4))?
+                .unwrap_or((default_mint_x(), default_mint_y()));
+            spread(sdk, rpc_client, &token_mint_a, &token_mint_b, size).await
+        }
+        "pool_health" => {
+            println!("Running pool_health()...");
+            let (token_mint_a, token_mint_b) = parse_mint_pair(args.get(2), args.get(3))?
+                .unwrap_or((default_mint_x(), default_mint_y()));
+            pool_health(rpc_client, &token_mint_a, &token_mint_b)
         }
-        _ => {
-            println!("Unknown function: {}", args[1]);
+        "derive_order" => {
+            println!("Running derive_order()...");
+            let owner = Pubkey::from_str(
+                args.get(2)
+                    .ok_or_else(|| usage_error("derive_order needs an owner address"))?,
+            )
+            .map_err(|_| usage_error("Invalid owner address"))?;
+            let (token_mint_a, token_mint_b) = parse_mint_pair(args.get(3), args.get(4))?
+                .unwrap_or((default_mint_x(), default_mint_y()));
+            derive_order_addresses(&owner, &token_mint_a, &token_mint_b).print();
             Ok(())
         }
+        "prepare" => {
+            println!("Running prepare()...");
+            let (token_mint_a, token_mint_b) = parse_mint_pair(args.get(2), args.get(3))?
+                .unwrap_or((default_mint_x(), default_mint_y()));
+            prepare(
+                load_keypair(&user_key)?,
+                rpc_client,
+                &token_mint_a,
+                &token_mint_b,
+            )
+            .await
+        }
+        "migrate_liquidity" => {
+            println!("Running migrate_liquidity()...");
+            let amount_lp = args
+                .get(2)
+                .ok_or_else(|| usage_error("migrate_liquidity needs an LP amount"))?
+                .parse()
+                .map_err(|_| usage_error("Invalid LP amount"))?;
+            let (Some(from), Some(to)) = (
+                parse_mint_pair(args.get(3), args.get(4))?,
+                parse_mint_pair(args.get(5), args.get(6))?,
+            ) else {
+                return Err(usage_error(
+                    "migrate_liquidity needs the old pair's and the new pair's mints",
+                ));
+            };
+            migrate_liquidity(
+                sdk,
+                load_keypair(&user_key)?,
+                rpc_client,
+                amount_lp,
+                [from.0, from.1],
+                [to.0, to.1],
+                args.iter().any(|arg| arg == "--dry-run"),
+            )
+            .await
+        }
+        "watch_wallet" => {
+            println!("Running watch_wallet()...");
+            let owner = Pubkey::from_str(
+                args.get(2)
+                    .ok_or_else(|| usage_error("watch_wallet needs an owner address"))?,
+            )
+            .map_err(|_| usage_error("Invalid owner address"))?;
+            let (token_mint_a, token_mint_b) = parse_mint_pair(args.get(3), args.get(4))?
+                .unwrap_or((default_mint_x(), default_mint_y()));
+            watch_wallet(rpc_client, &owner, &token_mint_a, &token_mint_b)
+        }
+        "verify_blob" => {
+            println!("Running verify_blob()...");
+            let blob = args
+                .get(2)
+                .ok_or_else(|| usage_error("verify_blob needs a base64 transaction"))?;
+            print_wallet_blob(blob)
+        }
+        "treasury_report" => {
+            println!("Running treasury_report()...");
+            let mut wallets = args[2..]
+                .iter()
+                .enumerate()
+                .filter(|(i, a)| {
+                    !a.starts_with("--") && !FLAGS_WITH_VALUE.contains(&args[i + 1].as_str())
+                })
+                .map(|(_, a)| {
+                    Pubkey::from_str(a).map_err(|_| usage_error("Invalid wallet address"))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            if wallets.is_empty() {
+                wallets.push(load_keypair(&user_key)?.pubkey());
+            }
+            let quote_mint = match args.iter().position(|arg| arg == "--quote-mint") {
+                Some(i) => Pubkey::from_str(
+                    args.get(i + 1)
+                        .ok_or_else(|| usage_error("--quote-mint needs a token mint"))?,
+                )
+                .map_err(|_| usage_error("Invalid quote mint"))?,
+                None => value_mint().unwrap_or(default_mint_y()),
+            };
+            treasury_report(rpc_client, &wallets, &quote_mint)
+        }
+        "endpoint_ranking" => {
+            println!("Running endpoint_ranking()...");
+            endpoint_ranking()
+        }
+        "costs_report" => {
+            println!("Running costs_report()...");
+            costs_report(rpc_client)
+        }
+        "generate_fixtures" => {
+            println!("Running generate_fixtures()...");
+            let (token_mint_a, token_mint_b) = parse_mint_pair(args.get(2), args.get(3))?
+                .unwrap_or((default_mint_x(), default_mint_y()));
+            generate_fixtures(sdk, rpc_client, &token_mint_a, &token_mint_b).await
+        }
+        "diff_ix" => {
+            println!("Running diff_ix()...");
+            let fixtures_dir = args
+                .get(2)
+                .ok_or_else(|| usage_error("diff_ix needs a fixtures directory"))?;
+            diff_ix(sdk, Path::new(fixtures_dir)).await
+        }
+        "quote_regress" => {
+            println!("Running quote_regress()...");
+            let fixtures_dir = args
+                .get(2)
+                .ok_or_else(|| usage_error("quote_regress needs a fixtures directory"))?;
+            let tolerance_bps = match flag_value("--tolerance-bps")? {
+                Some(tolerance) => tolerance
+                    .parse()
+                    .map_err(|_| usage_error("Invalid --tolerance-bps"))?,
+                None => 0.0,
+            };
+            quote_regress(sdk, rpc_client, Path::new(fixtures_dir), tolerance_bps).await
+        }
+        "fork_pool" => {
+            println!("Running fork_pool()...");
+            let (token_mint_a, token_mint_b) = parse_mint_pair(args.get(2), args.get(3))?
+                .unwrap_or((default_mint_x(), default_mint_y()));
+            fork_pool(rpc_client, &token_mint_a, &token_mint_b).await
+        }
+        _ => Err(usage_error(&format!("Unknown function: {}", args[1]))),
     }
 }
@@ -0,0 +1,528 @@
+//! Offline re-implementation of the Darklake constant-product quote
+//!
+//! Mirrors the program's math (trade fee rounded up, protocol fee rounded down,
+//! output rounded down, ratio change tolerance check) so quotes can be computed
+//! from a snapshot of the pool without an SDK / RPC round trip. Token-2022
+//! transfer fees are not modelled, quotes on such mints will be too high.
+
+use anyhow::{Result, bail};
+
+/// Denominator of the fee and tolerance rates in the amm config
+pub const MAX_PERCENTAGE: u64 = 1_000_000;
+
+/// Everything a quote depends on: reserve balances, the pool's locked and fee amounts
+/// and the amm config rates
+#[derive(Debug, Clone, Copy)]
+pub struct PoolSnapshot {
+    pub reserve_x_balance: u64,
+    pub reserve_y_balance: u64,
+    pub protocol_fee_x: u64,
+    pub protocol_fee_y: u64,
+    pub locked_x: u64,
+    pub locked_y: u64,
+    pub user_locked_x: u64,
+    pub user_locked_y: u64,
+    pub trade_fee_rate: u64,
+    pub protocol_fee_rate: u64,
+    pub ratio_change_tolerance_rate: u64,
+}
+
+impl PoolSnapshot {
+    /// Reserves excluding protocol fees and pending user orders
+    fn total_reserves(&self) -> Result<(u64, u64)> {
+        let total = |balance: u64, protocol_fee: u64, user_locked: u64| {
+            balance
+                .checked_sub(protocol_fee)
+                .and_then(|amount| amount.checked_sub(user_locked))
+        };
+        match (
+            total(
+                self.reserve_x_balance,
+                self.protocol_fee_x,
+                self.user_locked_x,
+            ),
+            total(
+                self.reserve_y_balance,
+                self.protocol_fee_y,
+                self.user_locked_y,
+            ),
+        ) {
+            (Some(total_x), Some(total_y)) => Ok((total_x, total_y)),
+            _ => bail!("Pool reserves are smaller than its fees and user locked amounts"),
+        }
+    }
+
+    /// Reserves available for trading, also excluding the pool's locked amounts
+    pub fn available_reserves(&self) -> Result<(u64, u64)> {
+        let (total_x, total_y) = self.total_reserves()?;
+        match (
+            total_x.checked_sub(self.locked_x),
+            total_y.checked_sub(self.locked_y),
+        ) {
+            (Some(available_x), Some(available_y)) => Ok((available_x, available_y)),
+            _ => bail!("Pool reserves are smaller than its locked amounts"),
+        }
+    }
+
+    /// Output per input before fees and price impact
+    pub fn spot_price(&self, is_swap_x_to_y: bool) -> Result<f64> {
+        let (available_x, available_y) = self.available_reserves()?;
+        if available_x == 0 || available_y == 0 {
+            bail!("Pool has no available liquidity on one side");
+        }
+        Ok(if is_swap_x_to_y {
+            available_y as f64 / available_x as f64
+        } else {
+            available_x as f64 / available_y as f64
+        })
+    }
+}
+
+/// Result of an exact-in quote, same fields as the program's quote output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OfflineQuote {
+    /// Input after the trade fee
+    pub from_amount: u64,
+    pub to_amount: u64,
+    pub trade_fee: u64,
+    pub protocol_fee: u64,
+    /// Input locked to keep the pool ratio after the swap
+    pub from_to_lock: u64,
+}
+
+/// Trade fee, rounded up
+pub fn get_trade_fee(amount: u64, trade_fee_rate: u64) -> u64 {
+    (amount as u128 * trade_fee_rate as u128).div_ceil(MAX_PERCENTAGE as u128) as u64
+}
+
+/// Protocol share of the trade fee, rounded down
+pub fn get_protocol_fee(trade_fee: u64, protocol_fee_rate: u64) -> u64 {
+    (trade_fee as u128 * protocol_fee_rate as u128 / MAX_PERCENTAGE as u128) as u64
+}
+
+/// Constant-product output without fees, rounded down: dy = dx * y / (x + dx)
+pub fn swap_base_input_without_fees(
+    source_amount: u64,
+    pool_source_amount: u64,
+    pool_destination_amount: u64,
+) -> Result<u64> {
+    let denominator = pool_source_amount as u128 + source_amount as u128;
+    if source_amount == 0 || denominator == 0 {
+        bail!("Input amount too small");
+    }
+    Ok((source_amount as u128 * pool_destination_amount as u128 / denominator) as u64)
+}
+
+/// Input to lock so the pool keeps its original ratio after the swap, and whether the
+/// ratio still moves by more than the tolerance
+fn rebalance_pool_ratio(
+    to_amount: u64,
+    current_source: u64,
+    current_destination: u64,
+    original_source: u64,
+    original_destination: u64,
+    ratio_change_tolerance_rate: u64,
+) -> (u64, bool) {
+    if to_amount >= current_destination || current_source == 0 || current_destination == 0 {
+        return (0, true);
+    }
+
+    let remaining_destination = current_destination - to_amount;
+    let original_ratio = original_source as f64 / original_destination as f64;
+
+    // the program tests the integers around the exact value, so the same is done here
+    let exact_from_to_lock = current_source as f64 - remaining_destination as f64 * original_ratio;
+    let start = (exact_from_to_lock - 1.0).max(0.0) as u64;
+    let end = (exact_from_to_lock + 1.0).min(current_source as f64) as u64;
+
+    let mut best_from_to_lock = 0;
+    let mut best_ratio_diff = f64::INFINITY;
+    for from_to_lock in start..=end {
+        if from_to_lock > current_source {
+            continue;
+        }
+        let new_ratio = (current_source - from_to_lock) as f64 / remaining_destination as f64;
+        let ratio_diff = (new_ratio - original_ratio).abs();
+        if ratio_diff < best_ratio_diff && new_ratio != 0.0 {
+            best_ratio_diff = ratio_diff;
+            best_from_to_lock = from_to_lock;
+        }
+    }
+
+    let new_ratio = (current_source - best_from_to_lock) as f64 / remaining_destination as f64;
+    let percentage_change = (new_ratio - original_ratio).abs() / original_ratio * 100.0;
+    let tolerance_percentage = ratio_change_tolerance_rate as f64 / MAX_PERCENTAGE as f64 * 100.0;
+
+    (best_from_to_lock, percentage_change > tolerance_percentage)
+}
+
+/// Fail when the input to lock doesn't fit in the available input reserve
+fn check_from_to_lock(from_to_lock: u64, available_in: u64, is_swap_x_to_y: bool) -> Result<()> {
+    // the program allows locking all of Y but not all of X
+    if from_to_lock > available_in || (is_swap_x_to_y && from_to_lock == available_in) {
+        bail!("Insufficient pool balance");
+    }
+    Ok(())
+}
+
+/// Quote an exact-in swap against a pool snapshot, fails like the program would
+pub fn quote(
+    snapshot: &PoolSnapshot,
+    amount_in: u64,
+    is_swap_x_to_y: bool,
+) -> Result<OfflineQuote> {
+    if amount_in == 0 {
+        bail!("Input amount too small");
+    }
+
+    let (total_x, total_y) = snapshot.total_reserves()?;
+    let (available_x, available_y) = snapshot.available_reserves()?;
+    let (available_in, available_out, total_in, total_out) = if is_swap_x_to_y {
+        (available_x, available_y, total_x, total_y)
+    } else {
+        (available_y, available_x, total_y, total_x)
+    };
+
+    let trade_fee = get_trade_fee(amount_in, snapshot.trade_fee_rate);
+    let protocol_fee = get_protocol_fee(trade_fee, snapshot.protocol_fee_rate);
+    // a trade fee rate above MAX_PERCENTAGE would take more than the input
+    let Some(from_amount) = amount_in.checked_sub(trade_fee) else {
+        bail!("Trade fee is larger than the input amount");
+    };
+    // nothing is left to swap once the rounded up fee is taken
+    if from_amount == 0 {
+        bail!("Input amount too small");
+    }
+    let to_amount = swap_base_input_without_fees(from_amount, available_in, available_out)?;
+
+    let (from_to_lock, is_rate_tolerance_exceeded) = rebalance_pool_ratio(
+        to_amount,
+        available_in,
+        available_out,
+        total_in,
+        total_out,
+        snapshot.ratio_change_tolerance_rate,
+    );
+    if is_rate_tolerance_exceeded {
+        bail!("Trade too big, the pool ratio would change beyond its tolerance");
+    }
+    check_from_to_lock(from_to_lock, available_in, is_swap_x_to_y)?;
+    if to_amount == 0 {
+        bail!("Output is zero");
+    }
+
+    Ok(OfflineQuote {
+        from_amount,
+        to_amount,
+        trade_fee,
+        protocol_fee,
+        from_to_lock,
+    })
+}
+
+/// Smallest exact-in swap whose output is at least `amount_out`
+///
+/// The program only supports exact-in swaps, so the input is searched for: doubled until
+/// it buys enough, then bisected.
+pub fn quote_exact_out(
+    snapshot: &PoolSnapshot,
+    amount_out: u64,
+    is_swap_x_to_y: bool,
+) -> Result<OfflineQuote> {
+    let buys_enough = |amount_in: u64| {
+        quote(snapshot, amount_in, is_swap_x_to_y).is_ok_and(|quote| quote.to_amount >= amount_out)
+    };
+
+    let mut high = 1u64;
+    while !buys_enough(high) {
+        high = match high.checked_mul(2) {
+            Some(high) => high,
+            None => bail!("No swap into this pool pays out {}", amount_out),
+        };
+    }
+
+    let mut low = high / 2 + 1;
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if buys_enough(mid) {
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+
+    quote(snapshot, high, is_swap_x_to_y)
+}
+
+/// Price impact of a swap in basis points: how much less than the spot price the
+/// input after fees buys
+pub fn price_impact_bps(
+    snapshot: &PoolSnapshot,
+    amount_in: u64,
+    is_swap_x_to_y: bool,
+) -> Result<f64> {
+    let quote = quote(snapshot, amount_in, is_swap_x_to_y)?;
+    let spot_out = quote.from_amount as f64 * snapshot.spot_price(is_swap_x_to_y)?;
+    Ok((1.0 - quote.to_amount as f64 / spot_out) * 10_000.0)
+}
+
+/// Largest exact-in swap whose price impact stays within `max_impact_bps`, with its input
+/// amount, `None` when no swap into this pool does
+///
+/// Tiny swaps show a large impact from the output rounding down, so the input is doubled
+/// until it fits, then until it doesn't anymore, and the last step is bisected.
+pub fn max_swap_within_impact(
+    snapshot: &PoolSnapshot,
+    is_swap_x_to_y: bool,
+    max_impact_bps: f64,
+) -> Result<Option<(u64, OfflineQuote)>> {
+    let fits = |amount_in: u64| {
+        price_impact_bps(snapshot, amount_in, is_swap_x_to_y)
+            .is_ok_and(|impact| impact <= max_impact_bps)
+    };
+
+    let mut low = 1u64;
+    while !fits(low) {
+        low = match low.checked_mul(2) {
+            Some(low) => low,
+            None => return Ok(None),
+        };
+    }
+
+    let mut high = low.saturating_mul(2);
+    while high < u64::MAX && fits(high) {
+        low = high;
+        high = high.saturating_mul(2);
+    }
+    // low fits and high doesn't
+    while high - low > 1 {
+        let mid = low + (high - low) / 2;
+        if fits(mid) {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+
+    Ok(Some((low, quote(snapshot, low, is_swap_x_to_y)?)))
+}
+
+/// Split of a large swap into equal chunks
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkPlan {
+    pub chunks: u64,
+    pub chunk_size: u64,
+    /// Smaller remainder swapped as the last chunk, 0 when the total splits evenly
+    pub last_chunk_size: u64,
+    pub chunk_impact_bps: f64,
+}
+
+/// Fewest equal chunks of `total_in` whose price impact stays under `max_impact_bps`
+///
+/// Every chunk is quoted against the same snapshot, i.e. assuming the pool price
+/// recovers between chunks as it does when they are spread out over time.
+pub fn plan_chunks(
+    snapshot: &PoolSnapshot,
+    total_in: u64,
+    is_swap_x_to_y: bool,
+    max_impact_bps: f64,
+    max_chunks: u64,
+) -> Result<ChunkPlan> {
+    let max_chunks = max_chunks.min(total_in);
+    let chunk_size = |chunks: u64| total_in.div_ceil(chunks);
+    let fits = |chunks: u64| {
+        price_impact_bps(snapshot, chunk_size(chunks), is_swap_x_to_y)
+            .is_ok_and(|impact| impact <= max_impact_bps)
+    };
+
+    // Fewer chunks would each be larger than the largest swap within the impact. The output
+    // rounding down makes the impact of smaller swaps jump around, so the count isn't taken
+    // for granted: it goes up until its chunks are checked to fit.
+    let fewest_chunks = match max_swap_within_impact(snapshot, is_swap_x_to_y, max_impact_bps)? {
+        Some((max_in, _)) => total_in.div_ceil(max_in).max(1),
+        None => u64::MAX,
+    };
+    let Some(chunks) = (fewest_chunks..=max_chunks).find(|&chunks| fits(chunks)) else {
+        bail!(
+            "Price impact stays above {} bps even with {} chunks",
+            max_impact_bps,
+            max_chunks
+        );
+    };
+
+    let size = chunk_size(chunks);
+    let full_chunks = total_in / size;
+    let last_chunk_size = total_in - full_chunks * size;
+    Ok(ChunkPlan {
+        chunks: full_chunks + u64::from(last_chunk_size > 0),
+        chunk_size: size,
+        last_chunk_size,
+        chunk_impact_bps: price_impact_bps(snapshot, size, is_swap_x_to_y)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Expected values below were recorded from dex-math 0.1.5, the math behind sdk.quote()
+
+    /// 0.3% trade fee, 20% of it to the protocol, 5% ratio change tolerance
+    fn snapshot() -> PoolSnapshot {
+        PoolSnapshot {
+            reserve_x_balance: 1_000_000_000,
+            reserve_y_balance: 2_000_000_000,
+            protocol_fee_x: 1_000,
+            protocol_fee_y: 2_000,
+            locked_x: 0,
+            locked_y: 0,
+            user_locked_x: 5_000,
+            user_locked_y: 0,
+            trade_fee_rate: 3_000,
+            protocol_fee_rate: 200_000,
+            ratio_change_tolerance_rate: 50_000,
+        }
+    }
+
+    /// Same rates, with 40% of X locked so the available ratio is off the total one
+    fn locked_snapshot() -> PoolSnapshot {
+        PoolSnapshot {
+            reserve_x_balance: 1_000_000_000,
+            reserve_y_balance: 1_000_000_000,
+            protocol_fee_x: 0,
+            protocol_fee_y: 0,
+            locked_x: 400_000_000,
+            user_locked_x: 0,
+            ..snapshot()
+        }
+    }
+
+    #[test]
+    fn trade_fee_rounds_up() {
+        assert_eq!(get_trade_fee(1_000, 3_000), 3);
+        assert_eq!(get_trade_fee(333, 3_000), 1);
+        assert_eq!(get_trade_fee(1, 3_000), 1);
+        assert_eq!(get_trade_fee(0, 3_000), 0);
+    }
+
+    #[test]
+    fn protocol_fee_rounds_down() {
+        assert_eq!(get_protocol_fee(3, 200_000), 0);
+        assert_eq!(get_protocol_fee(9, 200_000), 1);
+        assert_eq!(get_protocol_fee(3_000, 200_000), 600);
+    }
+
+    #[test]
+    fn swap_without_fees_rounds_down() {
+        assert_eq!(
+            swap_base_input_without_fees(997_000, 999_994_000, 1_999_998_000).unwrap(),
+            1_992_023
+        );
+        assert!(swap_base_input_without_fees(0, 999_994_000, 1_999_998_000).is_err());
+        assert!(swap_base_input_without_fees(0, 0, 1_000).is_err());
+    }
+
+    #[test]
+    fn quote_matches_dex_math() {
+        let snapshot = snapshot();
+        assert_eq!(
+            quote(&snapshot, 1_000_000, true).unwrap(),
+            OfflineQuote {
+                from_amount: 997_000,
+                to_amount: 1_992_023,
+                trade_fee: 3_000,
+                protocol_fee: 600,
+                from_to_lock: 996_007,
+            }
+        );
+        assert_eq!(
+            quote(&snapshot, 250_000_000, true).unwrap(),
+            OfflineQuote {
+                from_amount: 249_250_000,
+                to_amount: 399_040_941,
+                trade_fee: 750_000,
+                protocol_fee: 150_000,
+                from_to_lock: 199_519_473,
+            }
+        );
+        assert_eq!(
+            quote(&snapshot, 2_500_000, false).unwrap(),
+            OfflineQuote {
+                from_amount: 2_492_500,
+                to_amount: 1_244_692,
+                trade_fee: 7_500,
+                protocol_fee: 1_500,
+                from_to_lock: 2_489_396,
+            }
+        );
+        assert_eq!(
+            quote(&locked_snapshot(), 1_000_000, false).unwrap(),
+            OfflineQuote {
+                from_amount: 997_000,
+                to_amount: 597_604,
+                trade_fee: 3_000,
+                protocol_fee: 600,
+                from_to_lock: 400_597_604,
+            }
+        );
+    }
+
+    #[test]
+    fn quote_rejects_dust_and_bad_fee_rates() {
+        let snapshot = snapshot();
+        assert!(quote(&snapshot, 0, true).is_err());
+        // the rounded up fee takes the whole input
+        let error = quote(&snapshot, 1, true).unwrap_err();
+        assert_eq!(error.to_string(), "Input amount too small");
+
+        let over_100_percent = PoolSnapshot {
+            trade_fee_rate: 2 * MAX_PERCENTAGE,
+            ..snapshot
+        };
+        assert!(quote(&over_100_percent, 1_000, true).is_err());
+    }
+
+    #[test]
+    fn quote_bails_beyond_ratio_tolerance() {
+        let error = quote(&locked_snapshot(), 1_000_000, true).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Trade too big, the pool ratio would change beyond its tolerance"
+        );
+    }
+
+    #[test]
+    fn from_to_lock_must_fit_the_available_input() {
+        assert!(check_from_to_lock(999, 1_000, true).is_ok());
+        assert!(check_from_to_lock(1_000, 1_000, true).is_err());
+        assert!(check_from_to_lock(1_000, 1_000, false).is_ok());
+        assert!(check_from_to_lock(1_001, 1_000, false).is_err());
+    }
+
+    #[test]
+    fn quote_exact_out_finds_the_smallest_input() {
+        let snapshot = snapshot();
+        for (amount_out, is_swap_x_to_y, expected_in) in [
+            (1_992_023, true, 1_000_000),
+            (1_000_000, true, 501_754),
+            (1_000, false, 2_008),
+        ] {
+            let quote = quote_exact_out(&snapshot, amount_out, is_swap_x_to_y).unwrap();
+            assert_eq!(quote.from_amount + quote.trade_fee, expected_in);
+            assert!(quote.to_amount >= amount_out);
+        }
+        assert!(quote_exact_out(&snapshot, 2_000_000_000, true).is_err());
+    }
+
+    #[test]
+    fn plan_chunks_takes_the_fewest_fitting_chunks() {
+        let snapshot = snapshot();
+        let plan = plan_chunks(&snapshot, 250_000_000, true, 100.0, 100).unwrap();
+        assert!(plan.chunk_impact_bps <= 100.0);
+        assert!(plan.chunks > 1);
+        let fewer = 250_000_000u64.div_ceil(plan.chunks - 1);
+        assert!(price_impact_bps(&snapshot, fewer, true).unwrap() > 100.0);
+
+        assert!(plan_chunks(&snapshot, 250_000_000, true, 100.0, 2).is_err());
+    }
+}
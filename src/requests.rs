@@ -0,0 +1,229 @@
+//! Builders over the SDK's `SwapParamsIx` / `FinalizeParamsIx`
+//!
+//! The SDK params are plain structs with every field to fill in by hand. The builders
+//! default what can be derived: a random salt, the min out from a quote and a slippage,
+//! the settler and the order values, and they accept the SOL placeholder mint for WSOL.
+//!
+//! ```ignore
+//! let swap_params = SwapRequest::builder()
+//!     .from(SOL_MINT)
+//!     .to(token_mint)
+//!     .amount_ui(1.5)
+//!     .slippage_bps(50)
+//!     .authority(user)
+//!     .to_params(&mut sdk, &rpc_client)
+//!     .await?;
+//! let finalize_params = FinalizeRequest::for_swap(&swap_params, &order).to_params(&rpc_client)?;
+//! ```
+
+use crate::amount::TokenAmount;
+use crate::utils::{get_token_infos, to_pool_mint};
+use anyhow::{Context, Result, anyhow, bail};
+use darklake_sdk_on_chain::{DarklakeSDK, FinalizeParamsIx, Order, SwapMode, SwapParamsIx};
+use solana_rpc_client::rpc_client::RpcClient;
+use solana_sdk::{pubkey::Pubkey, signature::Keypair};
+
+/// Slippage applied to the quote when no min out is given
+pub const DEFAULT_SLIPPAGE_BPS: u64 = 50;
+
+/// Input amount of a swap request, in base units or in whole tokens
+#[derive(Debug, Clone, Copy)]
+enum Amount {
+    Raw(u64),
+    Ui(f64),
+}
+
+/// Exact-in swap to lower into `SwapParamsIx`
+#[derive(Debug, Clone)]
+pub struct SwapRequest {
+    token_mint_in: Option<Pubkey>,
+    token_mint_out: Option<Pubkey>,
+    amount: Option<Amount>,
+    authority: Option<Pubkey>,
+    min_out: Option<u64>,
+    slippage_bps: u64,
+    salt: [u8; 8],
+}
+
+impl SwapRequest {
+    /// A request with a random salt and `DEFAULT_SLIPPAGE_BPS`
+    pub fn builder() -> Self {
+        // no RNG crate is a dependency, a throwaway keypair's secret is random enough
+        let mut salt = [0; 8];
+        salt.copy_from_slice(&Keypair::new().to_bytes()[..8]);
+
+        Self {
+            token_mint_in: None,
+            token_mint_out: None,
+            amount: None,
+            authority: None,
+            min_out: None,
+            slippage_bps: DEFAULT_SLIPPAGE_BPS,
+            salt,
+        }
+    }
+
+    /// Input token, SOL can be given as the SOL placeholder or the WSOL mint (it has to be
+    /// wrapped before the swap, see `get_wrap_sol_to_wsol_instructions`)
+    pub fn from(mut self, token_mint: Pubkey) -> Self {
+        self.token_mint_in = Some(token_mint);
+        self
+    }
+
+    /// Output token, SOL can be given as the SOL placeholder or the WSOL mint
+    pub fn to(mut self, token_mint: Pubkey) -> Self {
+        self.token_mint_out = Some(token_mint);
+        self
+    }
+
+    /// Input amount in base units
+    pub fn amount(mut self, amount: u64) -> Self {
+        self.amount = Some(Amount::Raw(amount));
+        self
+    }
+
+    /// Input amount in whole tokens, converted with the mint's decimals
+    pub fn amount_ui(mut self, amount: f64) -> Self {
+        self.amount = Some(Amount::Ui(amount));
+        self
+    }
+
+    /// Owner of the input tokens, who signs the swap
+    pub fn authority(mut self, authority: Pubkey) -> Self {
+        self.authority = Some(authority);
+        self
+    }
+
+    /// Slippage below the quoted output the swap still accepts
+    pub fn slippage_bps(mut self, slippage_bps: u64) -> Self {
+        self.slippage_bps = slippage_bps;
+        self
+    }
+
+    /// Fixed min out instead of quoting, the slippage is ignored then
+    pub fn min_out(mut self, min_out: u64) -> Self {
+        self.min_out = Some(min_out);
+        self
+    }
+
+    /// Fixed salt instead of a random one, e.g. to rebuild the params of an open order
+    pub fn salt(mut self, salt: [u8; 8]) -> Self {
+        self.salt = salt;
+        self
+    }
+
+    /// Lower the request into the SDK params, quoting the pool for the min out if needed
+    pub async fn to_params(
+        &self,
+        sdk: &mut DarklakeSDK,
+        rpc_client: &RpcClient,
+    ) -> Result<SwapParamsIx> {
+        let token_mint_in = to_pool_mint(
+            &self
+                .token_mint_in
+                .context("Swap request has no input token")?,
+        );
+        let token_mint_out = to_pool_mint(
+            &self
+                .token_mint_out
+                .context("Swap request has no output token")?,
+        );
+        let authority = self.authority.context("Swap request has no authority")?;
+
+        let [token_in, token_out] = <[_; 2]>::try_from(get_token_infos(
+            rpc_client,
+            &[token_mint_in, token_mint_out],
+        )?)
+        .map_err(|_| anyhow!("Unexpected number of token mints returned"))?;
+
+        let amount_in = match self.amount.context("Swap request has no amount")? {
+            Amount::Raw(amount) => token_in.amount(amount),
+            Amount::Ui(amount) => TokenAmount::from_ui(token_mint_in, token_in.decimals, amount)?,
+        };
+        if amount_in.raw() == 0 {
+            bail!("Swap amount rounds to 0 base units");
+        }
+
+        let min_out = match self.min_out {
+            Some(min_out) => min_out,
+            None => {
+                let quote = sdk
+                    .quote(&token_mint_in, &token_mint_out, amount_in.raw())
+                    .await?;
+                token_out
+                    .amount(quote.out_amount)
+                    .less_bps(self.slippage_bps)?
+                    .raw()
+                    .max(1)
+            }
+        };
+
+        Ok(SwapParamsIx {
+            source_mint: token_mint_in,
+            destination_mint: token_mint_out,
+            token_transfer_authority: authority,
+            amount_in: amount_in.raw(),
+            swap_mode: SwapMode::ExactIn,
+            min_out,
+            salt: self.salt,
+        })
+    }
+}
+
+/// Finalize of an open order to lower into `FinalizeParamsIx`
+#[derive(Clone)]
+pub struct FinalizeRequest {
+    order_owner: Pubkey,
+    settle_signer: Pubkey,
+    unwrap_wsol: bool,
+    min_out: u64,
+    salt: [u8; 8],
+    order: Order,
+}
+
+impl FinalizeRequest {
+    /// Finalize by the swap's authority of the order the swap opened, with the same min out
+    /// and salt, unwrapping WSOL output
+    pub fn for_swap(swap_params: &SwapParamsIx, order: &Order) -> Self {
+        Self {
+            order_owner: swap_params.token_transfer_authority,
+            settle_signer: swap_params.token_transfer_authority,
+            unwrap_wsol: to_pool_mint(&swap_params.destination_mint) == spl_token::native_mint::ID,
+            min_out: swap_params.min_out,
+            salt: swap_params.salt,
+            order: order.clone(),
+        }
+    }
+
+    /// Settle with another signer, e.g. a keeper earning the settlement reward
+    pub fn settler(mut self, settle_signer: Pubkey) -> Self {
+        self.settle_signer = settle_signer;
+        self
+    }
+
+    /// Keep WSOL output wrapped instead of unwrapping it to SOL
+    pub fn unwrap_wsol(mut self, unwrap_wsol: bool) -> Self {
+        self.unwrap_wsol = unwrap_wsol;
+        self
+    }
+
+    /// Lower the request into the SDK params at the current slot
+    pub fn to_params(&self, rpc_client: &RpcClient) -> Result<FinalizeParamsIx> {
+        Ok(self.to_params_at(rpc_client.get_slot().context("Failed to get slot")?))
+    }
+
+    /// Lower the request into the SDK params at a slot fetched by the caller
+    pub fn to_params_at(&self, current_slot: u64) -> FinalizeParamsIx {
+        FinalizeParamsIx {
+            settle_signer: self.settle_signer,
+            order_owner: self.order_owner,
+            unwrap_wsol: self.unwrap_wsol,
+            min_out: self.min_out,
+            salt: self.salt,
+            output: self.order.d_out,
+            commitment: self.order.c_min,
+            deadline: self.order.deadline,
+            current_slot,
+        }
+    }
+}
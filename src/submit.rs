@@ -0,0 +1,427 @@
+//! Backends that get signed transactions to the cluster (`--submit-via`)
+//!
+//! Every transaction goes through `send_and_confirm`, which hands it to the configured
+//! `TxSubmitter` and then waits for the confirmation, so most backends only have to get
+//! the transaction accepted: by the RPC endpoint or as a Jito bundle. Fan-out sends to
+//! several endpoints at once and waits for the first of them to see the confirmation.
+
+use crate::utils::{record_stats, timeouts};
+use anyhow::{Context, Result, anyhow};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use solana_rpc_client::rpc_client::RpcClient;
+use solana_rpc_client_api::{config::RpcSendTransactionConfig, request::RpcRequest};
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+    transaction::{Result as TransactionResult, Transaction, VersionedTransaction},
+};
+use solana_system_interface::instruction::transfer;
+use std::collections::BTreeMap;
+use std::fs;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock, mpsc};
+use std::time::{Duration, Instant};
+
+/// Tip paid with every Jito bundle when `--jito-tip` isn't given, the block engine's minimum
+pub const DEFAULT_JITO_TIP: u64 = 1_000;
+
+// How often each fan-out endpoint checks the signature status, about half a slot
+const FAN_OUT_POLL_MS: u64 = 200;
+
+/// Tip transaction sent along with a transaction
+#[derive(Debug, Clone, Copy)]
+pub struct Tip {
+    pub signature: Signature,
+    pub lamports: u64,
+}
+
+/// A transaction accepted by a backend
+#[derive(Debug, Clone)]
+pub struct Submitted {
+    pub signature: Signature,
+    pub tip: Option<Tip>,
+    /// Outcome of the transaction if the backend waited for its confirmation, `None` if
+    /// it's still to be confirmed
+    pub status: Option<TransactionResult<()>>,
+}
+
+/// A way to send signed transactions
+pub trait TxSubmitter: Send + Sync {
+    /// Name given to `--submit-via`
+    fn name(&self) -> &'static str;
+
+    /// Whether `submit` waits for the confirmation itself, then `send_and_confirm`
+    /// doesn't subscribe to the signature
+    fn waits_for_confirmation(&self) -> bool {
+        false
+    }
+
+    /// Send a signed transaction, returning once it was accepted, or once it confirmed for
+    /// backends that wait for the confirmation
+    fn submit(
+        &self,
+        rpc_client: &RpcClient,
+        transaction: &VersionedTransaction,
+        config: RpcSendTransactionConfig,
+    ) -> Result<Submitted>;
+}
+
+/// Send to the RPC endpoint every command uses, the default
+pub struct RpcSubmitter;
+
+impl TxSubmitter for RpcSubmitter {
+    fn name(&self) -> &'static str {
+        "rpc"
+    }
+
+    fn submit(
+        &self,
+        rpc_client: &RpcClient,
+        transaction: &VersionedTransaction,
+        config: RpcSendTransactionConfig,
+    ) -> Result<Submitted> {
+        Ok(Submitted {
+            signature: rpc_client.send_transaction_with_config(transaction, config)?,
+            tip: None,
+            status: None,
+        })
+    }
+}
+
+// with --record-stats, fan-out records how each endpoint did here, relative to the working
+// directory
+pub const ENDPOINT_METRICS_PATH: &str = "endpoints.json";
+
+/// How an endpoint did on the transactions fanned out to it
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EndpointMetrics {
+    pub sent: u64,
+    pub accepted: u64,
+    /// Times it reported the confirmation before every other endpoint
+    pub first_confirmed: u64,
+    /// Sum of the times from sending to those first confirmations
+    pub first_confirmed_ms: u64,
+}
+
+impl EndpointMetrics {
+    /// Share of the transactions sent to it that it confirmed first
+    pub fn win_rate(&self) -> f64 {
+        self.first_confirmed as f64 / self.sent.max(1) as f64
+    }
+
+    pub fn accept_rate(&self) -> f64 {
+        self.accepted as f64 / self.sent.max(1) as f64
+    }
+
+    /// Average time to its first confirmations, `None` before its first one
+    pub fn average_confirm_ms(&self) -> Option<u64> {
+        (self.first_confirmed > 0).then(|| self.first_confirmed_ms / self.first_confirmed)
+    }
+}
+
+/// Metrics of every endpoint fan-out has sent to, empty before the first fan-out
+pub fn read_endpoint_metrics() -> Result<BTreeMap<String, EndpointMetrics>> {
+    let contents = match fs::read_to_string(ENDPOINT_METRICS_PATH) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e).context(format!("Failed to read {}", ENDPOINT_METRICS_PATH)),
+    };
+    let json: serde_json::Value = serde_json::from_str(&contents)
+        .with_context(|| format!("Invalid JSON in {}", ENDPOINT_METRICS_PATH))?;
+
+    let field = |metrics: &serde_json::Value, name: &str| metrics[name].as_u64().unwrap_or(0);
+    Ok(json
+        .as_object()
+        .with_context(|| format!("{} isn't a JSON object", ENDPOINT_METRICS_PATH))?
+        .iter()
+        .map(|(endpoint, metrics)| {
+            (
+                endpoint.clone(),
+                EndpointMetrics {
+                    sent: field(metrics, "sent"),
+                    accepted: field(metrics, "accepted"),
+                    first_confirmed: field(metrics, "first_confirmed"),
+                    first_confirmed_ms: field(metrics, "first_confirmed_ms"),
+                },
+            )
+        })
+        .collect())
+}
+
+fn write_endpoint_metrics(metrics: &BTreeMap<String, EndpointMetrics>) -> Result<()> {
+    let json: serde_json::Map<String, serde_json::Value> = metrics
+        .iter()
+        .map(|(endpoint, metrics)| {
+            (
+                endpoint.clone(),
+                serde_json::json!({
+                    "sent": metrics.sent,
+                    "accepted": metrics.accepted,
+                    "first_confirmed": metrics.first_confirmed,
+                    "first_confirmed_ms": metrics.first_confirmed_ms,
+                }),
+            )
+        })
+        .collect();
+    fs::write(ENDPOINT_METRICS_PATH, serde_json::to_string_pretty(&json)?)
+        .with_context(|| format!("Failed to write {}", ENDPOINT_METRICS_PATH))
+}
+
+/// Endpoints best first: most often confirmed first, then most often accepting, then
+/// fastest to confirm
+pub fn rank_endpoints(
+    metrics: BTreeMap<String, EndpointMetrics>,
+) -> Vec<(String, EndpointMetrics)> {
+    let mut ranking: Vec<_> = metrics.into_iter().collect();
+    ranking.sort_by(|(_, a), (_, b)| {
+        b.win_rate()
+            .total_cmp(&a.win_rate())
+            .then(b.accept_rate().total_cmp(&a.accept_rate()))
+            .then(
+                a.average_confirm_ms()
+                    .unwrap_or(u64::MAX)
+                    .cmp(&b.average_confirm_ms().unwrap_or(u64::MAX)),
+            )
+    });
+    ranking
+}
+
+/// What an endpoint of a fan-out reports back
+enum FanOutEvent {
+    Sent(usize, Result<()>),
+    Confirmed(usize, TransactionResult<()>, Duration),
+}
+
+/// Send to the RPC endpoint and to other endpoints at the same time, and take the
+/// confirmation from whichever endpoint sees it first
+pub struct FanOutSubmitter {
+    endpoints: Vec<String>,
+}
+
+impl FanOutSubmitter {
+    pub fn new(endpoints: Vec<String>) -> Self {
+        Self { endpoints }
+    }
+}
+
+impl TxSubmitter for FanOutSubmitter {
+    fn name(&self) -> &'static str {
+        "fan-out"
+    }
+
+    fn waits_for_confirmation(&self) -> bool {
+        true
+    }
+
+    /// Each endpoint polls its own signature status after accepting the transaction. The
+    /// first confirmation wins and the other endpoints stop polling. Fails with the RPC
+    /// endpoint's error when no endpoint accepts the transaction, and returns it
+    /// unconfirmed after the confirm timeout
+    fn submit(
+        &self,
+        rpc_client: &RpcClient,
+        transaction: &VersionedTransaction,
+        config: RpcSendTransactionConfig,
+    ) -> Result<Submitted> {
+        let endpoints: Vec<String> = std::iter::once(rpc_client.url())
+            .chain(self.endpoints.iter().cloned())
+            .collect();
+        let signature = transaction.signatures[0];
+        let started = Instant::now();
+        let deadline = started + timeouts().confirm;
+        let done = Arc::new(AtomicBool::new(false));
+
+        let (sender, receiver) = mpsc::channel();
+        for (index, endpoint) in endpoints.iter().enumerate() {
+            let client = RpcClient::new_with_timeout_and_commitment(
+                endpoint.clone(),
+                timeouts().rpc,
+                rpc_client.commitment(),
+            );
+            let (transaction, sender, done) = (transaction.clone(), sender.clone(), done.clone());
+            std::thread::spawn(move || {
+                let sent = client
+                    .send_transaction_with_config(&transaction, config)
+                    .map(|_| ())
+                    .map_err(anyhow::Error::from);
+                let accepted = sent.is_ok();
+                if sender.send(FanOutEvent::Sent(index, sent)).is_err() || !accepted {
+                    return;
+                }
+                // a processed status can still be rolled back, only count a confirmation
+                while !done.load(Ordering::Relaxed) && Instant::now() < deadline {
+                    if let Ok(Some(status)) = client.get_signature_status_with_commitment(
+                        &signature,
+                        CommitmentConfig::confirmed(),
+                    ) {
+                        let _ =
+                            sender.send(FanOutEvent::Confirmed(index, status, started.elapsed()));
+                        return;
+                    }
+                    std::thread::sleep(Duration::from_millis(FAN_OUT_POLL_MS));
+                }
+            });
+        }
+        drop(sender);
+
+        let mut metrics = if record_stats() {
+            read_endpoint_metrics().unwrap_or_else(|e| {
+                println!("Warning: resetting endpoint metrics ({:#})", e);
+                BTreeMap::new()
+            })
+        } else {
+            BTreeMap::new()
+        };
+        for endpoint in &endpoints {
+            metrics.entry(endpoint.clone()).or_default().sent += 1;
+        }
+
+        let mut rejected = 0;
+        let mut rpc_error = None;
+        let result = loop {
+            match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(FanOutEvent::Sent(index, Ok(_))) => {
+                    metrics
+                        .entry(endpoints[index].clone())
+                        .or_default()
+                        .accepted += 1;
+                }
+                Ok(FanOutEvent::Sent(index, Err(e))) => {
+                    println!(
+                        "Fan-out: {} rejected the transaction ({})",
+                        endpoints[index], e
+                    );
+                    if index == 0 {
+                        rpc_error = Some(e);
+                    }
+                    rejected += 1;
+                    if rejected == endpoints.len() {
+                        break Err(rpc_error
+                            .unwrap_or_else(|| anyhow!("No endpoint accepted the transaction")));
+                    }
+                }
+                Ok(FanOutEvent::Confirmed(index, status, elapsed)) => {
+                    println!(
+                        "Fan-out: {} confirmed the transaction first, after {:?}",
+                        endpoints[index], elapsed
+                    );
+                    let winner = metrics.entry(endpoints[index].clone()).or_default();
+                    winner.first_confirmed += 1;
+                    winner.first_confirmed_ms += elapsed.as_millis() as u64;
+                    break Ok(Some(status));
+                }
+                // left to `send_and_confirm`, which gives up as its deadline passed too
+                Err(_) => break Ok(None),
+            }
+        };
+        done.store(true, Ordering::Relaxed);
+
+        if record_stats()
+            && let Err(e) = write_endpoint_metrics(&metrics)
+        {
+            println!("Warning: {:#}", e);
+        }
+        result.map(|status| Submitted {
+            signature,
+            tip: None,
+            status,
+        })
+    }
+}
+
+/// Send each transaction as a Jito bundle with a tip transaction paid by `tip_payer`
+pub struct JitoSubmitter {
+    /// JSON-RPC endpoint of the block engine's bundles API
+    bundles_url: String,
+    tip_payer: Keypair,
+    tip: u64,
+}
+
+impl JitoSubmitter {
+    /// `block_engine_url` without a path, e.g. `https://ny.testnet.block-engine.jito.wtf`
+    pub fn new(block_engine_url: &str, tip_payer: Keypair, tip: u64) -> Self {
+        Self {
+            bundles_url: format!("{}/api/v1/bundles", block_engine_url.trim_end_matches('/')),
+            tip_payer,
+            tip,
+        }
+    }
+}
+
+impl TxSubmitter for JitoSubmitter {
+    fn name(&self) -> &'static str {
+        "jito"
+    }
+
+    /// The block engine simulates bundles itself, the preflight config doesn't apply
+    fn submit(
+        &self,
+        _rpc_client: &RpcClient,
+        transaction: &VersionedTransaction,
+        _config: RpcSendTransactionConfig,
+    ) -> Result<Submitted> {
+        let block_engine = RpcClient::new_with_timeout(self.bundles_url.clone(), timeouts().rpc);
+
+        let tip_accounts: Vec<String> = block_engine
+            .send(
+                RpcRequest::Custom {
+                    method: "getTipAccounts",
+                },
+                serde_json::json!([]),
+            )
+            .context("Failed to get Jito tip accounts")?;
+        let tip_account = Pubkey::from_str(
+            tip_accounts
+                .first()
+                .context("Block engine returned no tip accounts")?,
+        )?;
+
+        // same blockhash, so the tip can't land without the transaction
+        let tip_transaction = Transaction::new_signed_with_payer(
+            &[transfer(&self.tip_payer.pubkey(), &tip_account, self.tip)],
+            Some(&self.tip_payer.pubkey()),
+            &[&self.tip_payer],
+            *transaction.message.recent_blockhash(),
+        );
+
+        let bundle = [
+            STANDARD.encode(bincode::serialize(transaction)?),
+            STANDARD.encode(bincode::serialize(&tip_transaction)?),
+        ];
+        let bundle_id: String = block_engine
+            .send(
+                RpcRequest::Custom {
+                    method: "sendBundle",
+                },
+                serde_json::json!([bundle, { "encoding": "base64" }]),
+            )
+            .context("Failed to send Jito bundle")?;
+        println!("Sent Jito bundle {}", bundle_id);
+
+        Ok(Submitted {
+            signature: transaction.signatures[0],
+            tip: Some(Tip {
+                signature: tip_transaction.signatures[0],
+                lamports: self.tip,
+            }),
+            status: None,
+        })
+    }
+}
+
+static TX_SUBMITTER: OnceLock<Box<dyn TxSubmitter>> = OnceLock::new();
+
+/// Set the backend of every transaction sent, once at startup
+pub fn set_tx_submitter(submitter: Box<dyn TxSubmitter>) {
+    let _ = TX_SUBMITTER.set(submitter);
+}
+
+/// The configured backend, `RpcSubmitter` if none was set
+pub fn tx_submitter() -> &'static dyn TxSubmitter {
+    match TX_SUBMITTER.get() {
+        Some(submitter) => submitter.as_ref(),
+        None => &RpcSubmitter,
+    }
+}
@@ -1,19 +1,866 @@
+use crate::amount::TokenAmount;
+use crate::math::{self, OfflineQuote, PoolSnapshot};
+use crate::submit::tx_submitter;
 use darklake_sdk_on_chain::{DarklakeSDK, Order};
-use tokio::time::{Duration, sleep};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, mpsc};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::time::{Duration, Instant, sleep};
 
-use anyhow::{Context, Result};
-use solana_rpc_client::rpc_client::RpcClient;
+use anyhow::{Context, Result, anyhow, bail};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use solana_account_decoder_client_types::UiAccountEncoding;
+use solana_pubsub_client::pubsub_client::{
+    PubsubAccountClientSubscription, PubsubClient, SignatureSubscription,
+};
+use solana_rpc_client::{
+    nonblocking::rpc_client::RpcClient as NonblockingRpcClient,
+    rpc_client::{GetConfirmedSignaturesForAddress2Config, RpcClient, SerializableTransaction},
+};
+use solana_rpc_client_api::{
+    client_error::{Error as ClientError, ErrorKind as ClientErrorKind},
+    config::{
+        RpcAccountInfoConfig, RpcSendTransactionConfig, RpcSignatureSubscribeConfig,
+        RpcSimulateTransactionAccountsConfig, RpcSimulateTransactionConfig, RpcTransactionConfig,
+    },
+    request::{RpcError, RpcResponseErrorData},
+    response::RpcSignatureResult,
+};
 use solana_sdk::{
+    account::Account,
     address_lookup_table::AddressLookupTableAccount,
-    address_lookup_table::state::AddressLookupTable, instruction::Instruction, pubkey::Pubkey,
-    signature::Keypair, signer::Signer, transaction::Transaction,
+    address_lookup_table::state::AddressLookupTable,
+    clock::DEFAULT_MS_PER_SLOT,
+    commitment_config::CommitmentConfig,
+    compute_budget::ComputeBudgetInstruction,
+    hash::{Hash, hashv},
+    instruction::{Instruction, InstructionError},
+    message::{Message, VersionedMessage, v0},
+    native_token::lamports_to_sol,
+    packet::PACKET_DATA_SIZE,
+    program_pack::Pack,
+    pubkey,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+    signer::{Signer, keypair::keypair_from_seed},
+    transaction::{Transaction, TransactionError, VersionedTransaction},
 };
 use solana_system_interface::instruction::{create_account, transfer};
-use spl_associated_token_account::get_associated_token_address;
+use solana_transaction_status::{
+    UiLoadedAddresses, UiTransactionEncoding, UiTransactionTokenBalance,
+};
+use spl_associated_token_account::{
+    get_associated_token_address, get_associated_token_address_with_program_id,
+};
 use spl_token::{
-    instruction::{close_account, initialize_mint, mint_to, sync_native},
     native_mint,
+    state::{Account as TokenAccount, Mint},
 };
+// the SPL Token builders reject Token-2022, these build instructions for either program
+use spl_token_2022::instruction::{burn, close_account, initialize_mint, mint_to, sync_native};
+use url::Url;
+
+static NON_INTERACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Disable prompts, for containers and batch jobs (`--non-interactive`)
+pub fn set_non_interactive(non_interactive: bool) {
+    NON_INTERACTIVE.store(non_interactive, Ordering::Relaxed);
+}
+
+/// Prompts are only shown on a terminal unless `--non-interactive` was given
+pub fn is_interactive() -> bool {
+    !NON_INTERACTIVE.load(Ordering::Relaxed) && std::io::stdout().is_terminal()
+}
+
+/// Timeouts of the individual operations (`--rpc-timeout`, `--confirm-timeout`,
+/// `--order-timeout`, `--ws-timeout`, in seconds)
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    /// Single RPC request
+    pub rpc: Duration,
+    /// Waiting for a sent transaction to be confirmed
+    pub confirm: Duration,
+    /// Waiting for a swap's order account to become readable
+    pub order: Duration,
+    /// Silence on a WebSocket subscription before falling back to polling, or before
+    /// checking a signature status while waiting for its notification
+    pub websocket: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            rpc: Duration::from_secs(30),
+            confirm: Duration::from_secs(60),
+            order: Duration::from_secs(25),
+            websocket: Duration::from_secs(10),
+        }
+    }
+}
+
+static RAW_AMOUNTS: AtomicBool = AtomicBool::new(false);
+
+/// Print token amounts as plain base unit integers, for scripts (`--raw`)
+pub fn set_raw_amounts(raw: bool) {
+    RAW_AMOUNTS.store(raw, Ordering::Relaxed);
+}
+
+/// Symbol and decimals a token's amounts are printed with
+#[derive(Debug, Clone)]
+pub struct TokenInfo {
+    pub mint: Pubkey,
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+impl TokenInfo {
+    /// `raw` base units of this token
+    pub fn amount(&self, raw: u64) -> TokenAmount {
+        TokenAmount::new(self.mint, self.decimals, raw)
+    }
+
+    /// Amount in whole tokens with thousands separators and the symbol, e.g. `1,234.5 SOL`,
+    /// or the untouched base units with `--raw`
+    pub fn format(&self, amount: u64) -> String {
+        if RAW_AMOUNTS.load(Ordering::Relaxed) {
+            return amount.to_string();
+        }
+
+        // from 20 decimals on the divisor doesn't fit a u64, and every u64 amount is a fraction
+        let (whole, remainder) = match 10u64.checked_pow(self.decimals as u32) {
+            Some(divisor) => (amount / divisor, amount % divisor),
+            None => (0, amount),
+        };
+        let whole = whole.to_string();
+        let mut grouped = String::new();
+        for (i, digit) in whole.chars().enumerate() {
+            if i > 0 && (whole.len() - i).is_multiple_of(3) {
+                grouped.push(',');
+            }
+            grouped.push(digit);
+        }
+
+        let fraction = format!("{:0width$}", remainder, width = self.decimals as usize);
+        let fraction = fraction.trim_end_matches('0');
+        if fraction.is_empty() {
+            format!("{} {}", grouped, self.symbol)
+        } else {
+            format!("{}.{} {}", grouped, fraction, self.symbol)
+        }
+    }
+
+    /// Signed difference between two amounts, e.g. `+1.5 SOL` or `-20 DdLx`
+    pub fn format_change(&self, before: u64, after: u64) -> String {
+        if after >= before {
+            format!("+{}", self.format(after - before))
+        } else {
+            format!("-{}", self.format(before - after))
+        }
+    }
+}
+
+/// Token programs owning `mints`, SPL Token or Token-2022
+///
+/// Token accounts of a mint live under its token program, so ATAs and token instructions of
+/// the mint have to use it.
+pub fn resolve_token_programs(rpc_client: &RpcClient, mints: &[Pubkey]) -> Result<Vec<Pubkey>> {
+    let mints: Vec<Pubkey> = mints.iter().map(to_pool_mint).collect();
+    let accounts = rpc_client
+        .get_multiple_accounts(&mints)
+        .context("Failed to get token mints")?;
+
+    mints
+        .iter()
+        .zip(accounts)
+        .map(|(mint, account)| {
+            let owner = account
+                .with_context(|| format!("Token mint {} not found", mint))?
+                .owner;
+            if owner != spl_token::ID && owner != spl_token_2022::ID {
+                bail!("{} isn't a token mint, it's owned by {}", mint, owner);
+            }
+            Ok(owner)
+        })
+        .collect()
+}
+
+/// Token program owning `mint`, SPL Token or Token-2022
+pub fn resolve_token_program(rpc_client: &RpcClient, mint: &Pubkey) -> Result<Pubkey> {
+    resolve_token_programs(rpc_client, std::slice::from_ref(mint))?
+        .pop()
+        .ok_or_else(|| anyhow!("Unexpected number of token mints returned"))
+}
+
+/// Base state of a Token or Token-2022 mint, Token-2022 extensions follow it
+pub fn unpack_mint(data: &[u8]) -> Result<Mint> {
+    Ok(Mint::unpack(
+        data.get(..Mint::LEN).context("Mint account is too small")?,
+    )?)
+}
+
+/// Base state of a Token or Token-2022 token account, Token-2022 extensions follow it
+pub fn unpack_token_account(data: &[u8]) -> Result<TokenAccount> {
+    Ok(TokenAccount::unpack(
+        data.get(..TokenAccount::LEN)
+            .context("Token account is too small")?,
+    )?)
+}
+
+/// Decimals and symbols of token mints
+///
+/// Token metadata isn't read, so mints other than SOL are named by the start of their address.
+pub fn get_token_infos(rpc_client: &RpcClient, mints: &[Pubkey]) -> Result<Vec<TokenInfo>> {
+    let mints: Vec<Pubkey> = mints.iter().map(to_pool_mint).collect();
+    let accounts = rpc_client
+        .get_multiple_accounts(&mints)
+        .context("Failed to get token mints")?;
+
+    mints
+        .iter()
+        .zip(accounts)
+        .map(|(mint, account)| {
+            let account = account.with_context(|| format!("Token mint {} not found", mint))?;
+            let symbol = if *mint == native_mint::ID {
+                "SOL".to_string()
+            } else {
+                mint.to_string()[..4].to_string()
+            };
+            Ok(TokenInfo {
+                mint: *mint,
+                symbol,
+                decimals: unpack_mint(&account.data)?.decimals,
+            })
+        })
+        .collect()
+}
+
+/// Balances of `owner`'s associated token accounts of `mints`, 0 for accounts that don't exist
+pub fn get_token_balances(
+    rpc_client: &RpcClient,
+    owner: &Pubkey,
+    mints: &[Pubkey],
+) -> Result<Vec<u64>> {
+    let token_accounts: Vec<Pubkey> = mints
+        .iter()
+        .zip(resolve_token_programs(rpc_client, mints)?)
+        .map(|(mint, token_program)| {
+            get_associated_token_address_with_program_id(owner, mint, &token_program)
+        })
+        .collect();
+
+    Ok(rpc_client
+        .get_multiple_accounts(&token_accounts)
+        .context("Failed to get token accounts")?
+        .iter()
+        .map(|account| {
+            account
+                .as_ref()
+                .and_then(|account| unpack_owned_token_account(account, owner))
+                .map_or(0, |token_account| token_account.amount)
+        })
+        .collect())
+}
+
+static TIMEOUTS: OnceLock<Timeouts> = OnceLock::new();
+
+/// Set the operation timeouts, once at startup
+pub fn set_timeouts(timeouts: Timeouts) {
+    let _ = TIMEOUTS.set(timeouts);
+}
+
+/// The configured operation timeouts, defaults if none were set
+pub fn timeouts() -> Timeouts {
+    TIMEOUTS.get().copied().unwrap_or_default()
+}
+
+/// Compute unit limit the runtime applies per instruction without a compute budget instruction
+pub const DEFAULT_CU_LIMIT: u32 = 200_000;
+
+/// Transaction kinds with their own compute budget defaults
+#[derive(Debug, Clone, Copy)]
+pub enum TxKind {
+    Swap,
+    Finalize,
+    AddLiquidity,
+    RemoveLiquidity,
+    InitPool,
+    CreateAccounts,
+}
+
+impl TxKind {
+    /// Compute unit limit set when `--cu-limit` isn't given, None leaves the runtime default
+    fn default_cu_limit(self) -> Option<u32> {
+        match self {
+            TxKind::Finalize | TxKind::InitPool => Some(500_000),
+            TxKind::Swap
+            | TxKind::AddLiquidity
+            | TxKind::RemoveLiquidity
+            | TxKind::CreateAccounts => None,
+        }
+    }
+
+    /// Compute unit limit the transaction runs with, for fee estimates
+    pub fn cu_limit(self) -> u32 {
+        compute_budget()
+            .cu_limit
+            .or(self.default_cu_limit())
+            .unwrap_or(DEFAULT_CU_LIMIT)
+    }
+}
+
+/// Compute budget overrides of every transaction (`--cu-limit`, `--cu-price` in micro-lamports)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ComputeBudget {
+    pub cu_limit: Option<u32>,
+    pub cu_price: Option<u64>,
+}
+
+static COMPUTE_BUDGET: OnceLock<ComputeBudget> = OnceLock::new();
+
+/// Set the compute budget overrides, once at startup
+pub fn set_compute_budget(compute_budget: ComputeBudget) {
+    let _ = COMPUTE_BUDGET.set(compute_budget);
+}
+
+fn compute_budget() -> ComputeBudget {
+    COMPUTE_BUDGET.get().copied().unwrap_or_default()
+}
+
+/// Prefix instructions with the compute budget instructions of their transaction kind
+pub fn with_compute_budget(kind: TxKind, instructions: Vec<Instruction>) -> Vec<Instruction> {
+    let compute_budget = compute_budget();
+    let mut all_instructions = Vec::new();
+    if let Some(cu_limit) = compute_budget.cu_limit.or(kind.default_cu_limit()) {
+        all_instructions.push(ComputeBudgetInstruction::set_compute_unit_limit(cu_limit));
+    }
+    if let Some(cu_price) = compute_budget.cu_price {
+        all_instructions.push(ComputeBudgetInstruction::set_compute_unit_price(cu_price));
+    }
+    all_instructions.extend(instructions);
+    all_instructions
+}
+
+static SKIP_SIMULATION: AtomicBool = AtomicBool::new(false);
+
+/// Send transactions without simulating them first (`--skip-simulation`), the RPC
+/// preflight check is skipped as well so failing transactions land on-chain
+pub fn set_skip_simulation(skip_simulation: bool) {
+    SKIP_SIMULATION.store(skip_simulation, Ordering::Relaxed);
+}
+
+/// Simulate a transaction and refuse to send it if it fails, so deterministic failures
+/// (slippage, missing token accounts, expired orders) don't cost fees
+///
+/// The error carries the failure class and the program logs of the simulation.
+fn simulate_before_send(
+    rpc_client: &RpcClient,
+    transaction: &impl SerializableTransaction,
+) -> Result<()> {
+    let simulation = rpc_client
+        .simulate_transaction_with_config(
+            transaction,
+            RpcSimulateTransactionConfig {
+                commitment: Some(rpc_client.commitment()),
+                ..RpcSimulateTransactionConfig::default()
+            },
+        )
+        .context("Failed to simulate transaction")?
+        .value;
+
+    match simulation.err {
+        Some(error) => Err(simulation_error(
+            error,
+            simulation.logs.unwrap_or_default(),
+            "Simulation failed, transaction not sent (--skip-simulation to send anyway)",
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Error of a failed simulation with its failure class and program logs
+fn simulation_error(error: TransactionError, logs: Vec<String>, message: &str) -> anyhow::Error {
+    let class = classify_simulation_failure(&error, &logs);
+    anyhow::Error::new(error)
+        .context(format!(
+            "{}\nProgram logs:\n  {}",
+            message,
+            logs.join("\n  ")
+        ))
+        .context(class)
+}
+
+/// State of `addresses` before and after simulating an unsigned message, `None` for
+/// accounts that don't exist
+pub fn simulate_account_changes(
+    rpc_client: &RpcClient,
+    message: &VersionedMessage,
+    addresses: &[Pubkey],
+) -> Result<Vec<(Option<Account>, Option<Account>)>> {
+    let before = rpc_client
+        .get_multiple_accounts(addresses)
+        .context("Failed to get accounts")?;
+
+    let transaction = VersionedTransaction {
+        signatures: vec![Signature::default(); message.header().num_required_signatures as usize],
+        message: message.clone(),
+    };
+    let simulation = rpc_client
+        .simulate_transaction_with_config(
+            &transaction,
+            RpcSimulateTransactionConfig {
+                sig_verify: false,
+                replace_recent_blockhash: true,
+                commitment: Some(rpc_client.commitment()),
+                accounts: Some(RpcSimulateTransactionAccountsConfig {
+                    encoding: Some(UiAccountEncoding::Base64),
+                    addresses: addresses.iter().map(|key| key.to_string()).collect(),
+                }),
+                ..RpcSimulateTransactionConfig::default()
+            },
+        )
+        .context("Failed to simulate transaction")?
+        .value;
+
+    if let Some(error) = simulation.err {
+        return Err(simulation_error(
+            error,
+            simulation.logs.unwrap_or_default(),
+            "Simulation failed",
+        ));
+    }
+
+    let after = simulation
+        .accounts
+        .unwrap_or_default()
+        .into_iter()
+        .map(|account| {
+            account
+                .and_then(|account| account.decode::<Account>())
+                .filter(|account| account.lamports > 0)
+        });
+    Ok(before.into_iter().zip(after).collect())
+}
+
+/// Send a transaction and wait for confirmation
+///
+/// The transaction is simulated first unless `--skip-simulation` was given. The
+/// confirmation is read from a signature subscription opened before sending, so it's seen
+/// as soon as the node notifies instead of on the next poll, which matters most for
+/// finalizes racing the order deadline. Without a WebSocket connection the signature
+/// status is polled. The fan-out backend watches its endpoints for the confirmation
+/// instead. Gives up after the confirm timeout.
+pub fn send_and_confirm(
+    rpc_client: &RpcClient,
+    transaction: &(impl SerializableTransaction + Clone + Into<VersionedTransaction>),
+) -> Result<Signature> {
+    let skip_simulation = SKIP_SIMULATION.load(Ordering::Relaxed);
+    if !skip_simulation {
+        simulate_before_send(rpc_client, transaction)?;
+    }
+    let send_config = RpcSendTransactionConfig {
+        skip_preflight: skip_simulation,
+        ..RpcSendTransactionConfig::default()
+    };
+
+    let deadline = std::time::Instant::now() + timeouts().confirm;
+    // subscribed before sending so a fast confirmation can't be missed, unless the
+    // backend watches for the confirmation itself
+    let subscription = if tx_submitter().waits_for_confirmation() {
+        None
+    } else {
+        Some(PubsubClient::signature_subscribe(
+            &get_websocket_url(&rpc_client.url()),
+            transaction.get_signature(),
+            Some(RpcSignatureSubscribeConfig {
+                commitment: Some(rpc_client.commitment()),
+                enable_received_notification: Some(false),
+            }),
+        ))
+    };
+
+    let submitted = tx_submitter().submit(rpc_client, &transaction.clone().into(), send_config)?;
+    let signature = submitted.signature;
+    // a tip is counted with the transaction it was paid for, its own fee separately
+    record_sent_transaction(&signature, submitted.tip.map_or(0, |tip| tip.lamports));
+    if let Some(tip) = submitted.tip {
+        record_sent_transaction(&tip.signature, 0);
+    }
+
+    if let Some(status) = submitted.status {
+        status?;
+        return Ok(signature);
+    }
+    match subscription {
+        Some(Ok(subscription)) => {
+            if wait_for_signature_notification(rpc_client, &signature, subscription, deadline)? {
+                return Ok(signature);
+            }
+            println!("Signature subscription closed, falling back to polling");
+        }
+        Some(Err(e)) => println!(
+            "Signature subscription failed ({}), falling back to polling",
+            e
+        ),
+        None => {}
+    }
+
+    loop {
+        if check_signature_status(rpc_client, &signature, deadline)? {
+            return Ok(signature);
+        }
+        std::thread::sleep(std::time::Duration::from_millis(500));
+    }
+}
+
+// With --record-stats, every sent transaction is appended here for costs_report, relative to
+// the working directory
+pub const COSTS_LEDGER_PATH: &str = "costs.jsonl";
+
+static RECORD_STATS: AtomicBool = AtomicBool::new(false);
+
+/// Record sent transactions in the costs ledger and fan-out results in the endpoint metrics
+/// (`--record-stats`), nothing is written to the working directory otherwise
+pub fn set_record_stats(record_stats: bool) {
+    RECORD_STATS.store(record_stats, Ordering::Relaxed);
+}
+
+pub fn record_stats() -> bool {
+    RECORD_STATS.load(Ordering::Relaxed)
+}
+
+static COMMAND: OnceLock<String> = OnceLock::new();
+
+/// Name of the running command, recorded with the transactions it sends
+pub fn set_command(command: &str) {
+    let _ = COMMAND.set(command.to_string());
+}
+
+/// Append a sent transaction to the costs ledger, only warning if that fails as the
+/// transaction is already out
+fn record_sent_transaction(signature: &Signature, tip: u64) {
+    if !record_stats() {
+        return;
+    }
+
+    let entry = serde_json::json!({
+        "timestamp": SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs()),
+        "command": COMMAND.get().map_or("unknown", String::as_str),
+        "signature": signature.to_string(),
+        "tip": tip,
+    });
+    let result = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(COSTS_LEDGER_PATH)
+        .and_then(|mut file| writeln!(file, "{}", entry));
+    if let Err(e) = result {
+        println!(
+            "Warning: failed to record transaction {} in {} ({})",
+            signature, COSTS_LEDGER_PATH, e
+        );
+    }
+}
+
+/// A transaction of the costs ledger
+#[derive(Debug, Clone)]
+pub struct SentTransaction {
+    pub timestamp: u64,
+    pub command: String,
+    pub signature: Signature,
+    /// Lamports tipped to land the transaction, e.g. with a Jito bundle
+    pub tip: u64,
+}
+
+/// Transactions recorded in the costs ledger, once each even if they were re-sent
+pub fn read_costs_ledger() -> Result<Vec<SentTransaction>> {
+    let contents = match fs::read_to_string(COSTS_LEDGER_PATH) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).context(format!("Failed to read {}", COSTS_LEDGER_PATH)),
+    };
+
+    let mut transactions: Vec<SentTransaction> = Vec::new();
+    let mut seen = HashSet::new();
+    for (index, line) in contents.lines().enumerate() {
+        let entry = serde_json::from_str::<serde_json::Value>(line)
+            .ok()
+            .and_then(|entry| {
+                Some(SentTransaction {
+                    timestamp: entry["timestamp"].as_u64()?,
+                    command: entry["command"].as_str()?.to_string(),
+                    signature: Signature::from_str(entry["signature"].as_str()?).ok()?,
+                    tip: entry["tip"].as_u64().unwrap_or(0),
+                })
+            })
+            .with_context(|| {
+                format!(
+                    "Invalid entry on line {} of {}",
+                    index + 1,
+                    COSTS_LEDGER_PATH
+                )
+            })?;
+        if seen.insert(entry.signature) {
+            transactions.push(entry);
+        }
+    }
+    Ok(transactions)
+}
+
+/// Whether a sent transaction is confirmed, failing if it failed or the deadline passed
+fn check_signature_status(
+    rpc_client: &RpcClient,
+    signature: &Signature,
+    deadline: std::time::Instant,
+) -> Result<bool> {
+    if let Some(status) =
+        rpc_client.get_signature_status_with_commitment(signature, rpc_client.commitment())?
+    {
+        status?;
+        return Ok(true);
+    }
+    if std::time::Instant::now() >= deadline {
+        bail!(
+            "Transaction {} not confirmed within {:?}",
+            signature,
+            timeouts().confirm
+        );
+    }
+    Ok(false)
+}
+
+/// Wait for the confirmation of a sent transaction on its signature subscription,
+/// `false` if the subscription closed first
+fn wait_for_signature_notification(
+    rpc_client: &RpcClient,
+    signature: &Signature,
+    (mut subscription, notifications): SignatureSubscription,
+    deadline: std::time::Instant,
+) -> Result<bool> {
+    let result = loop {
+        let wait = deadline
+            .saturating_duration_since(std::time::Instant::now())
+            .min(timeouts().websocket);
+        match notifications.recv_timeout(wait) {
+            Ok(notification) => {
+                if let RpcSignatureResult::ProcessedSignature(result) = notification.value {
+                    break match result.err {
+                        Some(error) => Err(error.into()),
+                        None => Ok(true),
+                    };
+                }
+            }
+            // the node can drop a notification, so the status is checked on every silence
+            Err(e) if e.is_timeout() => {
+                match check_signature_status(rpc_client, signature, deadline) {
+                    Ok(false) => {}
+                    result => break result,
+                }
+            }
+            Err(_) => break Ok(false),
+        }
+    };
+
+    let _ = subscription.shutdown();
+    result
+}
+
+/// Times a finalize is sent while it's unclear whether an earlier send landed
+const FINALIZE_SEND_ATTEMPTS: u32 = 3;
+
+/// Send a finalize, re-sending it after a timeout or RPC failure only if it can't have landed
+///
+/// The signature status and the order account are checked before each re-send, and the
+/// same signed transaction is re-sent, which the runtime executes at most once. Returns
+/// `None` when someone else settled the order in the meantime.
+pub fn send_finalize(
+    rpc_client: &RpcClient,
+    transaction: &VersionedTransaction,
+    order_address: &Pubkey,
+) -> Result<Option<Signature>> {
+    let signature = transaction.signatures[0];
+    let mut attempt = 1;
+    loop {
+        let error = match send_and_confirm(rpc_client, transaction) {
+            Ok(signature) => return Ok(Some(signature)),
+            Err(error) => error,
+        };
+        // program errors are deterministic, sending again would fail the same way
+        if !matches!(
+            classify_error(&error),
+            FailureClass::Rpc | FailureClass::Other
+        ) || attempt == FINALIZE_SEND_ATTEMPTS
+        {
+            return Err(error);
+        }
+
+        match rpc_client
+            .get_signature_status_with_commitment(&signature, rpc_client.commitment())
+            .context("Failed to get finalize status")?
+        {
+            Some(Ok(())) => {
+                println!("Finalize {} landed after all", signature);
+                return Ok(Some(signature));
+            }
+            Some(Err(transaction_error)) => {
+                return Err(anyhow::Error::new(transaction_error).context("Finalize failed"));
+            }
+            None => {}
+        }
+        if !check_order_open(rpc_client, order_address)? {
+            return Ok(None);
+        }
+        if !rpc_client
+            .is_blockhash_valid(
+                transaction.message.recent_blockhash(),
+                rpc_client.commitment(),
+            )
+            .context("Failed to check the finalize blockhash")?
+        {
+            return Err(error.context(format!(
+                "Finalize {} didn't land and its blockhash expired, run again to rebuild it",
+                signature
+            )));
+        }
+
+        attempt += 1;
+        println!(
+            "Finalize {} not confirmed ({}), sending it again ({}/{})",
+            signature, error, attempt, FINALIZE_SEND_ATTEMPTS
+        );
+    }
+}
+
+/// Failure classes of the CLI, each with its own process exit code so scripts can
+/// branch on the kind of failure instead of parsing stderr
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureClass {
+    /// Anything not classified below
+    Other,
+    /// Bad arguments or keys
+    Config,
+    /// RPC endpoint unreachable or returning errors
+    Rpc,
+    SlippageExceeded,
+    OrderExpired,
+    InsufficientFunds,
+    /// Transaction rejected by a program
+    ProgramError,
+    /// A finalized swap paid out less than its min out (`--assert-received`)
+    ReceivedTooLittle,
+}
+
+impl FailureClass {
+    pub fn exit_code(self) -> u8 {
+        match self {
+            FailureClass::Other => 1,
+            FailureClass::Config => 2,
+            FailureClass::Rpc => 3,
+            FailureClass::SlippageExceeded => 4,
+            FailureClass::OrderExpired => 5,
+            FailureClass::InsufficientFunds => 6,
+            FailureClass::ProgramError => 7,
+            FailureClass::ReceivedTooLittle => 8,
+        }
+    }
+}
+
+impl std::fmt::Display for FailureClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            FailureClass::Other => "error",
+            FailureClass::Config => "configuration error",
+            FailureClass::Rpc => "RPC failure",
+            FailureClass::SlippageExceeded => "slippage exceeded",
+            FailureClass::OrderExpired => "order expired",
+            FailureClass::InsufficientFunds => "insufficient funds",
+            FailureClass::ProgramError => "program error",
+            FailureClass::ReceivedTooLittle => "received less than the min out",
+        };
+        f.write_str(description)
+    }
+}
+
+impl std::error::Error for FailureClass {}
+
+/// Failure class of a transaction error
+fn classify_transaction_error(error: &TransactionError) -> FailureClass {
+    match error {
+        TransactionError::InsufficientFundsForFee
+        | TransactionError::InsufficientFundsForRent { .. }
+        | TransactionError::AccountNotFound => FailureClass::InsufficientFunds,
+        // custom error 1 is insufficient funds in both the system and the token program
+        TransactionError::InstructionError(_, InstructionError::Custom(1)) => {
+            FailureClass::InsufficientFunds
+        }
+        TransactionError::InstructionError(..) => FailureClass::ProgramError,
+        _ => FailureClass::Other,
+    }
+}
+
+/// Slippage and expiry failure classes from the Anchor error name in program logs
+fn classify_program_logs(logs: &[String]) -> Option<FailureClass> {
+    let code = logs.iter().find_map(|log| {
+        let (_, rest) = log.split_once("Error Code: ")?;
+        rest.split('.').next()
+    })?;
+    if code.contains("Slippage") {
+        Some(FailureClass::SlippageExceeded)
+    } else if code.contains("Expired") {
+        Some(FailureClass::OrderExpired)
+    } else {
+        None
+    }
+}
+
+/// Failure class of a failed simulation
+fn classify_simulation_failure(error: &TransactionError, logs: &[String]) -> FailureClass {
+    classify_program_logs(logs).unwrap_or_else(|| classify_transaction_error(error))
+}
+
+/// Failure class of an RPC client error, Anchor error names in the simulation logs
+/// tell slippage and expiry apart from other program errors
+fn classify_client_error(error: &ClientError) -> FailureClass {
+    if let ClientErrorKind::RpcError(RpcError::RpcResponseError {
+        data: RpcResponseErrorData::SendTransactionPreflightFailure(simulation),
+        ..
+    }) = &error.kind
+        && let Some(class) = simulation.logs.as_deref().and_then(classify_program_logs)
+    {
+        return class;
+    }
+
+    match error.get_transaction_error() {
+        Some(transaction_error) => classify_transaction_error(&transaction_error),
+        None => FailureClass::Rpc,
+    }
+}
+
+/// Failure class of an error, from a `FailureClass` attached as context or the
+/// errors it was caused by
+pub fn classify_error(error: &anyhow::Error) -> FailureClass {
+    if let Some(class) = error.downcast_ref::<FailureClass>() {
+        return *class;
+    }
+    for cause in error.chain() {
+        if let Some(client_error) = cause.downcast_ref::<ClientError>() {
+            return classify_client_error(client_error);
+        }
+        if let Some(transaction_error) = cause.downcast_ref::<TransactionError>() {
+            return classify_transaction_error(transaction_error);
+        }
+    }
+
+    // the SDK reports expired orders as plain messages
+    if error.root_cause().to_string().contains("Order has expired") {
+        return FailureClass::OrderExpired;
+    }
+    FailureClass::Other
+}
 
 pub fn get_wrap_sol_to_wsol_instructions(
     payer: Pubkey,
@@ -21,6 +868,7 @@ pub fn get_wrap_sol_to_wsol_instructions(
 ) -> Result<Vec<Instruction>> {
     let mut instructions = Vec::new();
 
+    // the native mint only exists under SPL Token
     let token_mint_wsol = native_mint::ID;
     let token_program_id = spl_token::ID;
 
@@ -76,135 +924,723 @@ pub fn get_unwrap_wsol_to_sol_instructions(payer: Pubkey) -> Result<Vec<Instruct
     Ok(instructions)
 }
 
-/// Mint tokens to user's associated token account
-pub async fn mint_tokens_to_user(
+/// Instructions wrapping only the part of `amount_in_lamports` the payer's WSOL ATA lacks
+///
+/// WSOL left in the ATA, e.g. by an add liquidity that took less than its max, is spent
+/// first. Nothing is wrapped when it already holds enough, and an existing ATA isn't
+/// created again. Checks with `check_wrap_amount` that the wrap leaves the fee buffer.
+pub fn get_wrap_shortfall_instructions(
     rpc_client: &RpcClient,
-    user_keypair: &Keypair,
-    mint_pubkey: &Pubkey,
-    amount: u64,
-) -> Result<()> {
-    let user_token_account = get_associated_token_address(&user_keypair.pubkey(), mint_pubkey);
+    payer: Pubkey,
+    amount_in_lamports: u64,
+    fee_buffer_lamports: u64,
+) -> Result<Vec<Instruction>> {
+    let wsol_ata = get_associated_token_address(&payer, &native_mint::ID);
+    let wsol_balance = rpc_client
+        .get_account_with_commitment(&wsol_ata, rpc_client.commitment())
+        .context("Failed to get WSOL account")?
+        .value
+        .map(|account| unpack_token_account(&account.data).map(|wsol| wsol.amount))
+        .transpose()?;
 
-    let create_ata_ix =
-        spl_associated_token_account::instruction::create_associated_token_account_idempotent(
-            &user_keypair.pubkey(),
-            &user_keypair.pubkey(),
-            mint_pubkey,
-            &spl_token::ID,
+    let shortfall = amount_in_lamports.saturating_sub(wsol_balance.unwrap_or(0));
+    if shortfall == 0 {
+        println!(
+            "WSOL account already holds {} SOL, nothing to wrap",
+            lamports_to_sol(wsol_balance.unwrap_or(0))
         );
+        return Ok(Vec::new());
+    }
 
-    let mint_to_ix = mint_to(
-        &spl_token::ID,
-        mint_pubkey,
-        &user_token_account,
-        &user_keypair.pubkey(),
-        &[],
-        amount,
-    )?;
-
-    let recent_blockhash = rpc_client
-        .get_latest_blockhash()
-        .context("Failed to get recent blockhash")?;
-
-    let mint_tx = Transaction::new_signed_with_payer(
-        &[create_ata_ix, mint_to_ix],
-        Some(&user_keypair.pubkey()),
-        &[user_keypair],
-        recent_blockhash,
-    );
-
-    rpc_client
-        .send_and_confirm_transaction_with_spinner(&mint_tx)
-        .context("Failed to mint tokens")?;
+    check_wrap_amount(rpc_client, &payer, shortfall, fee_buffer_lamports)?;
 
-    Ok(())
+    match wsol_balance {
+        Some(wsol_balance) => {
+            println!(
+                "WSOL account holds {} SOL, wrapping the remaining {} SOL",
+                lamports_to_sol(wsol_balance),
+                lamports_to_sol(shortfall)
+            );
+            Ok(vec![
+                transfer(&payer, &wsol_ata, shortfall),
+                sync_native(&spl_token::ID, &wsol_ata)?,
+            ])
+        }
+        None => get_wrap_sol_to_wsol_instructions(payer, shortfall),
+    }
 }
 
-/// Create a new SPL token mint with a simple helper function
-pub async fn create_token_mint(
+/// Make sure wrapping `amount_in_lamports` leaves the payer enough SOL for follow-up transactions
+///
+/// Accounts for the rent-exempt minimum of the WSOL ATA when it doesn't exist yet and keeps
+/// `fee_buffer_lamports` in the wallet (e.g. to pay for the finalize transaction).
+/// Returns the total lamports the wrap will take from the payer.
+pub fn check_wrap_amount(
     rpc_client: &RpcClient,
-    user_keypair: &Keypair,
-    mint_keypair: &Keypair,
-) -> Result<Pubkey> {
-    const MINT_SIZE: usize = 82; // SPL token mint account size
-    let mint_rent = rpc_client
-        .get_minimum_balance_for_rent_exemption(MINT_SIZE)
-        .context("Failed to get rent exemption")?;
+    payer: &Pubkey,
+    amount_in_lamports: u64,
+    fee_buffer_lamports: u64,
+) -> Result<u64> {
+    let wsol_ata = get_associated_token_address(payer, &native_mint::ID);
 
-    let mint_pubkey = mint_keypair.pubkey();
+    let wsol_ata_exists = rpc_client
+        .get_account_with_commitment(&wsol_ata, rpc_client.commitment())
+        .context("Failed to get WSOL account")?
+        .value
+        .is_some();
 
-    // Create account and initialize mint in one transaction
-    let create_mint_ix = create_account(
-        &user_keypair.pubkey(),
-        &mint_pubkey,
-        mint_rent,
-        MINT_SIZE as u64,
-        &spl_token::ID,
-    );
+    let ata_rent = if wsol_ata_exists {
+        0
+    } else {
+        rpc_client
+            .get_minimum_balance_for_rent_exemption(TokenAccount::LEN)
+            .context("Failed to get rent exemption")?
+    };
 
-    let init_mint_ix = initialize_mint(
-        &spl_token::ID,
-        &mint_pubkey,
-        &user_keypair.pubkey(),
-        None,
-        9,
-    )?;
+    let wrap_cost = amount_in_lamports
+        .checked_add(ata_rent)
+        .context("Wrap amount overflow")?;
 
-    let recent_blockhash = rpc_client
-        .get_latest_blockhash()
+    let balance = rpc_client
+        .get_balance(payer)
+        .context("Failed to get wallet balance")?;
+
+    let required = wrap_cost
+        .checked_add(fee_buffer_lamports)
+        .context("Wrap amount overflow")?;
+
+    if balance < required {
+        bail!(
+            "Insufficient SOL: wrapping {} SOL (+{} SOL WSOL account rent) needs {} SOL with a {} SOL fee buffer for the finalize transaction, wallet has {} SOL",
+            lamports_to_sol(amount_in_lamports),
+            lamports_to_sol(ata_rent),
+            lamports_to_sol(required),
+            lamports_to_sol(fee_buffer_lamports),
+            lamports_to_sol(balance)
+        );
+    }
+
+    Ok(wrap_cost)
+}
+
+/// Check the order still exists before finalizing it
+///
+/// If someone else (e.g. a keeper) already settled, slashed or cancelled it, print who did and
+/// in which transaction. Returns false when there's nothing left to finalize.
+pub fn check_order_open(rpc_client: &RpcClient, order_address: &Pubkey) -> Result<bool> {
+    let order_account = rpc_client
+        .get_account_with_commitment(order_address, rpc_client.commitment())
+        .context("Failed to get order account")?
+        .value;
+    if order_account.is_some() {
+        return Ok(true);
+    }
+
+    println!(
+        "Order {} was already finalized by someone else",
+        order_address
+    );
+
+    if ASSERT_RECEIVED.load(Ordering::Relaxed) {
+        println!("  skipping --assert-received, this run didn't finalize the order");
+    }
+
+    // the node defaults to finalized, which misses an order closed seconds ago
+    let signatures = rpc_client
+        .get_signatures_for_address_with_config(
+            order_address,
+            GetConfirmedSignaturesForAddress2Config {
+                commitment: Some(CommitmentConfig::confirmed()),
+                ..Default::default()
+            },
+        )
+        .context("Failed to get order signatures")?;
+    let Some(last) = signatures.first() else {
+        println!("  no transactions found for the order");
+        return Ok(false);
+    };
+
+    let signature = Signature::from_str(&last.signature)?;
+    let transaction = rpc_client
+        .get_transaction_with_config(
+            &signature,
+            RpcTransactionConfig {
+                encoding: Some(UiTransactionEncoding::Base64),
+                // getTransaction refuses anything below confirmed
+                commitment: Some(CommitmentConfig::confirmed()),
+                max_supported_transaction_version: Some(0),
+            },
+        )
+        .context("Failed to get finalizing transaction")?
+        .transaction;
+
+    // settle, cancel and slash all take the signer closing the order first, it may not be
+    // the fee payer
+    let closed_by = transaction.transaction.decode().and_then(|tx| {
+        let account_keys = tx.message.static_account_keys();
+        tx.message
+            .instructions()
+            .iter()
+            .find(|instruction| {
+                account_keys.get(instruction.program_id_index as usize)
+                    == Some(&DARKLAKE_PROGRAM_ID)
+            })
+            .and_then(|instruction| instruction.accounts.first())
+            .and_then(|index| account_keys.get(*index as usize))
+            .map(Pubkey::to_string)
+    });
+
+    let logs: Option<Vec<String>> = transaction.meta.and_then(|meta| meta.log_messages.into());
+    let action = logs.and_then(|logs| get_darklake_instruction(&logs));
+
+    println!("  action:      {}", action.as_deref().unwrap_or("unknown"));
+    println!(
+        "  by:          {}",
+        closed_by.as_deref().unwrap_or("unknown")
+    );
+    println!("  transaction: {} (slot {})", signature, last.slot);
+
+    Ok(false)
+}
+
+/// Name of the first Darklake instruction in a transaction's logs, e.g. `Swap`
+fn get_darklake_instruction(logs: &[String]) -> Option<String> {
+    // Anchor logs the instruction name right after the program is invoked
+    let invoke_log = format!("Program {} invoke [1]", DARKLAKE_PROGRAM_ID);
+    logs.iter()
+        .skip_while(|log| **log != invoke_log)
+        .find_map(|log| log.strip_prefix("Program log: Instruction: "))
+        .map(str::to_string)
+}
+
+// Base fee per signature, the priority fee is what a transaction paid above it
+const LAMPORTS_PER_SIGNATURE: u64 = 5_000;
+
+/// What a landed transaction cost its fee payer
+#[derive(Debug, Clone)]
+pub struct TransactionCost {
+    /// Darklake instruction of the transaction, `None` for e.g. token account setups
+    pub instruction: Option<String>,
+    pub pool: Option<Pubkey>,
+    pub network_fee: u64,
+    pub priority_fee: u64,
+    /// Lamports left in the accounts the transaction created, less wrapped SOL
+    pub rent: u64,
+    pub failed: bool,
+}
+
+/// Cost of a sent transaction read from its metadata, `None` if it never landed
+pub fn get_transaction_cost(
+    rpc_client: &RpcClient,
+    signature: &Signature,
+) -> Result<Option<TransactionCost>> {
+    if rpc_client
+        .get_signature_status_with_commitment_and_history(
+            signature,
+            CommitmentConfig::confirmed(),
+            true,
+        )
+        .context("Failed to get signature status")?
+        .is_none()
+    {
+        return Ok(None);
+    }
+
+    let transaction = rpc_client
+        .get_transaction_with_config(
+            signature,
+            RpcTransactionConfig {
+                encoding: Some(UiTransactionEncoding::Base64),
+                commitment: Some(CommitmentConfig::confirmed()),
+                max_supported_transaction_version: Some(0),
+            },
+        )
+        .context("Failed to get transaction")?
+        .transaction;
+    let decoded = transaction
+        .transaction
+        .decode()
+        .context("Failed to decode transaction")?;
+    let meta = transaction.meta.context("Transaction has no metadata")?;
+
+    // balances are listed for the static keys followed by the lookup table ones
+    let mut account_keys = decoded.message.static_account_keys().to_vec();
+    let loaded_addresses: Option<UiLoadedAddresses> = meta.loaded_addresses.into();
+    if let Some(loaded_addresses) = loaded_addresses {
+        for address in loaded_addresses
+            .writable
+            .iter()
+            .chain(&loaded_addresses.readonly)
+        {
+            account_keys.push(Pubkey::from_str(address)?);
+        }
+    }
+
+    let post_token_balances: Option<Vec<UiTransactionTokenBalance>> =
+        meta.post_token_balances.into();
+    let wrapped_sol = |index: usize| {
+        post_token_balances
+            .iter()
+            .flatten()
+            .find(|balance| {
+                balance.account_index as usize == index
+                    && balance.mint == native_mint::ID.to_string()
+            })
+            .and_then(|balance| balance.ui_token_amount.amount.parse::<u64>().ok())
+            .unwrap_or(0)
+    };
+    let rent = meta
+        .pre_balances
+        .iter()
+        .zip(&meta.post_balances)
+        .enumerate()
+        .filter(|(_, (pre, post))| **pre == 0 && **post > 0)
+        .map(|(index, (_, post))| post.saturating_sub(wrapped_sol(index)))
+        .sum();
+
+    let pool = account_keys.iter().find_map(|key_a| {
+        account_keys
+            .iter()
+            .filter(|key_b| key_a < *key_b)
+            .map(|key_b| get_pool_address(key_a, key_b))
+            .find(|pool_key| account_keys.contains(pool_key))
+    });
+
+    let network_fee = decoded.signatures.len() as u64 * LAMPORTS_PER_SIGNATURE;
+    let logs: Option<Vec<String>> = meta.log_messages.into();
+    Ok(Some(TransactionCost {
+        instruction: logs.and_then(|logs| get_darklake_instruction(&logs)),
+        pool,
+        network_fee: network_fee.min(meta.fee),
+        priority_fee: meta.fee.saturating_sub(network_fee),
+        rent,
+        failed: meta.err.is_some(),
+    }))
+}
+
+/// Print the settler's SOL balance change for a single settle (rewards minus fees paid)
+pub fn print_settler_earnings(balance_before: u64, balance_after: u64) {
+    let delta = balance_after as i128 - balance_before as i128;
+    println!(
+        "Settler balance change: {}{} SOL ({} lamports)",
+        if delta < 0 { "-" } else { "+" },
+        lamports_to_sol(delta.unsigned_abs() as u64),
+        delta
+    );
+}
+
+static ASSERT_RECEIVED: AtomicBool = AtomicBool::new(false);
+
+/// Check what swaps paid out against their min out after the finalize (`--assert-received`)
+pub fn set_assert_received(assert_received: bool) {
+    ASSERT_RECEIVED.store(assert_received, Ordering::Relaxed);
+}
+
+/// Output token balance of a swap's owner from before the swap, to check the finalize paid
+/// out at least the min out
+///
+/// The program already refuses to settle below the min out, this also catches output paid
+/// to another account or in other units than expected, and orders cancelled instead of settled.
+pub struct ReceivedCheck {
+    owner: Pubkey,
+    token: TokenInfo,
+    balance_before: u64,
+    min_out: u64,
+}
+
+impl ReceivedCheck {
+    /// Record the owner's balance of the output token, `None` without `--assert-received`
+    pub fn start(
+        rpc_client: &RpcClient,
+        owner: &Pubkey,
+        token_mint_out: &Pubkey,
+        min_out: u64,
+    ) -> Result<Option<Self>> {
+        if !ASSERT_RECEIVED.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        if to_pool_mint(token_mint_out) == native_mint::ID {
+            bail!("--assert-received can't check SOL output, the same balance pays the fees");
+        }
+
+        let token = get_token_infos(rpc_client, &[*token_mint_out])?.remove(0);
+        let balance_before = get_token_balances(rpc_client, owner, &[token.mint])?[0];
+        Ok(Some(Self {
+            owner: *owner,
+            token,
+            balance_before,
+            min_out,
+        }))
+    }
+
+    /// Fail with `FailureClass::ReceivedTooLittle` if the balance grew by less than the min out
+    pub fn check(&self, rpc_client: &RpcClient) -> Result<()> {
+        let balance_after = get_token_balances(rpc_client, &self.owner, &[self.token.mint])?[0];
+        let received = balance_after.saturating_sub(self.balance_before);
+        if received < self.min_out {
+            return Err(
+                anyhow::Error::new(FailureClass::ReceivedTooLittle).context(format!(
+                    "ALERT: received {} but the min out was {} (balance {} -> {})",
+                    self.token.format(received),
+                    self.token.format(self.min_out),
+                    self.token.format(self.balance_before),
+                    self.token.format(balance_after)
+                )),
+            );
+        }
+
+        println!(
+            "Received {} (min out {})",
+            self.token.format(received),
+            self.token.format(self.min_out)
+        );
+        Ok(())
+    }
+}
+
+const MINT_DECIMALS: u8 = 9;
+
+/// Instructions creating the payer's token account (if missing) and minting `amount` to it
+fn get_mint_to_user_instructions(
+    payer: &Pubkey,
+    mint_pubkey: &Pubkey,
+    token_program: &Pubkey,
+    amount: u64,
+) -> Result<Vec<Instruction>> {
+    let user_token_account =
+        get_associated_token_address_with_program_id(payer, mint_pubkey, token_program);
+
+    let create_ata_ix =
+        spl_associated_token_account::instruction::create_associated_token_account_idempotent(
+            payer,
+            payer,
+            mint_pubkey,
+            token_program,
+        );
+
+    let mint_to_ix = mint_to(
+        token_program,
+        mint_pubkey,
+        &user_token_account,
+        payer,
+        &[],
+        amount,
+    )?;
+
+    Ok(vec![create_ata_ix, mint_to_ix])
+}
+
+/// Instructions creating and initializing a mint owned by the payer, the mint keypair has to sign
+fn get_create_token_mint_instructions(
+    payer: &Pubkey,
+    mint_pubkey: &Pubkey,
+    mint_rent: u64,
+) -> Result<Vec<Instruction>> {
+    let create_mint_ix = create_account(
+        payer,
+        mint_pubkey,
+        mint_rent,
+        spl_token::state::Mint::LEN as u64,
+        &spl_token::ID,
+    );
+
+    let init_mint_ix = initialize_mint(&spl_token::ID, mint_pubkey, payer, None, MINT_DECIMALS)?;
+
+    Ok(vec![create_mint_ix, init_mint_ix])
+}
+
+/// Mint tokens to user's associated token account
+pub async fn mint_tokens_to_user(
+    rpc_client: &RpcClient,
+    user_keypair: &Keypair,
+    mint_pubkey: &Pubkey,
+    amount: u64,
+) -> Result<()> {
+    let token_program = resolve_token_program(rpc_client, mint_pubkey)?;
+    let instructions =
+        get_mint_to_user_instructions(&user_keypair.pubkey(), mint_pubkey, &token_program, amount)?;
+
+    let recent_blockhash = rpc_client
+        .get_latest_blockhash()
+        .context("Failed to get recent blockhash")?;
+
+    let mint_tx = Transaction::new_signed_with_payer(
+        &instructions,
+        Some(&user_keypair.pubkey()),
+        &[user_keypair],
+        recent_blockhash,
+    );
+
+    send_and_confirm(rpc_client, &mint_tx).context("Failed to mint tokens")?;
+
+    Ok(())
+}
+
+/// Create a new SPL token mint with a simple helper function
+pub async fn create_token_mint(
+    rpc_client: &RpcClient,
+    user_keypair: &Keypair,
+    mint_keypair: &Keypair,
+) -> Result<Pubkey> {
+    let mint_rent = rpc_client
+        .get_minimum_balance_for_rent_exemption(spl_token::state::Mint::LEN)
+        .context("Failed to get rent exemption")?;
+
+    let mint_pubkey = mint_keypair.pubkey();
+
+    // Create account and initialize mint in one transaction
+    let instructions =
+        get_create_token_mint_instructions(&user_keypair.pubkey(), &mint_pubkey, mint_rent)?;
+
+    let recent_blockhash = rpc_client
+        .get_latest_blockhash()
         .context("Failed to get recent blockhash")?;
 
     let create_mint_tx = Transaction::new_signed_with_payer(
-        &[create_mint_ix, init_mint_ix],
+        &instructions,
         Some(&user_keypair.pubkey()),
         &[user_keypair, mint_keypair],
         recent_blockhash,
     );
 
-    rpc_client
-        .send_and_confirm_transaction_with_spinner(&create_mint_tx)
-        .context("Failed to create token mint")?;
+    send_and_confirm(rpc_client, &create_mint_tx).context("Failed to create token mint")?;
 
     Ok(mint_pubkey)
 }
 
-/// Create two new SPL token mints - simplified version
+/// Keypair for a new mint, random or derived from `seed` and `name` so reruns get the same address
+pub fn new_mint_keypair(seed: Option<&str>, name: &str) -> Result<Keypair> {
+    match seed {
+        Some(seed) => keypair_from_seed(hashv(&[seed.as_bytes(), name.as_bytes()]).as_ref())
+            .map_err(|e| anyhow!("Failed to derive mint keypair: {}", e)),
+        None => Ok(Keypair::new()),
+    }
+}
+
+/// Create two new SPL token mints and mint `mint_amount` of each to the user
+///
+/// Everything goes into a single transaction, or two (mints, then mint_to) if it doesn't fit.
+/// With a `mint_seed` the mint addresses are deterministic, which only works once per cluster,
+/// so each command derives them under its own `seed_prefix`.
 pub async fn create_new_tokens(
     rpc_client: &RpcClient,
     user_keypair: &Keypair,
+    mint_seed: Option<&str>,
+    seed_prefix: &str,
     mint_amount: u64,
 ) -> Result<(Pubkey, Pubkey)> {
-    let token_mint_x_keypair = Keypair::new();
-    let token_mint_y_keypair = Keypair::new();
-
-    println!("Creating Token X Mint...");
-    let token_mint_x = create_token_mint(rpc_client, user_keypair, &token_mint_x_keypair).await?;
-
-    println!("Creating Token Y Mint...");
-    let token_mint_y = create_token_mint(rpc_client, user_keypair, &token_mint_y_keypair).await?;
+    let token_mint_x_keypair = new_mint_keypair(mint_seed, &format!("{seed_prefix}x"))?;
+    let token_mint_y_keypair = new_mint_keypair(mint_seed, &format!("{seed_prefix}y"))?;
+    let token_mint_x = token_mint_x_keypair.pubkey();
+    let token_mint_y = token_mint_y_keypair.pubkey();
+    let payer = user_keypair.pubkey();
 
     println!("Token X Mint: {}", token_mint_x);
     println!("Token Y Mint: {}", token_mint_y);
 
-    println!("Minting Token X to user...");
-    mint_tokens_to_user(rpc_client, user_keypair, &token_mint_x, mint_amount).await?;
+    if mint_seed.is_some()
+        && rpc_client
+            .get_multiple_accounts(&[token_mint_x, token_mint_y])?
+            .iter()
+            .any(Option::is_some)
+    {
+        bail!(
+            "Mints derived from this seed already exist, pass them as existing mints or use another seed"
+        );
+    }
+
+    let mint_rent = rpc_client
+        .get_minimum_balance_for_rent_exemption(spl_token::state::Mint::LEN)
+        .context("Failed to get rent exemption")?;
+
+    let create_mint_instructions = [
+        get_create_token_mint_instructions(&payer, &token_mint_x, mint_rent)?,
+        get_create_token_mint_instructions(&payer, &token_mint_y, mint_rent)?,
+    ]
+    .concat();
+    // the mints above are created under SPL Token
+    let mint_to_instructions = [
+        get_mint_to_user_instructions(&payer, &token_mint_x, &spl_token::ID, mint_amount)?,
+        get_mint_to_user_instructions(&payer, &token_mint_y, &spl_token::ID, mint_amount)?,
+    ]
+    .concat();
+
+    let signers = [user_keypair, &token_mint_x_keypair, &token_mint_y_keypair];
+
+    let recent_blockhash = rpc_client
+        .get_latest_blockhash()
+        .context("Failed to get recent blockhash")?;
+
+    let all_instructions = [create_mint_instructions.as_slice(), &mint_to_instructions].concat();
+    let combined_tx = Transaction::new_signed_with_payer(
+        &all_instructions,
+        Some(&payer),
+        &signers,
+        recent_blockhash,
+    );
+
+    let transactions = if serialized_transaction_size(&combined_tx) <= PACKET_DATA_SIZE {
+        vec![combined_tx]
+    } else {
+        vec![
+            Transaction::new_signed_with_payer(
+                &create_mint_instructions,
+                Some(&payer),
+                &signers,
+                recent_blockhash,
+            ),
+            Transaction::new_signed_with_payer(
+                &mint_to_instructions,
+                Some(&payer),
+                &[user_keypair],
+                recent_blockhash,
+            ),
+        ]
+    };
 
-    println!("Minting Token Y to user...");
-    mint_tokens_to_user(rpc_client, user_keypair, &token_mint_y, mint_amount).await?;
+    println!(
+        "Creating and minting both tokens in {} transaction(s)...",
+        transactions.len()
+    );
+    for tx in &transactions {
+        send_and_confirm(rpc_client, tx).context("Failed to create tokens")?;
+    }
 
     println!("Successfully created and minted both tokens!");
     Ok((token_mint_x, token_mint_y))
 }
 
-pub async fn get_address_lookup_table(
+/// Wire size of a legacy transaction (compact signature count + signatures + message)
+fn serialized_transaction_size(tx: &Transaction) -> usize {
+    1 + tx.signatures.len() * 64 + tx.message.serialize().len()
+}
+
+/// Mints passed in to resume a failed pool init, otherwise two freshly created ones
+pub async fn get_or_create_new_tokens(
+    rpc_client: &RpcClient,
+    user_keypair: &Keypair,
+    existing_mints: Option<(Pubkey, Pubkey)>,
+    mint_seed: Option<&str>,
+    seed_prefix: &str,
+    mint_amount: u64,
+) -> Result<(Pubkey, Pubkey)> {
+    match existing_mints {
+        Some((token_mint_x, token_mint_y)) => {
+            println!("Reusing existing token mints...");
+            Ok((token_mint_x, token_mint_y))
+        }
+        None => {
+            println!("Creating new token mints...");
+            create_new_tokens(
+                rpc_client,
+                user_keypair,
+                mint_seed,
+                seed_prefix,
+                mint_amount,
+            )
+            .await
+        }
+    }
+}
+
+/// Burn the user's balance of each mint and close its token account, returns the reclaimed rent
+///
+/// SPL Token mints can't be closed, so only the token accounts are cleaned up. Only mints
+/// whose mint authority is the user are accepted, so tokens of a real asset are never burned.
+pub async fn close_token_accounts(
     rpc_client: &RpcClient,
+    user_keypair: &Keypair,
+    mints: &[Pubkey],
+) -> Result<u64> {
+    let mint_accounts = rpc_client
+        .get_multiple_accounts(mints)
+        .context("Failed to get token mints")?;
+    for (mint, account) in mints.iter().zip(mint_accounts) {
+        let account = account.with_context(|| format!("Token mint {} not found", mint))?;
+        let mint_authority: Option<Pubkey> = unpack_mint(&account.data)?.mint_authority.into();
+        if mint_authority != Some(user_keypair.pubkey()) {
+            bail!(
+                "Mint {} isn't controlled by the user key (mint authority: {}), refusing to burn its tokens",
+                mint,
+                mint_authority.map_or("none".to_string(), |authority| authority.to_string())
+            );
+        }
+    }
+
+    let token_programs = resolve_token_programs(rpc_client, mints)?;
+    let token_accounts: Vec<Pubkey> = mints
+        .iter()
+        .zip(&token_programs)
+        .map(|(mint, token_program)| {
+            get_associated_token_address_with_program_id(
+                &user_keypair.pubkey(),
+                mint,
+                token_program,
+            )
+        })
+        .collect();
+    let accounts = rpc_client.get_multiple_accounts(&token_accounts)?;
+
+    let mut instructions = Vec::new();
+    let mut reclaimed = 0;
+
+    for (((mint, token_program), token_account), account) in mints
+        .iter()
+        .zip(&token_programs)
+        .zip(&token_accounts)
+        .zip(accounts)
+    {
+        let Some(account) = account else {
+            println!("No token account for mint {}, skipping", mint);
+            continue;
+        };
+
+        let amount = unpack_token_account(&account.data)?.amount;
+        if amount > 0 {
+            instructions.push(burn(
+                token_program,
+                token_account,
+                mint,
+                &user_keypair.pubkey(),
+                &[],
+                amount,
+            )?);
+        }
+        instructions.push(close_account(
+            token_program,
+            token_account,
+            &user_keypair.pubkey(),
+            &user_keypair.pubkey(),
+            &[],
+        )?);
+        reclaimed += account.lamports;
+    }
+
+    if instructions.is_empty() {
+        return Ok(0);
+    }
+
+    let recent_blockhash = rpc_client
+        .get_latest_blockhash()
+        .context("Failed to get recent blockhash")?;
+
+    let close_tx = Transaction::new_signed_with_payer(
+        &instructions,
+        Some(&user_keypair.pubkey()),
+        &[user_keypair],
+        recent_blockhash,
+    );
+
+    send_and_confirm(rpc_client, &close_tx).context("Failed to close token accounts")?;
+
+    Ok(reclaimed)
+}
+
+fn parse_address_lookup_table(
     lookup_table_pubkey: Pubkey,
+    data: &[u8],
 ) -> Result<AddressLookupTableAccount> {
-    let alt_account = rpc_client
-        .get_account(&lookup_table_pubkey)
-        .context("Failed to get address lookup table")?;
-
-    let table = AddressLookupTable::deserialize(&alt_account.data)?;
+    let table = AddressLookupTable::deserialize(data)?;
 
     let address_lookup_table = AddressLookupTableAccount {
         key: lookup_table_pubkey,
@@ -214,31 +1650,1986 @@ pub async fn get_address_lookup_table(
     Ok(address_lookup_table)
 }
 
-pub async fn get_order(
-    sdk: &DarklakeSDK,
-    order_owner: &Pubkey,
+pub async fn get_address_lookup_table(
     rpc_client: &RpcClient,
-) -> Result<Order> {
-    for attempt in 1..=5 {
-        match sdk
-            .get_order(order_owner, rpc_client.commitment().commitment)
-            .await
-        {
-            Ok(result) => {
-                return Ok(result);
+    lookup_table_pubkey: Pubkey,
+) -> Result<AddressLookupTableAccount> {
+    let alt_account = rpc_client
+        .get_account(&lookup_table_pubkey)
+        .context("Failed to get address lookup table")?;
+
+    parse_address_lookup_table(lookup_table_pubkey, &alt_account.data)
+}
+
+/// Read the lookup table saved by `solana address-lookup-table get <address> --output json`,
+/// so messages can be compiled offline
+pub fn read_address_lookup_table_file(
+    path: &Path,
+    lookup_table_pubkey: Pubkey,
+) -> Result<AddressLookupTableAccount> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let table: serde_json::Value = serde_json::from_str(&contents)
+        .with_context(|| format!("Invalid lookup table JSON in {}", path.display()))?;
+
+    if let Some(address) = table["lookupTableAddress"].as_str()
+        && address != lookup_table_pubkey.to_string()
+    {
+        bail!(
+            "{} holds lookup table {}, expected {}",
+            path.display(),
+            address,
+            lookup_table_pubkey
+        );
+    }
+    let addresses = table["addresses"]
+        .as_array()
+        .with_context(|| format!("No addresses in {}", path.display()))?
+        .iter()
+        .map(|address| {
+            address
+                .as_str()
+                .and_then(|address| Pubkey::from_str(address).ok())
+                .with_context(|| format!("Invalid address {} in {}", address, path.display()))
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(AddressLookupTableAccount {
+        key: lookup_table_pubkey,
+        addresses,
+    })
+}
+
+/// Default runtime limit on the accounts one transaction may lock
+const MAX_TRANSACTION_ACCOUNTS: usize = 64;
+
+/// Check the accounts of `instructions` before compiling them into one transaction
+///
+/// Bundles like wrap + swap or create ATA + remove liquidity + unwrap otherwise fail in
+/// `try_compile`, signing or the runtime without saying which instruction is at fault.
+pub fn check_instruction_accounts(
+    payer: &Pubkey,
+    signers: &[Pubkey],
+    instructions: &[Instruction],
+) -> Result<()> {
+    let mut accounts = vec![*payer];
+    for (index, instruction) in instructions.iter().enumerate() {
+        for meta in &instruction.accounts {
+            if meta.is_signer && !signers.contains(&meta.pubkey) {
+                bail!(
+                    "Instruction {} requires a signature of {}, which isn't one of the signers ({})",
+                    index,
+                    meta.pubkey,
+                    signers
+                        .iter()
+                        .map(Pubkey::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
             }
-            Err(e) => {
-                if attempt < 5 {
-                    println!(
-                        "get_order failed (attempt {}): {}. Retrying in 5 seconds...",
-                        attempt, e
-                    );
-                    sleep(Duration::from_secs(5)).await;
-                } else {
-                    return Err(e.into());
-                }
+            if meta.is_writable
+                && let Some(program_index) = instructions
+                    .iter()
+                    .position(|other| other.program_id == meta.pubkey)
+            {
+                bail!(
+                    "Instruction {} writes to {}, which instruction {} invokes as a program",
+                    index,
+                    meta.pubkey,
+                    program_index
+                );
+            }
+            if !accounts.contains(&meta.pubkey) {
+                accounts.push(meta.pubkey);
             }
         }
+        if !accounts.contains(&instruction.program_id) {
+            accounts.push(instruction.program_id);
+        }
+    }
+
+    if accounts.len() > MAX_TRANSACTION_ACCOUNTS {
+        let per_instruction = instructions
+            .iter()
+            .enumerate()
+            .map(|(index, instruction)| format!("#{}: {}", index, instruction.accounts.len() + 1))
+            .collect::<Vec<_>>()
+            .join(", ");
+        bail!(
+            "Transaction would lock {} accounts > {}, split the instructions (accounts per instruction {})",
+            accounts.len(),
+            MAX_TRANSACTION_ACCOUNTS,
+            per_instruction
+        );
+    }
+
+    Ok(())
+}
+
+/// Compile `instructions` into a V0 message using the lookup table, or a legacy message without it
+///
+/// The payer must be the only signer. Legacy messages list every account inline, so bigger
+/// instructions (e.g. finalize) may not fit.
+pub fn compile_message(
+    payer: &Pubkey,
+    instructions: &[Instruction],
+    address_lookup_table: &AddressLookupTableAccount,
+    recent_blockhash: Hash,
+    legacy: bool,
+) -> Result<VersionedMessage> {
+    check_instruction_accounts(payer, &[*payer], instructions)?;
+
+    if !legacy {
+        let message = VersionedMessage::V0(v0::Message::try_compile(
+            payer,
+            instructions,
+            std::slice::from_ref(address_lookup_table),
+            recent_blockhash,
+        )?);
+        let size = transaction_size(&message);
+        if size > PACKET_DATA_SIZE {
+            return Err(oversized_message_error(&message, instructions, size));
+        }
+        return Ok(message);
+    }
+
+    let message = VersionedMessage::Legacy(Message::new_with_blockhash(
+        instructions,
+        Some(payer),
+        &recent_blockhash,
+    ));
+
+    let size = transaction_size(&message);
+    if size > PACKET_DATA_SIZE {
+        bail!(
+            "Legacy transaction too large ({} accounts, {} bytes > {} bytes), drop --legacy-tx to use the lookup table",
+            message.static_account_keys().len(),
+            size,
+            PACKET_DATA_SIZE
+        );
+    }
+
+    Ok(message)
+}
+
+/// Wire size of the signed transaction of a message (compact signature count + signatures + message)
+pub fn transaction_size(message: &VersionedMessage) -> usize {
+    1 + message.header().num_required_signatures as usize * 64 + message.serialize().len()
+}
+
+/// Error for a V0 message over the packet size, saying which inline accounts the lookup
+/// table would have to hold to make it fit, or else how big each instruction is so some
+/// can be moved to a second transaction
+fn oversized_message_error(
+    message: &VersionedMessage,
+    instructions: &[Instruction],
+    size: usize,
+) -> anyhow::Error {
+    let excess = size - PACKET_DATA_SIZE;
+    // signers and programs can't be loaded from a lookup table
+    let movable: Vec<Pubkey> = message
+        .static_account_keys()
+        .iter()
+        .enumerate()
+        .filter(|(index, key)| {
+            !message.is_signer(*index)
+                && !instructions
+                    .iter()
+                    .any(|instruction| instruction.program_id == **key)
+        })
+        .map(|(_, key)| *key)
+        .collect();
+    // an inline key takes 32 bytes, a looked up one a 1 byte index
+    let needed = excess.div_ceil(31);
+
+    if needed <= movable.len() {
+        return anyhow!(
+            "Transaction too large ({} bytes > {} bytes), add {} of these accounts to the lookup table: {}",
+            size,
+            PACKET_DATA_SIZE,
+            needed,
+            movable
+                .iter()
+                .map(Pubkey::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    let instruction_sizes = instructions
+        .iter()
+        .enumerate()
+        .map(|(index, instruction)| {
+            format!(
+                "#{} {} ({} accounts, {} data bytes)",
+                index,
+                instruction.program_id,
+                instruction.accounts.len(),
+                instruction.data.len()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    anyhow!(
+        "Transaction too large ({} bytes > {} bytes) even with all accounts in the lookup table, move instructions to a second transaction: {}",
+        size,
+        PACKET_DATA_SIZE,
+        instruction_sizes
+    )
+}
+
+/// Print a message built offline, to be signed elsewhere and sent with its signatures
+pub fn print_unsigned_message(message: &VersionedMessage) {
+    println!("Unsigned message (base64):");
+    println!("{}", STANDARD.encode(message.serialize()));
+    println!("Recent blockhash: {}", message.recent_blockhash());
+}
+
+/// Transaction with an empty signature slot for each required signer of the message
+pub fn unsigned_transaction(message: &VersionedMessage) -> VersionedTransaction {
+    VersionedTransaction {
+        signatures: vec![Signature::default(); message.header().num_required_signatures as usize],
+        message: message.clone(),
+    }
+}
+
+/// Unsigned transaction in the base64 wire format web wallet adapters deserialize, with an
+/// empty signature slot for each required signer
+pub fn get_wallet_blob(message: &VersionedMessage) -> Result<String> {
+    encode_wallet_blob(&unsigned_transaction(message))
+}
+
+/// A transaction in the base64 wire format, signed or not
+pub fn encode_wallet_blob(transaction: &VersionedTransaction) -> Result<String> {
+    Ok(STANDARD.encode(bincode::serialize(transaction)?))
+}
+
+/// Decode and sanitize a base64 transaction blob
+pub fn decode_wallet_blob(blob: &str) -> Result<VersionedTransaction> {
+    let bytes = STANDARD
+        .decode(blob.trim())
+        .context("Blob is not valid base64")?;
+    let transaction: VersionedTransaction =
+        bincode::deserialize(&bytes).context("Blob is not a serialized transaction")?;
+    transaction
+        .sanitize()
+        .context("Transaction failed sanitization")?;
+    Ok(transaction)
+}
+
+/// Fill `signer`'s signature slot of a partially signed transaction
+///
+/// The signatures already present are checked first, so a message changed after another
+/// signer signed it is refused instead of being sent with an invalid signature.
+pub fn sign_partial(transaction: &mut VersionedTransaction, signer: &Keypair) -> Result<()> {
+    let message_bytes = transaction.message.serialize();
+    let account_keys = transaction.message.static_account_keys();
+    let index = account_keys
+        .iter()
+        .take(transaction.signatures.len())
+        .position(|key| *key == signer.pubkey())
+        .with_context(|| format!("{} isn't a signer of the transaction", signer.pubkey()))?;
+
+    for (key, signature) in account_keys.iter().zip(&transaction.signatures) {
+        if *signature != Signature::default() && !signature.verify(key.as_ref(), &message_bytes) {
+            bail!("Signature of {} doesn't match the message", key);
+        }
+    }
+
+    transaction.signatures[index] = signer.sign_message(&message_bytes);
+    Ok(())
+}
+
+/// Signers of a transaction whose signature slot is still empty
+pub fn missing_signers(transaction: &VersionedTransaction) -> Vec<Pubkey> {
+    transaction
+        .message
+        .static_account_keys()
+        .iter()
+        .zip(&transaction.signatures)
+        .filter(|(_, signature)| **signature == Signature::default())
+        .map(|(key, _)| *key)
+        .collect()
+}
+
+/// Decode a base64 transaction blob and print its signers, accounts and instructions
+pub fn print_wallet_blob(blob: &str) -> Result<()> {
+    let transaction = decode_wallet_blob(blob)?;
+    let size = bincode::serialized_size(&transaction)?;
+
+    let message = &transaction.message;
+    let header = message.header();
+    let account_keys = message.static_account_keys();
+
+    println!(
+        "Version: {}",
+        match message {
+            VersionedMessage::Legacy(_) => "legacy",
+            VersionedMessage::V0(_) => "v0",
+        }
+    );
+    println!("Size: {} bytes", size);
+    println!("Fee payer: {}", account_keys[0]);
+    println!("Recent blockhash: {}", message.recent_blockhash());
+
+    let message_bytes = message.serialize();
+    println!("Signatures:");
+    for (signer, signature) in account_keys.iter().zip(&transaction.signatures) {
+        let status = if *signature == Signature::default() {
+            "missing"
+        } else if signature.verify(signer.as_ref(), &message_bytes) {
+            "valid"
+        } else {
+            "INVALID"
+        };
+        println!("  {} - {}", signer, status);
+    }
+
+    println!("Accounts:");
+    for (index, key) in account_keys.iter().enumerate() {
+        println!(
+            "  [{}] {}{}{}",
+            index,
+            key,
+            if message.is_signer(index) {
+                " signer"
+            } else {
+                ""
+            },
+            if message.is_maybe_writable(index, None) {
+                " writable"
+            } else {
+                ""
+            }
+        );
+    }
+
+    if let Some(lookups) = message.address_table_lookups() {
+        for lookup in lookups {
+            println!(
+                "Lookup table {}: writable {:?}, readonly {:?}",
+                lookup.account_key, lookup.writable_indexes, lookup.readonly_indexes
+            );
+        }
+    }
+
+    println!("Instructions:");
+    for (index, instruction) in message.instructions().iter().enumerate() {
+        println!(
+            "  #{} program {} - {} accounts, {} bytes of data",
+            index,
+            account_keys[instruction.program_id_index as usize],
+            instruction.accounts.len(),
+            instruction.data.len()
+        );
+    }
+
+    println!(
+        "Header: {} signatures, {} readonly signed, {} readonly unsigned",
+        header.num_required_signatures,
+        header.num_readonly_signed_accounts,
+        header.num_readonly_unsigned_accounts
+    );
+
+    Ok(())
+}
+
+static NONBLOCKING_CLIENT: Mutex<Option<Arc<NonblockingRpcClient>>> = Mutex::new(None);
+
+/// Nonblocking client for the endpoint of `rpc_client`, kept across calls so its
+/// connection is reused
+///
+/// The blocking `RpcClient` can't run requests concurrently, the helpers below run them
+/// on this one instead.
+fn nonblocking_client(rpc_client: &RpcClient) -> Arc<NonblockingRpcClient> {
+    let mut client = NONBLOCKING_CLIENT.lock().unwrap();
+    match client.as_ref() {
+        Some(client) if client.url() == rpc_client.url() => client.clone(),
+        _ => {
+            let new_client = Arc::new(NonblockingRpcClient::new_with_timeout_and_commitment(
+                rpc_client.url(),
+                timeouts().rpc,
+                rpc_client.commitment(),
+            ));
+            *client = Some(new_client.clone());
+            new_client
+        }
+    }
+}
+
+/// Fetch the recent blockhash and the address lookup table concurrently
+pub async fn get_blockhash_and_lookup_table(
+    rpc_client: &RpcClient,
+    lookup_table_pubkey: Pubkey,
+) -> Result<(Hash, AddressLookupTableAccount)> {
+    let nonblocking_client = nonblocking_client(rpc_client);
+
+    let (recent_blockhash, alt_account) = tokio::join!(
+        nonblocking_client.get_latest_blockhash(),
+        nonblocking_client.get_account(&lookup_table_pubkey),
+    );
+
+    let recent_blockhash = recent_blockhash.context("Failed to get recent blockhash")?;
+    let alt_account = alt_account.context("Failed to get address lookup table")?;
+
+    let address_lookup_table = parse_address_lookup_table(lookup_table_pubkey, &alt_account.data)?;
+
+    Ok((recent_blockhash, address_lookup_table))
+}
+
+/// Fetch the current slot and the recent blockhash concurrently, the two reads a finalize
+/// needs once its order is known
+///
+/// The finalize creates missing output token accounts on-chain, so there's nothing else
+/// to look up before building it.
+pub async fn get_slot_and_blockhash(rpc_client: &RpcClient) -> Result<(u64, Hash)> {
+    let nonblocking_client = nonblocking_client(rpc_client);
+
+    let (slot, recent_blockhash) = tokio::join!(
+        nonblocking_client.get_slot(),
+        nonblocking_client.get_latest_blockhash(),
+    );
+
+    Ok((
+        slot.context("Failed to get slot")?,
+        recent_blockhash.context("Failed to get recent blockhash")?,
+    ))
+}
+
+pub async fn get_order(
+    sdk: &DarklakeSDK,
+    order_owner: &Pubkey,
+    rpc_client: &RpcClient,
+) -> Result<Order> {
+    let order_timeout = timeouts().order;
+    let deadline = Instant::now() + order_timeout;
+    for attempt in 1.. {
+        match tokio::time::timeout_at(
+            deadline,
+            sdk.get_order(order_owner, rpc_client.commitment().commitment),
+        )
+        .await
+        {
+            Ok(Ok(result)) => {
+                return Ok(result);
+            }
+            Ok(Err(e)) => {
+                if Instant::now() + Duration::from_secs(5) < deadline {
+                    println!(
+                        "get_order failed (attempt {}): {}. Retrying in 5 seconds...",
+                        attempt, e
+                    );
+                    sleep(Duration::from_secs(5)).await;
+                } else {
+                    return Err(e);
+                }
+            }
+            Err(_) => break,
+        }
+    }
+    Err(anyhow::anyhow!(
+        "Order not available within {:?}",
+        order_timeout
+    ))
+}
+
+/// Derive the WebSocket endpoint from an HTTP RPC endpoint
+///
+/// An endpoint that doesn't parse as a URL is returned unchanged, the subscription then
+/// fails and the callers fall back to polling.
+pub fn get_websocket_url(rpc_url: &str) -> String {
+    let Ok(mut url) = Url::parse(rpc_url) else {
+        return rpc_url.to_string();
+    };
+    let scheme = match url.scheme() {
+        "https" => "wss",
+        "http" => "ws",
+        _ => return rpc_url.to_string(),
+    };
+    // http(s) to ws(s) stays among the special schemes, so this can't fail
+    let _ = url.set_scheme(scheme);
+
+    // solana-test-validator serves WebSocket on the RPC port + 1
+    if url.port() == Some(8899) {
+        let _ = url.set_port(Some(8900));
+    }
+    url.to_string()
+}
+
+fn print_slot_eta(current_slot: u64, target_slot: u64) {
+    let remaining_slots = target_slot.saturating_sub(current_slot);
+    let eta_secs = (remaining_slots * DEFAULT_MS_PER_SLOT) as f64 / 1_000.0;
+    println!(
+        "Waiting for slot {}: current slot {}, {} slots left (~{:.1}s)",
+        target_slot, current_slot, remaining_slots, eta_secs
+    );
+}
+
+/// Wait until the cluster reaches `target_slot`, printing an ETA on every new slot
+///
+/// Uses a slot subscription on the RPC WebSocket endpoint and falls back to polling
+/// `get_slot` if the subscription can't be established. Returns the slot that was reached.
+pub async fn wait_for_slot(rpc_client: &RpcClient, target_slot: u64) -> Result<u64> {
+    let current_slot = rpc_client.get_slot().context("Failed to get slot")?;
+    if current_slot >= target_slot {
+        return Ok(current_slot);
+    }
+
+    print_slot_eta(current_slot, target_slot);
+
+    let ws_url = get_websocket_url(&rpc_client.url());
+
+    match PubsubClient::slot_subscribe(&ws_url) {
+        Ok((mut subscription, receiver)) => {
+            let mut last_slot = current_slot;
+            let result = loop {
+                // a few missed slots are fine, a silent socket for this long is not
+                match receiver.recv_timeout(timeouts().websocket) {
+                    Ok(slot_info) => {
+                        if slot_info.slot <= last_slot {
+                            continue;
+                        }
+                        last_slot = slot_info.slot;
+
+                        if slot_info.slot >= target_slot {
+                            break Ok(slot_info.slot);
+                        }
+                        print_slot_eta(slot_info.slot, target_slot);
+                    }
+                    Err(e) => break Err(e),
+                }
+            };
+
+            let _ = subscription.shutdown();
+
+            match result {
+                Ok(slot) => return Ok(slot),
+                Err(e) => println!("Slot subscription stalled ({}), falling back to polling", e),
+            }
+        }
+        Err(e) => println!("Slot subscription failed ({}), falling back to polling", e),
+    }
+
+    loop {
+        let current_slot = rpc_client.get_slot().context("Failed to get slot")?;
+        if current_slot >= target_slot {
+            return Ok(current_slot);
+        }
+        print_slot_eta(current_slot, target_slot);
+        sleep(Duration::from_millis(DEFAULT_MS_PER_SLOT)).await;
+    }
+}
+
+/// Account change: slot, index of the account in the subscribed addresses, new state
+/// (`None` once closed)
+pub type AccountUpdate = (u64, usize, Option<Account>);
+
+/// Subscribe to changes of several accounts, all updates arrive on one channel
+///
+/// The subscriptions stop when the returned handles are dropped.
+pub fn subscribe_accounts(
+    rpc_client: &RpcClient,
+    addresses: &[Pubkey],
+) -> Result<(
+    Vec<PubsubAccountClientSubscription>,
+    mpsc::Receiver<AccountUpdate>,
+)> {
+    let ws_url = get_websocket_url(&rpc_client.url());
+    let (sender, receiver) = mpsc::channel();
+    let mut subscriptions = Vec::new();
+
+    for (index, address) in addresses.iter().enumerate() {
+        let (subscription, updates) = PubsubClient::account_subscribe(
+            &ws_url,
+            address,
+            Some(RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                commitment: Some(rpc_client.commitment()),
+                ..RpcAccountInfoConfig::default()
+            }),
+        )
+        .with_context(|| format!("Failed to subscribe to account {}", address))?;
+        subscriptions.push(subscription);
+
+        let sender = sender.clone();
+        std::thread::spawn(move || {
+            for update in updates {
+                let account = update
+                    .value
+                    .decode::<Account>()
+                    .filter(|account| account.lamports > 0);
+                if sender.send((update.context.slot, index, account)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    Ok((subscriptions, receiver))
+}
+
+// The SDK keeps its PDA helpers private, so the pool accounts are re-derived here
+const DARKLAKE_PROGRAM_ID: Pubkey = pubkey!("darkr3FB87qAZmgLwKov6Hk9Yiah5UT4rUYu8Zhthw1");
+// Placeholder mint the SDK accepts for native SOL
+const SOL_MINT: Pubkey = pubkey!("So11111111111111111111111111111111111111111");
+
+pub fn get_amm_config_address() -> Pubkey {
+    Pubkey::find_program_address(&[b"amm_config", &0u32.to_le_bytes()], &DARKLAKE_PROGRAM_ID).0
+}
+
+/// Mint the pool stores for a token, SOL is held as WSOL
+pub fn to_pool_mint(token_mint: &Pubkey) -> Pubkey {
+    if *token_mint == SOL_MINT {
+        native_mint::ID
+    } else {
+        *token_mint
+    }
+}
+
+/// Pool address of a pair, mints can be passed in any order (SOL has to be passed as WSOL)
+pub fn get_pool_address(token_mint_a: &Pubkey, token_mint_b: &Pubkey) -> Pubkey {
+    let (token_mint_x, token_mint_y) = if token_mint_a < token_mint_b {
+        (token_mint_a, token_mint_b)
+    } else {
+        (token_mint_b, token_mint_a)
+    };
+
+    Pubkey::find_program_address(
+        &[
+            b"pool",
+            get_amm_config_address().as_ref(),
+            token_mint_x.as_ref(),
+            token_mint_y.as_ref(),
+        ],
+        &DARKLAKE_PROGRAM_ID,
+    )
+    .0
+}
+
+pub fn get_token_mint_lp(pool: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"lp", pool.as_ref()], &DARKLAKE_PROGRAM_ID).0
+}
+
+pub fn get_pool_reserve(pool: &Pubkey, token_mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"pool_reserve", pool.as_ref(), token_mint.as_ref()],
+        &DARKLAKE_PROGRAM_ID,
+    )
+    .0
+}
+
+pub fn get_pool_wsol_reserve(pool: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"pool_wsol_reserve", pool.as_ref()], &DARKLAKE_PROGRAM_ID).0
+}
+
+pub fn get_order_address(pool: &Pubkey, user: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"order", pool.as_ref(), user.as_ref()],
+        &DARKLAKE_PROGRAM_ID,
+    )
+    .0
+}
+
+pub fn get_order_wsol_address(pool: &Pubkey, user: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"order_wsol", pool.as_ref(), user.as_ref()],
+        &DARKLAKE_PROGRAM_ID,
+    )
+    .0
+}
+
+/// Order a finalize message settles, taken from its Darklake instruction's accounts
+///
+/// Settle, cancel and slash all pass the order owner second and the pool sixth, so the
+/// order is derived from those and checked against the instruction's account list.
+/// Accounts loaded from a lookup table are resolved over RPC.
+pub fn get_finalize_order_address(
+    rpc_client: &RpcClient,
+    message: &VersionedMessage,
+) -> Result<Pubkey> {
+    let mut account_keys = message.static_account_keys().to_vec();
+    if let Some(lookups) = message.address_table_lookups() {
+        let tables = lookups
+            .iter()
+            .map(|lookup| {
+                let account = rpc_client
+                    .get_account(&lookup.account_key)
+                    .context("Failed to get address lookup table")?;
+                parse_address_lookup_table(lookup.account_key, &account.data)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        // loaded addresses follow the static keys, writable ones of every table first
+        for writable in [true, false] {
+            for (lookup, table) in lookups.iter().zip(&tables) {
+                let indexes = if writable {
+                    &lookup.writable_indexes
+                } else {
+                    &lookup.readonly_indexes
+                };
+                for index in indexes {
+                    account_keys.push(*table.addresses.get(*index as usize).with_context(
+                        || format!("Lookup table {} has no index {}", table.key, index),
+                    )?);
+                }
+            }
+        }
+    }
+
+    let instruction = message
+        .instructions()
+        .iter()
+        .find(|instruction| {
+            account_keys.get(instruction.program_id_index as usize) == Some(&DARKLAKE_PROGRAM_ID)
+        })
+        .context("The transaction has no Darklake instruction")?;
+    let accounts = instruction
+        .accounts
+        .iter()
+        .map(|index| {
+            account_keys
+                .get(*index as usize)
+                .copied()
+                .with_context(|| format!("The Darklake instruction has no account {}", index))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let (Some(order_owner), Some(pool)) = (accounts.get(1), accounts.get(5)) else {
+        bail!(
+            "The Darklake instruction has only {} accounts",
+            accounts.len()
+        );
+    };
+    let order_address = get_order_address(pool, order_owner);
+    if !accounts.contains(&order_address) {
+        bail!(
+            "The Darklake instruction doesn't finalize an order of {} on pool {}",
+            order_owner,
+            pool
+        );
+    }
+
+    Ok(order_address)
+}
+
+/// Program addresses of a user's order on a pair, derived without any RPC
+pub struct OrderAddresses {
+    pub token_mint_x: Pubkey,
+    pub token_mint_y: Pubkey,
+    pub pool: Pubkey,
+    pub amm_config: Pubkey,
+    pub token_mint_lp: Pubkey,
+    pub reserve_x: Pubkey,
+    pub reserve_y: Pubkey,
+    pub order: Pubkey,
+    /// WSOL account of the order, only used on pairs with SOL
+    pub order_wsol: Option<Pubkey>,
+}
+
+impl OrderAddresses {
+    pub fn print(&self) {
+        println!("Pool:        {}", self.pool);
+        println!("AMM config:  {}", self.amm_config);
+        println!("LP mint:     {}", self.token_mint_lp);
+        println!("Reserve X:   {}", self.reserve_x);
+        println!("Reserve Y:   {}", self.reserve_y);
+        println!("Order:       {}", self.order);
+        if let Some(order_wsol) = self.order_wsol {
+            println!("Order WSOL:  {}", order_wsol);
+        }
+    }
+}
+
+/// Order, pool and vault addresses of `owner` on a pair, mints can be passed in any order
+pub fn derive_order_addresses(
+    owner: &Pubkey,
+    token_mint_a: &Pubkey,
+    token_mint_b: &Pubkey,
+) -> OrderAddresses {
+    let token_mint_a = to_pool_mint(token_mint_a);
+    let token_mint_b = to_pool_mint(token_mint_b);
+    let (token_mint_x, token_mint_y) = if token_mint_a < token_mint_b {
+        (token_mint_a, token_mint_b)
+    } else {
+        (token_mint_b, token_mint_a)
+    };
+    let pool = get_pool_address(&token_mint_x, &token_mint_y);
+
+    OrderAddresses {
+        token_mint_x,
+        token_mint_y,
+        pool,
+        amm_config: get_amm_config_address(),
+        token_mint_lp: get_token_mint_lp(&pool),
+        reserve_x: get_pool_reserve(&pool, &token_mint_x),
+        reserve_y: get_pool_reserve(&pool, &token_mint_y),
+        order: get_order_address(&pool, owner),
+        order_wsol: (token_mint_x == native_mint::ID || token_mint_y == native_mint::ID)
+            .then(|| get_order_wsol_address(&pool, owner)),
+    }
+}
+
+/// Pool account state, the SDK doesn't expose its own pool type
+pub struct PoolState {
+    pub amm_config: Pubkey,
+    pub token_mint_x: Pubkey,
+    pub token_mint_y: Pubkey,
+    pub reserve_x: Pubkey,
+    pub reserve_y: Pubkey,
+    pub token_lp_supply: u64,
+    pub protocol_fee_x: u64,
+    pub protocol_fee_y: u64,
+    pub locked_x: u64,
+    pub locked_y: u64,
+    pub user_locked_x: u64,
+    pub user_locked_y: u64,
+}
+
+impl PoolState {
+    /// Reserves available for trading given the reserve token account balances
+    pub fn available_reserves(&self, balance_x: u64, balance_y: u64) -> (u64, u64) {
+        (
+            balance_x
+                .saturating_sub(self.protocol_fee_x)
+                .saturating_sub(self.locked_x)
+                .saturating_sub(self.user_locked_x),
+            balance_y
+                .saturating_sub(self.protocol_fee_y)
+                .saturating_sub(self.locked_y)
+                .saturating_sub(self.user_locked_y),
+        )
+    }
+}
+
+/// Amm config fields, shared by every pool using the config
+pub struct AmmConfigState {
+    pub trade_fee_rate: u64,
+    pub protocol_fee_rate: u64,
+    pub deadline_slot_duration: u64,
+    pub ratio_change_tolerance_rate: u64,
+    pub halted: bool,
+}
+
+fn read_pubkey(data: &[u8], offset: usize) -> Result<Pubkey> {
+    let bytes: [u8; 32] = data
+        .get(offset..offset + 32)
+        .context("Account data too short")?
+        .try_into()?;
+    Ok(Pubkey::new_from_array(bytes))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64> {
+    let bytes: [u8; 8] = data
+        .get(offset..offset + 8)
+        .context("Account data too short")?
+        .try_into()?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Parse a pool account
+///
+/// Layout after the 8 byte discriminator: creator, amm_config, token_mint_x, token_mint_y,
+/// reserve_x, reserve_y (pubkeys), then token_lp_supply, protocol_fee_x, protocol_fee_y,
+/// locked_x, locked_y, user_locked_x, user_locked_y (u64).
+pub fn parse_pool_state(data: &[u8]) -> Result<PoolState> {
+    let key_at = |index: usize| read_pubkey(data, 8 + index * 32);
+    let u64_at = |index: usize| read_u64(data, 8 + 6 * 32 + index * 8);
+
+    Ok(PoolState {
+        amm_config: key_at(1)?,
+        token_mint_x: key_at(2)?,
+        token_mint_y: key_at(3)?,
+        reserve_x: key_at(4)?,
+        reserve_y: key_at(5)?,
+        token_lp_supply: u64_at(0)?,
+        protocol_fee_x: u64_at(1)?,
+        protocol_fee_y: u64_at(2)?,
+        locked_x: u64_at(3)?,
+        locked_y: u64_at(4)?,
+        user_locked_x: u64_at(5)?,
+        user_locked_y: u64_at(6)?,
+    })
+}
+
+/// Order fields the wallet watcher prints, the SDK only parses orders it loaded itself
+pub struct OrderSummary {
+    pub actual_in: u64,
+    pub actual_out: u64,
+    pub deadline: u64,
+    pub is_x_to_y: bool,
+}
+
+/// Parse an order account
+///
+/// Layout after the 8 byte discriminator: trader, token_mint_x, token_mint_y (pubkeys),
+/// actual_in, exchange_in, actual_out, from_to_lock, d_in, d_out, deadline, protocol_fee,
+/// wsol_deposit (u64), c_min ([u8; 32]), is_x_to_y.
+pub fn parse_order_summary(data: &[u8]) -> Result<OrderSummary> {
+    let u64_at = |index: usize| read_u64(data, 8 + 3 * 32 + index * 8);
+
+    Ok(OrderSummary {
+        actual_in: u64_at(0)?,
+        actual_out: u64_at(2)?,
+        deadline: u64_at(6)?,
+        is_x_to_y: *data
+            .get(8 + 3 * 32 + 9 * 8 + 32)
+            .context("Account data too short")?
+            != 0,
+    })
+}
+
+/// Parse an amm config account
+///
+/// Layout after the 8 byte discriminator: trade_fee_rate, create_pool_fee, protocol_fee_rate,
+/// wsol_trade_deposit, deadline_slot_duration, ratio_change_tolerance_rate (u64), bump, halted.
+pub fn parse_amm_config(data: &[u8]) -> Result<AmmConfigState> {
+    let u64_at = |index: usize| read_u64(data, 8 + index * 8);
+
+    Ok(AmmConfigState {
+        trade_fee_rate: u64_at(0)?,
+        protocol_fee_rate: u64_at(2)?,
+        deadline_slot_duration: u64_at(4)?,
+        ratio_change_tolerance_rate: u64_at(5)?,
+        halted: *data.get(8 + 6 * 8 + 1).context("Account data too short")? != 0,
+    })
+}
+
+/// Pool checks run before swapping
+pub struct PoolHealth {
+    pub pool: Pubkey,
+    pub token_x: TokenInfo,
+    pub token_y: TokenInfo,
+    pub available_x: u64,
+    pub available_y: u64,
+    pub halted: bool,
+    /// How far the slot the accounts were read at trails the cluster
+    pub data_slot_lag: u64,
+    pub issues: Vec<String>,
+}
+
+impl PoolHealth {
+    pub fn is_healthy(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    pub fn print(&self) {
+        println!("Pool:        {}", self.pool);
+        println!("Reserve X:   {}", self.token_x.format(self.available_x));
+        println!("Reserve Y:   {}", self.token_y.format(self.available_y));
+        println!("Halted:      {}", self.halted);
+        println!("Data lag:    {} slots", self.data_slot_lag);
+        if self.is_healthy() {
+            println!("Status:      healthy");
+        } else {
+            println!("Status:      unhealthy");
+            for issue in &self.issues {
+                println!("  - {}", issue);
+            }
+        }
+    }
+}
+
+/// Reserves of a pool available for trading and withdrawals
+pub fn get_available_reserves(rpc_client: &RpcClient, pool: &PoolState) -> Result<(u64, u64)> {
+    let accounts = rpc_client
+        .get_multiple_accounts(&[pool.reserve_x, pool.reserve_y])
+        .context("Failed to get pool reserves")?;
+    let [reserve_x, reserve_y] = <[_; 2]>::try_from(accounts)
+        .map_err(|_| anyhow!("Unexpected number of accounts returned"))?;
+
+    let balance_x = unpack_token_account(&reserve_x.context("Reserve X not found")?.data)?.amount;
+    let balance_y = unpack_token_account(&reserve_y.context("Reserve Y not found")?.data)?.amount;
+
+    Ok(pool.available_reserves(balance_x, balance_y))
+}
+
+/// Read a pool, its reserve balances and amm config into a snapshot for the offline quote math
+pub fn get_pool_snapshot(
+    rpc_client: &RpcClient,
+    pool_key: &Pubkey,
+) -> Result<(PoolState, PoolSnapshot)> {
+    let pool_account = rpc_client
+        .get_account(pool_key)
+        .context("Failed to get pool account")?;
+    let pool = parse_pool_state(&pool_account.data)?;
+
+    let accounts = rpc_client
+        .get_multiple_accounts(&[pool.amm_config, pool.reserve_x, pool.reserve_y])
+        .context("Failed to get amm config and pool reserves")?;
+    let [amm_config, reserve_x, reserve_y] = <[_; 3]>::try_from(accounts)
+        .map_err(|_| anyhow!("Unexpected number of accounts returned"))?;
+
+    let snapshot = build_pool_snapshot(&pool, amm_config, reserve_x, reserve_y)?;
+
+    Ok((pool, snapshot))
+}
+
+fn build_pool_snapshot(
+    pool: &PoolState,
+    amm_config: Option<Account>,
+    reserve_x: Option<Account>,
+    reserve_y: Option<Account>,
+) -> Result<PoolSnapshot> {
+    let amm_config = parse_amm_config(&amm_config.context("Amm config not found")?.data)?;
+    let balance_x = unpack_token_account(&reserve_x.context("Reserve X not found")?.data)?.amount;
+    let balance_y = unpack_token_account(&reserve_y.context("Reserve Y not found")?.data)?.amount;
+
+    Ok(PoolSnapshot {
+        reserve_x_balance: balance_x,
+        reserve_y_balance: balance_y,
+        protocol_fee_x: pool.protocol_fee_x,
+        protocol_fee_y: pool.protocol_fee_y,
+        locked_x: pool.locked_x,
+        locked_y: pool.locked_y,
+        user_locked_x: pool.user_locked_x,
+        user_locked_y: pool.user_locked_y,
+        trade_fee_rate: amm_config.trade_fee_rate,
+        protocol_fee_rate: amm_config.protocol_fee_rate,
+        ratio_change_tolerance_rate: amm_config.ratio_change_tolerance_rate,
+    })
+}
+
+// Most accounts a getMultipleAccounts request accepts
+const MAX_MULTIPLE_ACCOUNTS: usize = 100;
+
+fn get_accounts_chunked(
+    rpc_client: &RpcClient,
+    addresses: &[Pubkey],
+) -> Result<(u64, Vec<Option<Account>>)> {
+    let mut slot = None;
+    let mut accounts = Vec::with_capacity(addresses.len());
+    for chunk in addresses.chunks(MAX_MULTIPLE_ACCOUNTS) {
+        let response = rpc_client
+            .get_multiple_accounts_with_commitment(chunk, rpc_client.commitment())
+            .context("Failed to get accounts")?;
+        slot = Some(slot.map_or(response.context.slot, |slot: u64| {
+            slot.min(response.context.slot)
+        }));
+        accounts.extend(response.value);
+    }
+    Ok((slot.unwrap_or_default(), accounts))
+}
+
+/// Quotes of `quote_batch`, with the slot the pools were read at
+pub struct QuoteBatch {
+    pub slot: u64,
+    pub quotes: Vec<Result<OfflineQuote>>,
+}
+
+/// Exact-in quotes of many (mint_in, mint_out, amount_in) entries, in the same order
+///
+/// Every pool is read once however many entries it has, and all pools are read together:
+/// one request for the pools, one for their amm configs and reserves. Each entry gets its
+/// own result, so a missing pool or a trade too big only fails that entry.
+pub fn quote_batch(
+    rpc_client: &RpcClient,
+    requests: &[(Pubkey, Pubkey, u64)],
+) -> Result<QuoteBatch> {
+    let pool_mints: Vec<_> = requests
+        .iter()
+        .map(|(token_mint_in, token_mint_out, _)| {
+            (to_pool_mint(token_mint_in), to_pool_mint(token_mint_out))
+        })
+        .collect();
+    let mut pool_keys: Vec<_> = pool_mints
+        .iter()
+        .map(|(token_mint_in, token_mint_out)| get_pool_address(token_mint_in, token_mint_out))
+        .collect();
+    pool_keys.sort();
+    pool_keys.dedup();
+
+    let (slot, pools) = get_accounts_chunked(rpc_client, &pool_keys)?;
+    let pools: Vec<Option<PoolState>> = pools
+        .into_iter()
+        .map(|account| account.and_then(|account| parse_pool_state(&account.data).ok()))
+        .collect();
+    let addresses: Vec<_> = pools
+        .iter()
+        .flatten()
+        .flat_map(|pool| [pool.amm_config, pool.reserve_x, pool.reserve_y])
+        .collect();
+    let mut accounts = get_accounts_chunked(rpc_client, &addresses)?.1.into_iter();
+
+    // errors are kept as text, an entry per request is built from them
+    let mut snapshots = HashMap::new();
+    for (pool_key, pool) in pool_keys.iter().zip(pools) {
+        let snapshot = match pool {
+            Some(pool) => {
+                let mut next = || accounts.next().flatten();
+                build_pool_snapshot(&pool, next(), next(), next())
+                    .map(|snapshot| (pool.token_mint_x, snapshot))
+                    .map_err(|e| format!("{:#}", e))
+            }
+            None => Err("Pool not found".to_string()),
+        };
+        snapshots.insert(*pool_key, snapshot);
+    }
+
+    let quotes = requests
+        .iter()
+        .zip(pool_mints)
+        .map(|((_, _, amount_in), (token_mint_in, token_mint_out))| {
+            match &snapshots[&get_pool_address(&token_mint_in, &token_mint_out)] {
+                Ok((token_mint_x, snapshot)) => {
+                    math::quote(snapshot, *amount_in, token_mint_in == *token_mint_x)
+                }
+                Err(e) => Err(anyhow!("{}", e)),
+            }
+        })
+        .collect();
+
+    Ok(QuoteBatch { slot, quotes })
+}
+
+static VALUE_MINT: OnceLock<Pubkey> = OnceLock::new();
+
+/// Also value balances in this token (`--value-in`)
+pub fn set_value_mint(value_mint: Pubkey) {
+    let _ = VALUE_MINT.set(value_mint);
+}
+
+pub fn value_mint() -> Option<Pubkey> {
+    VALUE_MINT.get().copied()
+}
+
+/// Token amounts valued in another token, see `value_amounts`
+pub struct Valuations {
+    /// Token the values are in
+    pub token: TokenInfo,
+    /// Value of each amount, `None` when no Darklake pool pairs it with the valuation token
+    pub values: Vec<Option<u64>>,
+    /// Slot the pools were read at
+    pub slot: u64,
+    /// Set when the node serving the pools was more than `MAX_DATA_SLOT_LAG` slots behind
+    pub stale: bool,
+}
+
+impl Valuations {
+    /// Sum of the values, failing instead of wrapping on overflow
+    pub fn total(&self) -> Result<u64> {
+        self.values
+            .iter()
+            .flatten()
+            .try_fold(self.token.amount(0), |total, value| {
+                total.checked_add(self.token.amount(*value))
+            })
+            .map(|total| total.raw())
+    }
+
+    /// Value of the `index`th amount, e.g. `= 1.5 SOL`, marked when stale
+    pub fn format(&self, index: usize) -> String {
+        match self.values[index] {
+            Some(value) => format!(
+                "= {}{}",
+                self.token.format(value),
+                if self.stale { " (stale)" } else { "" }
+            ),
+            None => "no Darklake pool to value it".to_string(),
+        }
+    }
+}
+
+/// Value each (mint, amount) at what swapping it into `value_mint` on Darklake would
+/// return, price impact included
+///
+/// Quoted offline from one read of the pools, see `quote_batch`.
+pub fn value_amounts(
+    rpc_client: &RpcClient,
+    amounts: &[(Pubkey, u64)],
+    value_mint: &Pubkey,
+) -> Result<Valuations> {
+    let value_mint = to_pool_mint(value_mint);
+    let requests: Vec<_> = amounts
+        .iter()
+        .filter(|(mint, amount)| to_pool_mint(mint) != value_mint && *amount > 0)
+        .map(|(mint, amount)| (*mint, value_mint, *amount))
+        .collect();
+    let batch = quote_batch(rpc_client, &requests)?;
+    let data_slot_lag = rpc_client
+        .get_slot_with_commitment(CommitmentConfig::processed())?
+        .saturating_sub(batch.slot);
+
+    let mut quotes = batch.quotes.into_iter();
+    let values = amounts
+        .iter()
+        .map(|(mint, amount)| {
+            if to_pool_mint(mint) == value_mint || *amount == 0 {
+                return Some(*amount);
+            }
+            quotes
+                .next()
+                .and_then(|quote| quote.ok())
+                .map(|quote| quote.to_amount)
+        })
+        .collect();
+
+    Ok(Valuations {
+        token: get_token_infos(rpc_client, &[value_mint])?.remove(0),
+        values,
+        slot: batch.slot,
+        stale: !requests.is_empty() && data_slot_lag > MAX_DATA_SLOT_LAG,
+    })
+}
+
+/// Expected and minimum outputs of burning LP tokens, in the pool's x / y order
+pub struct RemoveLiquidityQuote {
+    pub token_mint_x: Pubkey,
+    pub token_x: TokenInfo,
+    pub token_y: TokenInfo,
+    pub expected_x: u64,
+    pub expected_y: u64,
+    pub min_amount_x: u64,
+    pub min_amount_y: u64,
+}
+
+impl RemoveLiquidityQuote {
+    /// Minimum amounts ordered with `token_mint` first (SOL is matched as WSOL)
+    pub fn min_amounts_for(&self, token_mint: &Pubkey) -> (u64, u64) {
+        if to_pool_mint(token_mint) == self.token_mint_x {
+            (self.min_amount_x, self.min_amount_y)
+        } else {
+            (self.min_amount_y, self.min_amount_x)
+        }
+    }
+
+    pub fn print(&self) {
+        println!(
+            "Expected: {} / {}, minimum: {} / {}",
+            self.token_x.format(self.expected_x),
+            self.token_y.format(self.expected_y),
+            self.token_x.format(self.min_amount_x),
+            self.token_y.format(self.min_amount_y)
+        );
+    }
+}
+
+/// Quote burning `amount_lp` as the LP share of the pool's available reserves, with the
+/// minimums reduced by `slippage_bps`
+pub fn quote_remove_liquidity(
+    rpc_client: &RpcClient,
+    token_mint_a: &Pubkey,
+    token_mint_b: &Pubkey,
+    amount_lp: u64,
+    slippage_bps: u64,
+) -> Result<RemoveLiquidityQuote> {
+    let pool_key = get_pool_address(&to_pool_mint(token_mint_a), &to_pool_mint(token_mint_b));
+
+    let pool_account = rpc_client
+        .get_account(&pool_key)
+        .context("Failed to get pool account")?;
+    let pool = parse_pool_state(&pool_account.data)?;
+    let (available_x, available_y) = get_available_reserves(rpc_client, &pool)?;
+
+    if pool.token_lp_supply == 0 {
+        bail!("Pool has no LP supply");
+    }
+
+    let share =
+        |reserve: u64| (amount_lp as u128 * reserve as u128 / pool.token_lp_supply as u128) as u64;
+
+    let [token_x, token_y] = <[_; 2]>::try_from(get_token_infos(
+        rpc_client,
+        &[pool.token_mint_x, pool.token_mint_y],
+    )?)
+    .map_err(|_| anyhow!("Unexpected number of token mints returned"))?;
+    let expected_x = token_x.amount(share(available_x));
+    let expected_y = token_y.amount(share(available_y));
+
+    Ok(RemoveLiquidityQuote {
+        token_mint_x: pool.token_mint_x,
+        token_x,
+        token_y,
+        expected_x: expected_x.raw(),
+        expected_y: expected_y.raw(),
+        min_amount_x: expected_x.less_bps(slippage_bps)?.raw(),
+        min_amount_y: expected_y.less_bps(slippage_bps)?.raw(),
+    })
+}
+
+// Account data older than this (about a minute) is considered stale
+const MAX_DATA_SLOT_LAG: u64 = 150;
+
+/// An RPC node this many slots (about 10 seconds) behind the reference is considered degraded
+pub const MAX_RPC_SLOT_LAG: u64 = 25;
+
+/// How many slots the RPC node is behind a reference endpoint
+///
+/// A lagging node serves stale quotes and makes the finalize deadline look
+/// further away than it is.
+pub fn get_rpc_slot_lag(rpc_client: &RpcClient, reference_url: &str) -> Result<u64> {
+    let reference_client = RpcClient::new_with_timeout_and_commitment(
+        reference_url.to_string(),
+        timeouts().rpc,
+        rpc_client.commitment(),
+    );
+
+    let slot = rpc_client.get_slot().context("Failed to get slot")?;
+    let reference_slot = reference_client
+        .get_slot()
+        .context("Failed to get slot from the reference RPC")?;
+
+    Ok(reference_slot.saturating_sub(slot))
+}
+
+/// Check a pool exists, isn't halted, has liquidity on both sides and was read from a fresh node
+///
+/// There's no oracle in the examples, so prices aren't checked against an external reference.
+pub fn check_pool_health(rpc_client: &RpcClient, pool_key: &Pubkey) -> Result<PoolHealth> {
+    let response = rpc_client
+        .get_account_with_commitment(pool_key, rpc_client.commitment())
+        .context("Failed to get pool account")?;
+    let pool_account = response.value.context("Pool account not found")?;
+    let pool = parse_pool_state(&pool_account.data)?;
+
+    let amm_config = rpc_client
+        .get_account(&pool.amm_config)
+        .context("Failed to get amm config")?;
+    let amm_config = parse_amm_config(&amm_config.data)?;
+    let (available_x, available_y) = get_available_reserves(rpc_client, &pool)?;
+
+    let data_slot_lag = rpc_client
+        .get_slot_with_commitment(CommitmentConfig::processed())?
+        .saturating_sub(response.context.slot);
+
+    let mut issues = Vec::new();
+    if amm_config.halted {
+        issues.push("trading is halted by the amm config".to_string());
+    }
+    if available_x == 0 || available_y == 0 {
+        issues.push("pool has no available liquidity on one side".to_string());
+    }
+    if data_slot_lag > MAX_DATA_SLOT_LAG {
+        issues.push(format!(
+            "account data is {} slots behind the cluster",
+            data_slot_lag
+        ));
+    }
+
+    let [token_x, token_y] = <[_; 2]>::try_from(get_token_infos(
+        rpc_client,
+        &[pool.token_mint_x, pool.token_mint_y],
+    )?)
+    .map_err(|_| anyhow!("Unexpected number of token mints returned"))?;
+
+    Ok(PoolHealth {
+        pool: *pool_key,
+        token_x,
+        token_y,
+        available_x,
+        available_y,
+        halted: amm_config.halted,
+        data_slot_lag,
+        issues,
+    })
+}
+
+/// Write an account as a `solana-test-validator --account` JSON file, returns the file path
+pub fn write_account_json(dir: &Path, address: &Pubkey, account: &Account) -> Result<PathBuf> {
+    let json = serde_json::json!({
+        "pubkey": address.to_string(),
+        "account": {
+            "lamports": account.lamports,
+            "data": [STANDARD.encode(&account.data), "base64"],
+            "owner": account.owner.to_string(),
+            "executable": account.executable,
+            "rentEpoch": account.rent_epoch,
+            "space": account.data.len(),
+        },
+    });
+
+    let path = dir.join(format!("{}.json", address));
+    fs::write(&path, serde_json::to_string_pretty(&json)?)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    Ok(path)
+}
+
+/// Prices of `base` in whole `quote` tokens derived from quoting the pair in both directions
+pub struct MidPrice {
+    /// Price received when selling `base`
+    pub bid: f64,
+    /// Price paid when buying `base`
+    pub ask: f64,
+}
+
+impl MidPrice {
+    /// Bid / ask from a round trip: selling `sell.0` base for `sell.1` quote, then buying
+    /// `buy.1` base with `buy.0` quote (amounts in base units, scaled by the mints' decimals)
+    pub fn from_round_trip(
+        base: &TokenInfo,
+        quote: &TokenInfo,
+        sell: (u64, u64),
+        buy: (u64, u64),
+    ) -> Result<Self> {
+        if sell.1 == 0 || buy.1 == 0 {
+            bail!(
+                "{} / {} quotes zero output, the amount is too small or the pool is empty",
+                base.symbol,
+                quote.symbol
+            );
+        }
+        let price = |base_amount: u64, quote_amount: u64| {
+            quote.amount(quote_amount).to_ui() / base.amount(base_amount).to_ui()
+        };
+
+        Ok(MidPrice {
+            bid: price(sell.0, sell.1),
+            ask: price(buy.1, buy.0),
+        })
+    }
+
+    pub fn mid(&self) -> f64 {
+        (self.bid + self.ask) / 2.0
+    }
+
+    pub fn spread_bps(&self) -> f64 {
+        (self.ask - self.bid) / self.mid() * 10_000.0
+    }
+}
+
+/// Quote `amount` of `base` into `quote` and the received amount back, to derive bid / ask
+///
+/// Fails when either leg quotes zero output, which would make the price 0 or infinite.
+pub async fn get_mid_price(
+    sdk: &mut DarklakeSDK,
+    base: &TokenInfo,
+    quote: &TokenInfo,
+    amount: u64,
+) -> Result<MidPrice> {
+    let sell = sdk.quote(&base.mint, &quote.mint, amount).await?;
+
+    // buy back with the same notional in quote token
+    let buy = sdk
+        .quote(&quote.mint, &base.mint, sell.out_amount.max(1))
+        .await?;
+
+    MidPrice::from_round_trip(
+        base,
+        quote,
+        (amount, sell.out_amount),
+        (sell.out_amount, buy.out_amount),
+    )
+}
+
+/// Account created by a message
+pub struct NewAccount {
+    pub address: Pubkey,
+    /// What the account is, e.g. "order" or "user token account"
+    pub label: &'static str,
+    /// Rent-exempt lamports locked in the account
+    pub rent: u64,
+}
+
+/// Expected SOL cost of sending a message
+pub struct FeeEstimate {
+    /// Base fee returned by `getFeeForMessage`
+    pub network_fee: u64,
+    /// Median recent prioritization fee for the written accounts, in micro-lamports per CU
+    pub priority_fee_per_cu: u64,
+    pub priority_fee: u64,
+    /// Accounts the message would create and the rent they lock up
+    pub new_accounts: Vec<NewAccount>,
+    /// Set when the rent couldn't be determined through simulation
+    pub rent_error: Option<String>,
+}
+
+impl FeeEstimate {
+    pub fn rent(&self) -> u64 {
+        self.new_accounts.iter().map(|account| account.rent).sum()
+    }
+
+    pub fn total(&self) -> u64 {
+        self.network_fee + self.priority_fee + self.rent()
+    }
+
+    pub fn print(&self) {
+        println!("Network fee:  {} SOL", lamports_to_sol(self.network_fee));
+        println!(
+            "Priority fee: {} SOL ({} micro-lamports/CU)",
+            lamports_to_sol(self.priority_fee),
+            self.priority_fee_per_cu
+        );
+        match &self.rent_error {
+            Some(e) => println!("Rent:         unknown ({})", e),
+            None => {
+                println!("Rent:         {} SOL", lamports_to_sol(self.rent()));
+                for account in &self.new_accounts {
+                    println!(
+                        "  {} ({}) - {} SOL",
+                        account.address,
+                        account.label,
+                        lamports_to_sol(account.rent)
+                    );
+                }
+            }
+        }
+        println!("Total:        {} SOL", lamports_to_sol(self.total()));
+    }
+}
+
+/// Name an account of a message by matching it against the PDAs / ATAs the message's
+/// other accounts imply (order, reserves, LP mint, pool, payer token accounts)
+fn label_account(address: &Pubkey, payer: &Pubkey, account_keys: &[Pubkey]) -> &'static str {
+    for key in account_keys {
+        if [spl_token::ID, spl_token_2022::ID]
+            .iter()
+            .any(|token_program| {
+                *address == get_associated_token_address_with_program_id(payer, key, token_program)
+            })
+        {
+            return "user token account";
+        }
+        if *address == get_order_address(key, payer) {
+            return "order";
+        }
+        if *address == get_order_wsol_address(key, payer) {
+            return "order WSOL account";
+        }
+        if *address == get_token_mint_lp(key) {
+            return "LP mint";
+        }
+        if *address == get_pool_wsol_reserve(key) {
+            return "pool WSOL reserve";
+        }
+    }
+
+    for key_a in account_keys {
+        for key_b in account_keys {
+            if *address == get_pool_reserve(key_a, key_b) {
+                return "pool reserve";
+            }
+            if key_a < key_b && *address == get_pool_address(key_a, key_b) {
+                return "pool";
+            }
+        }
+    }
+
+    "unknown"
+}
+
+/// Size of a pool account: discriminator, 6 pubkeys, 7 u64 amounts, the bump and 4 u64 of padding
+const POOL_ACCOUNT_LEN: usize = 8 + 6 * 32 + 7 * 8 + 1 + 4 * 8;
+
+/// Accounts initializing the pool of `token_x` / `token_y` creates, with their rent
+///
+/// Computed from the accounts' sizes rather than a simulation, so it also works for mints
+/// that don't exist yet. The LP token's metadata account is not included.
+pub fn get_init_pool_new_accounts(
+    rpc_client: &RpcClient,
+    user: &Pubkey,
+    token_x: &Pubkey,
+    token_y: &Pubkey,
+) -> Result<Vec<NewAccount>> {
+    let pool = get_pool_address(token_x, token_y);
+    let token_mint_lp = get_token_mint_lp(&pool);
+    let accounts = [
+        (pool, "pool", POOL_ACCOUNT_LEN),
+        (
+            get_pool_reserve(&pool, token_x),
+            "pool reserve",
+            TokenAccount::LEN,
+        ),
+        (
+            get_pool_reserve(&pool, token_y),
+            "pool reserve",
+            TokenAccount::LEN,
+        ),
+        (token_mint_lp, "LP mint", Mint::LEN),
+        (
+            get_associated_token_address(user, &token_mint_lp),
+            "user token account",
+            TokenAccount::LEN,
+        ),
+    ];
+
+    accounts
+        .into_iter()
+        .map(|(address, label, len)| {
+            let rent = rpc_client
+                .get_minimum_balance_for_rent_exemption(len)
+                .context("Failed to get rent exemption")?;
+            Ok(NewAccount {
+                address,
+                label,
+                rent,
+            })
+        })
+        .collect()
+}
+
+/// Estimate what sending `message` would cost: network fee, priority fee and rent of new accounts
+///
+/// Rent is taken from a simulation of the message, so accounts that are created and closed
+/// within the same transaction are not counted.
+pub fn estimate_message_cost(
+    rpc_client: &RpcClient,
+    message: &VersionedMessage,
+    compute_unit_limit: u32,
+) -> Result<FeeEstimate> {
+    let network_fee = match message {
+        VersionedMessage::Legacy(message) => rpc_client.get_fee_for_message(message),
+        VersionedMessage::V0(message) => rpc_client.get_fee_for_message(message),
+    }
+    .context("Failed to get fee for message")?;
+
+    let account_keys = message.static_account_keys();
+    let writable_accounts: Vec<Pubkey> = account_keys
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| message.is_maybe_writable(*index, None))
+        .map(|(_, key)| *key)
+        .collect();
+
+    let mut recent_fees: Vec<u64> = rpc_client
+        .get_recent_prioritization_fees(&writable_accounts)
+        .context("Failed to get recent prioritization fees")?
+        .iter()
+        .map(|fee| fee.prioritization_fee)
+        .collect();
+    recent_fees.sort_unstable();
+    let priority_fee_per_cu = recent_fees.get(recent_fees.len() / 2).copied().unwrap_or(0);
+    let priority_fee =
+        (priority_fee_per_cu as u128 * compute_unit_limit as u128).div_ceil(1_000_000) as u64;
+
+    // Writable accounts that don't exist yet will be created by the transaction
+    let signer_count = message.header().num_required_signatures as usize;
+    let candidates: Vec<Pubkey> = writable_accounts
+        .into_iter()
+        .filter(|key| !account_keys[..signer_count].contains(key))
+        .collect();
+    let existing = rpc_client
+        .get_multiple_accounts(&candidates)
+        .context("Failed to get accounts")?;
+    let missing: Vec<Pubkey> = candidates
+        .iter()
+        .zip(existing)
+        .filter(|(_, account)| account.is_none())
+        .map(|(key, _)| *key)
+        .collect();
+
+    let payer = account_keys[0];
+    let mut new_accounts = Vec::new();
+    let mut rent_error = None;
+
+    if !missing.is_empty() {
+        let transaction = VersionedTransaction {
+            signatures: vec![Signature::default(); signer_count],
+            message: message.clone(),
+        };
+
+        let simulation = rpc_client
+            .simulate_transaction_with_config(
+                &transaction,
+                RpcSimulateTransactionConfig {
+                    sig_verify: false,
+                    replace_recent_blockhash: true,
+                    accounts: Some(RpcSimulateTransactionAccountsConfig {
+                        encoding: None,
+                        addresses: missing.iter().map(|key| key.to_string()).collect(),
+                    }),
+                    ..RpcSimulateTransactionConfig::default()
+                },
+            )
+            .context("Failed to simulate transaction")?
+            .value;
+
+        match simulation.err {
+            Some(err) => rent_error = Some(format!("simulation failed: {}", err)),
+            None => {
+                let accounts = simulation.accounts.unwrap_or_default();
+                for (key, account) in missing.iter().zip(accounts) {
+                    if let Some(account) = account {
+                        new_accounts.push(NewAccount {
+                            address: *key,
+                            label: label_account(key, &payer, account_keys),
+                            rent: account.lamports,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(FeeEstimate {
+        network_fee,
+        priority_fee_per_cu,
+        priority_fee,
+        new_accounts,
+        rent_error,
+    })
+}
+
+/// Balance change of the fee payer's wallet in a previewed transaction
+pub struct WalletChange {
+    pub token: TokenInfo,
+    pub before: u64,
+    pub after: u64,
+}
+
+/// What a wallet's approval screen shows for a transaction, see `preview_message`
+pub struct TransactionPreview {
+    pub fee_payer: Pubkey,
+    /// Fee returned by `getFeeForMessage`
+    pub fee: u64,
+    /// Compute unit limit and price set by the message's compute budget instructions
+    pub cu_limit: Option<u32>,
+    pub cu_price: Option<u64>,
+    /// Static writable accounts and their labels
+    pub writable_accounts: Vec<(Pubkey, &'static str)>,
+    /// Lookup tables and how many writable accounts the message loads from each
+    pub lookup_writes: Vec<(Pubkey, usize)>,
+    /// SOL first, then the payer's token accounts the message writes to
+    pub wallet_changes: Vec<WalletChange>,
+    /// Output held by an order the message opens, paid out by its finalize
+    pub order_output: Option<u64>,
+    /// Least the finalize pays out, and in which token
+    pub min_out: Option<(TokenInfo, u64)>,
+}
+
+impl TransactionPreview {
+    pub fn print(&self) {
+        println!("Transaction preview");
+        println!("  Fee payer: {}", self.fee_payer);
+        println!("  Writes to:");
+        for (address, label) in &self.writable_accounts {
+            println!("    {} ({})", address, label);
+        }
+        for (lookup_table, count) in &self.lookup_writes {
+            println!("    {} more from lookup table {}", count, lookup_table);
+        }
+
+        println!("  Wallet balance changes:");
+        for change in &self.wallet_changes {
+            println!(
+                "    {}: {} -> {} ({})",
+                change.token.symbol,
+                change.token.format(change.before),
+                change.token.format(change.after),
+                change.token.format_change(change.before, change.after)
+            );
+        }
+        if let Some((token_out, min_out)) = &self.min_out {
+            if let Some(order_output) = self.order_output {
+                println!(
+                    "  Order output: {}, paid out when the order is finalized",
+                    token_out.format(order_output)
+                );
+            }
+            println!(
+                "  Worst case: {} received, below that the order can only be cancelled and the input is refunded",
+                token_out.format(*min_out)
+            );
+        }
+
+        println!("  Fee: {} SOL", lamports_to_sol(self.fee));
+        println!(
+            "  Compute budget: {} CU limit, {} micro-lamports/CU",
+            self.cu_limit
+                .map_or("default".to_string(), |limit| limit.to_string()),
+            self.cu_price.unwrap_or(0)
+        );
+    }
+}
+
+/// Compute unit limit and price set by a message's compute budget instructions
+fn decode_compute_budget(message: &VersionedMessage) -> (Option<u32>, Option<u64>) {
+    let account_keys = message.static_account_keys();
+    let mut cu_limit = None;
+    let mut cu_price = None;
+    for instruction in message.instructions() {
+        if account_keys.get(instruction.program_id_index as usize)
+            != Some(&solana_sdk::compute_budget::ID)
+        {
+            continue;
+        }
+        // borsh encoded: a variant tag, then the little-endian value
+        match instruction.data.split_first() {
+            Some((2, value)) => {
+                cu_limit = value
+                    .get(..4)
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .map(u32::from_le_bytes);
+            }
+            Some((3, value)) => {
+                cu_price = value
+                    .get(..8)
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .map(u64::from_le_bytes);
+            }
+            _ => {}
+        }
+    }
+    (cu_limit, cu_price)
+}
+
+/// Token account owned by `owner`, read from the start of a Token or Token-2022 account
+fn unpack_owned_token_account(account: &Account, owner: &Pubkey) -> Option<TokenAccount> {
+    if account.owner != spl_token::ID && account.owner != spl_token_2022::ID {
+        return None;
+    }
+    unpack_token_account(&account.data)
+        .ok()
+        .filter(|token_account| token_account.owner == *owner)
+}
+
+/// Decode an unsigned message and simulate it into a wallet-style preview: the accounts it
+/// writes to, how the fee payer's SOL and token balances change and the fee
+///
+/// A swap only opens an order, its output is paid by the finalize. Pass the swap's output
+/// mint and min out to show the order's output and the worst case.
+pub fn preview_message(
+    rpc_client: &RpcClient,
+    message: &VersionedMessage,
+    min_out: Option<(Pubkey, u64)>,
+) -> Result<TransactionPreview> {
+    let account_keys = message.static_account_keys();
+    let fee_payer = account_keys[0];
+
+    let fee = match message {
+        VersionedMessage::Legacy(message) => rpc_client.get_fee_for_message(message),
+        VersionedMessage::V0(message) => rpc_client.get_fee_for_message(message),
+    }
+    .context("Failed to get fee for message")?;
+    let (cu_limit, cu_price) = decode_compute_budget(message);
+
+    let writable: Vec<Pubkey> = account_keys
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| message.is_maybe_writable(*index, None))
+        .map(|(_, key)| *key)
+        .collect();
+    let writable_accounts = writable
+        .iter()
+        .map(|key| {
+            let label = if *key == fee_payer {
+                "fee payer"
+            } else {
+                label_account(key, &fee_payer, account_keys)
+            };
+            (*key, label)
+        })
+        .collect();
+    let lookup_writes = message
+        .address_table_lookups()
+        .unwrap_or_default()
+        .iter()
+        .filter(|lookup| !lookup.writable_indexes.is_empty())
+        .map(|lookup| (lookup.account_key, lookup.writable_indexes.len()))
+        .collect();
+
+    let changes = simulate_account_changes(rpc_client, message, &writable)?;
+
+    let mut balances = vec![(
+        native_mint::ID,
+        changes[0].0.as_ref().map_or(0, |account| account.lamports),
+        changes[0].1.as_ref().map_or(0, |account| account.lamports),
+    )];
+    let mut order_output = None;
+    for (key, (before, after)) in writable.iter().zip(&changes).skip(1) {
+        let before_token = before
+            .as_ref()
+            .and_then(|account| unpack_owned_token_account(account, &fee_payer));
+        let after_token = after
+            .as_ref()
+            .and_then(|account| unpack_owned_token_account(account, &fee_payer));
+        if let Some(mint) = before_token.or(after_token).map(|token| token.mint) {
+            balances.push((
+                mint,
+                before_token.map_or(0, |token| token.amount),
+                after_token.map_or(0, |token| token.amount),
+            ));
+        } else if label_account(key, &fee_payer, account_keys) == "order"
+            && before.is_none()
+            && let Some(order) = after
+        {
+            order_output = Some(parse_order_summary(&order.data)?.actual_out);
+        }
+    }
+
+    let mut mints: Vec<Pubkey> = balances.iter().map(|(mint, _, _)| *mint).collect();
+    mints.extend(min_out.map(|(mint, _)| mint));
+    let mut token_infos = get_token_infos(rpc_client, &mints)?;
+    let min_out = match min_out {
+        Some((_, min_out)) => Some((token_infos.pop().context("Missing token info")?, min_out)),
+        None => None,
+    };
+    let wallet_changes = balances
+        .into_iter()
+        .zip(token_infos)
+        .enumerate()
+        .map(|(index, ((_, before, after), mut token))| {
+            // the first entry is the SOL balance, a WSOL token account is told apart from it
+            if index > 0 && token.symbol == "SOL" {
+                token.symbol = "WSOL".to_string();
+            }
+            WalletChange {
+                token,
+                before,
+                after,
+            }
+        })
+        .collect();
+
+    Ok(TransactionPreview {
+        fee_payer,
+        fee,
+        cu_limit,
+        cu_price,
+        writable_accounts,
+        lookup_writes,
+        wallet_changes,
+        order_output,
+        min_out,
+    })
+}
+
+/// Records how long each stage of a flow takes (quote, build, sign, send, ...)
+pub struct StageTimer {
+    started_at: Instant,
+    last_mark: Instant,
+    stages: Vec<(&'static str, Duration)>,
+}
+
+impl Default for StageTimer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StageTimer {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            started_at: now,
+            last_mark: now,
+            stages: Vec::new(),
+        }
+    }
+
+    /// Record the time elapsed since the previous stage (or since the timer was created)
+    pub fn stage(&mut self, name: &'static str) {
+        let now = Instant::now();
+        self.stages.push((name, now - self.last_mark));
+        self.last_mark = now;
+    }
+
+    pub fn print_report(&self) {
+        let total = self.last_mark - self.started_at;
+
+        println!("\nLatency report");
+        println!("==============");
+        for (name, duration) in &self.stages {
+            let share = if total.is_zero() {
+                0.0
+            } else {
+                duration.as_secs_f64() / total.as_secs_f64() * 100.0
+            };
+            println!(
+                "{:<20} {:>10.3}s {:>6.1}%",
+                name,
+                duration.as_secs_f64(),
+                share
+            );
+        }
+        println!("{:<20} {:>10.3}s", "total", total.as_secs_f64());
     }
-    Err(anyhow::anyhow!("Failed to get order"))
 }